@@ -0,0 +1,247 @@
+//! The content-addressed artifact store.
+//!
+//! Paths may be marked as artifacts, in which case their content (whether a file or directory) is
+//! stored by digest when they are stored (e.g. in a cache), and re-materialized when they are
+//! restored. This makes it safe to cache paths of build outputs which may later be removed or
+//! overwritten.
+//!
+//! Artifacts are marked by path (for the lifetime of the runtime) rather than by value, so any
+//! Path value with a marked path is stored as an artifact.
+
+use crate as ergo_runtime;
+use crate::abi_stable::{bst::BstSet, external_types::RMutex, path::PathBuf, StableAbi};
+use crate::type_system::ErgoType;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+#[derive(StableAbi, ErgoType)]
+#[repr(C)]
+pub struct Artifacts {
+    directory: PathBuf,
+    paths: RMutex<BstSet<PathBuf>>,
+}
+
+/// The stored content of an artifact path.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Artifact {
+    /// A file, with the digest of its content.
+    File { digest: String, executable: bool },
+    /// A directory, with its entries.
+    Directory(BTreeMap<String, Artifact>),
+    /// A symbolic link, with its target.
+    Symlink(std::path::PathBuf),
+}
+
+impl Artifacts {
+    /// Create an artifact store in the given directory.
+    pub fn new(directory: std::path::PathBuf) -> Self {
+        Artifacts {
+            directory: directory.into(),
+            paths: RMutex::new(Default::default()),
+        }
+    }
+
+    /// Mark a path as an artifact.
+    pub fn add(&self, path: &PathBuf) {
+        self.paths.lock().insert(path.clone());
+    }
+
+    /// Return whether a path is marked as an artifact.
+    pub fn contains(&self, path: &PathBuf) -> bool {
+        self.paths.lock().contains(path)
+    }
+
+    /// Store the content of the given path, returning the stored artifact.
+    pub fn store(&self, path: &Path) -> io::Result<Artifact> {
+        let meta = std::fs::symlink_metadata(path)?;
+        if meta.file_type().is_symlink() {
+            Ok(Artifact::Symlink(std::fs::read_link(path)?))
+        } else if meta.is_dir() {
+            let mut entries = BTreeMap::new();
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?;
+                let name = entry.file_name().into_string().map_err(|name| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("non-unicode file name: {}", name.to_string_lossy()),
+                    )
+                })?;
+                entries.insert(name, self.store(&entry.path())?);
+            }
+            Ok(Artifact::Directory(entries))
+        } else {
+            let digest = digest(path)?;
+            let stored = self.content_path(&digest);
+            if !stored.exists() {
+                std::fs::create_dir_all(self.directory.as_ref())?;
+                // Copy to a temporary file and rename, so that the store never has partial content.
+                let tmp = stored.with_extension(format!("tmp{}", std::process::id()));
+                std::fs::copy(path, &tmp)?;
+                std::fs::rename(&tmp, &stored)?;
+            }
+            Ok(Artifact::File {
+                digest,
+                executable: is_executable(&meta),
+            })
+        }
+    }
+
+    /// Restore the content of an artifact to the given path.
+    ///
+    /// Existing files which already have the stored content are left untouched, and directory
+    /// entries which are not part of the artifact are removed.
+    pub fn restore(&self, path: &Path, artifact: &Artifact) -> io::Result<()> {
+        let existing = std::fs::symlink_metadata(path).ok();
+        match artifact {
+            Artifact::File { digest, executable } => {
+                let stored = self.content_path(digest);
+                if !stored.exists() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("artifact {} is missing from the store", digest),
+                    ));
+                }
+                if let Some(meta) = existing {
+                    if meta.is_file()
+                        && is_executable(&meta) == *executable
+                        && self::digest(path)? == *digest
+                    {
+                        return Ok(());
+                    }
+                    remove(path, &meta)?;
+                }
+                std::fs::copy(&stored, path)?;
+                set_executable(path, *executable)
+            }
+            Artifact::Directory(entries) => {
+                match existing {
+                    Some(meta) if meta.is_dir() => {
+                        for entry in std::fs::read_dir(path)? {
+                            let entry = entry?;
+                            let keep = entry
+                                .file_name()
+                                .to_str()
+                                .map(|name| entries.contains_key(name))
+                                .unwrap_or(false);
+                            if !keep {
+                                remove(&entry.path(), &entry.metadata()?)?;
+                            }
+                        }
+                    }
+                    Some(meta) => {
+                        remove(path, &meta)?;
+                        std::fs::create_dir(path)?;
+                    }
+                    None => std::fs::create_dir_all(path)?,
+                }
+                for (name, artifact) in entries {
+                    self.restore(&path.join(name), artifact)?;
+                }
+                Ok(())
+            }
+            Artifact::Symlink(target) => {
+                if let Some(meta) = existing {
+                    if meta.file_type().is_symlink() && std::fs::read_link(path)? == *target {
+                        return Ok(());
+                    }
+                    remove(path, &meta)?;
+                }
+                symlink(target, path)
+            }
+        }
+    }
+
+    fn content_path(&self, digest: &str) -> std::path::PathBuf {
+        self.directory.as_ref().join(digest)
+    }
+}
+
+fn digest(path: &Path) -> io::Result<String> {
+    Ok(format!(
+        "{:032x}",
+        crate::hash::hash_read(std::fs::File::open(path)?)?
+    ))
+}
+
+fn remove(path: &Path, meta: &std::fs::Metadata) -> io::Result<()> {
+    if meta.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_meta: &std::fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path, executable: bool) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    // Restored files are always writable by the owner.
+    let mode = permissions.mode() | 0o200;
+    permissions.set_mode(if executable {
+        mode | 0o111
+    } else {
+        mode & !0o111
+    });
+    std::fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+fn set_executable(path: &Path, _executable: bool) -> io::Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_readonly(false);
+    std::fs::set_permissions(path, permissions)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn store_and_restore() -> io::Result<()> {
+        let root = std::env::temp_dir().join(format!("ergo-artifacts-test-{}", std::process::id()));
+        drop(std::fs::remove_dir_all(&root));
+
+        let artifacts = Artifacts::new(root.join("store"));
+        let out = root.join("out");
+        std::fs::create_dir_all(out.join("sub"))?;
+        std::fs::write(out.join("a"), "hello")?;
+        std::fs::write(out.join("sub").join("b"), "world")?;
+
+        let artifact = artifacts.store(&out)?;
+        std::fs::remove_dir_all(&out)?;
+        artifacts.restore(&out, &artifact)?;
+        assert_eq!(std::fs::read_to_string(out.join("a"))?, "hello");
+        assert_eq!(std::fs::read_to_string(out.join("sub").join("b"))?, "world");
+
+        std::fs::write(out.join("a"), "changed")?;
+        std::fs::write(out.join("extra"), "extra")?;
+        artifacts.restore(&out, &artifact)?;
+        assert_eq!(std::fs::read_to_string(out.join("a"))?, "hello");
+        assert!(!out.join("extra").exists());
+
+        std::fs::remove_dir_all(&root)
+    }
+}
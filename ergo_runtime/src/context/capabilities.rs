@@ -0,0 +1,359 @@
+//! Capabilities granted to evaluation.
+//!
+//! Capabilities restrict the side effects that standard library functions may perform: which
+//! programs may be executed, which paths may be read or written, and which hosts may be accessed
+//! over the network. They also restrict which plugins may be loaded, since plugins are native code
+//! which is not otherwise subject to capabilities. Capabilities may only be restricted further
+//! (never expanded) when evaluating nested values.
+
+use crate::abi_stable::{
+    path::PathBuf,
+    std_types::{RString, RVec},
+    StableAbi,
+};
+use std::path::{Component, Path};
+
+/// A set of allowed items.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, StableAbi)]
+#[repr(C)]
+pub enum Allowed<T> {
+    /// All items are allowed.
+    All,
+    /// Only the given items are allowed.
+    Only(RVec<T>),
+}
+
+impl<T> Default for Allowed<T> {
+    fn default() -> Self {
+        Allowed::All
+    }
+}
+
+impl<T: Clone> Allowed<T> {
+    /// Intersect two sets of allowed items, where `contains(a, b)` returns whether `a` includes
+    /// `b`.
+    fn intersect<F: Fn(&T, &T) -> bool>(&self, other: &Self, contains: F) -> Self {
+        match (self, other) {
+            (Allowed::All, o) | (o, Allowed::All) => o.clone(),
+            (Allowed::Only(a), Allowed::Only(b)) => {
+                // Keep the items of each set which are included in the other set.
+                let mut ret: Vec<T> = a
+                    .iter()
+                    .filter(|x| b.iter().any(|y| contains(y, x)))
+                    .cloned()
+                    .collect();
+                for y in b.iter() {
+                    if a.iter().any(|x| contains(x, y)) && !ret.iter().any(|r| contains(r, y)) {
+                        ret.push(y.clone());
+                    }
+                }
+                Allowed::Only(ret.into())
+            }
+        }
+    }
+
+    fn allows<F: Fn(&T) -> bool>(&self, f: F) -> bool {
+        match self {
+            Allowed::All => true,
+            Allowed::Only(items) => items.iter().any(f),
+        }
+    }
+}
+
+/// The capabilities of evaluation.
+///
+/// By default, all capabilities are granted.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, StableAbi)]
+#[repr(C)]
+pub struct Capabilities {
+    exec: Allowed<RString>,
+    read: Allowed<PathBuf>,
+    write: Allowed<PathBuf>,
+    net: Allowed<RString>,
+    plugin: Allowed<PathBuf>,
+}
+
+crate::ConstantDependency!(Capabilities);
+
+impl Capabilities {
+    /// Capabilities which allow nothing.
+    pub fn none() -> Self {
+        Capabilities {
+            exec: Allowed::Only(Default::default()),
+            read: Allowed::Only(Default::default()),
+            write: Allowed::Only(Default::default()),
+            net: Allowed::Only(Default::default()),
+            plugin: Allowed::Only(Default::default()),
+        }
+    }
+
+    /// Only allow executing the given programs.
+    ///
+    /// Programs without path separators must be executed by name (with path lookup); programs
+    /// with path separators must be executed by path.
+    pub fn with_exec<I, S>(mut self, programs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.exec = Allowed::Only(
+            programs
+                .into_iter()
+                .map(|p| {
+                    let p: String = p.into();
+                    if is_path(p.as_str()) {
+                        resolve(p.as_str()).to_string_lossy().into_owned().into()
+                    } else {
+                        p.into()
+                    }
+                })
+                .collect(),
+        );
+        self
+    }
+
+    /// Only allow reading within the given paths.
+    pub fn with_read<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.read = Allowed::Only(paths.into_iter().map(|p| resolve(p).into()).collect());
+        self
+    }
+
+    /// Only allow writing within the given paths.
+    pub fn with_write<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.write = Allowed::Only(paths.into_iter().map(|p| resolve(p).into()).collect());
+        self
+    }
+
+    /// Only allow network access to the given hosts.
+    ///
+    /// Hosts may optionally include a port (`host:port`), otherwise any port is allowed.
+    pub fn with_net<I, S>(mut self, hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.net = Allowed::Only(hosts.into_iter().map(|h| h.into().into()).collect());
+        self
+    }
+
+    /// Only allow loading plugins within the given paths.
+    pub fn with_plugin<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.plugin = Allowed::Only(paths.into_iter().map(|p| resolve(p).into()).collect());
+        self
+    }
+
+    /// Return whether no capabilities are restricted.
+    pub fn is_unrestricted(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Restrict these capabilities to those also allowed by `other`.
+    pub fn restrict(&self, other: &Self) -> Self {
+        Capabilities {
+            exec: self.exec.intersect(&other.exec, |a, b| a == b),
+            read: self
+                .read
+                .intersect(&other.read, |a, b| b.as_ref().starts_with(a.as_ref())),
+            write: self
+                .write
+                .intersect(&other.write, |a, b| b.as_ref().starts_with(a.as_ref())),
+            net: self.net.intersect(&other.net, |a, b| {
+                a == b || b.rsplit_once(':').map(|(h, _)| h == a.as_str()) == Some(true)
+            }),
+            plugin: self
+                .plugin
+                .intersect(&other.plugin, |a, b| b.as_ref().starts_with(a.as_ref())),
+        }
+    }
+
+    /// Return whether the given program may be executed.
+    pub fn allows_exec<P: AsRef<std::ffi::OsStr>>(&self, program: P) -> bool {
+        let program = program.as_ref();
+        if is_path(program) {
+            let program = resolve(program);
+            self.exec
+                .allows(|p| is_path(p.as_str()) && Path::new(p.as_str()) == program)
+        } else {
+            self.exec
+                .allows(|p| !is_path(p.as_str()) && std::ffi::OsStr::new(p.as_str()) == program)
+        }
+    }
+
+    /// Return whether the given path may be read.
+    pub fn allows_read<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = resolve(path);
+        self.read.allows(|p| path.starts_with(p.as_ref()))
+    }
+
+    /// Return whether the given path may be written.
+    pub fn allows_write<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = resolve(path);
+        self.write.allows(|p| path.starts_with(p.as_ref()))
+    }
+
+    /// Return whether the given host (and port) may be accessed.
+    pub fn allows_net(&self, host: &str, port: Option<u16>) -> bool {
+        self.net.allows(|h| {
+            h.as_str() == host
+                || port.map(|port| h.as_str() == format!("{}:{}", host, port)) == Some(true)
+        })
+    }
+
+    /// Return whether the plugin at the given path may be loaded.
+    pub fn allows_plugin<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = resolve(path);
+        self.plugin.allows(|p| path.starts_with(p.as_ref()))
+    }
+
+    /// Check that the given program may be executed.
+    pub fn check_exec<P: AsRef<std::ffi::OsStr>>(&self, program: P) -> Result<(), String> {
+        if self.allows_exec(program.as_ref()) {
+            Ok(())
+        } else {
+            Err(format!(
+                "executing {} is not allowed",
+                program.as_ref().to_string_lossy()
+            ))
+        }
+    }
+
+    /// Check that the given path may be read.
+    pub fn check_read<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        if self.allows_read(path.as_ref()) {
+            Ok(())
+        } else {
+            Err(format!(
+                "reading {} is not allowed",
+                path.as_ref().display()
+            ))
+        }
+    }
+
+    /// Check that the given path may be written.
+    pub fn check_write<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        if self.allows_write(path.as_ref()) {
+            Ok(())
+        } else {
+            Err(format!(
+                "writing {} is not allowed",
+                path.as_ref().display()
+            ))
+        }
+    }
+
+    /// Check that the given host (and port) may be accessed.
+    pub fn check_net(&self, host: &str, port: Option<u16>) -> Result<(), String> {
+        if self.allows_net(host, port) {
+            Ok(())
+        } else {
+            Err(format!("network access to {} is not allowed", host))
+        }
+    }
+
+    /// Check that the plugin at the given path may be loaded.
+    pub fn check_plugin<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        if self.allows_plugin(path.as_ref()) {
+            Ok(())
+        } else {
+            Err(format!(
+                "loading plugin {} is not allowed",
+                path.as_ref().display()
+            ))
+        }
+    }
+}
+
+fn is_path<S: AsRef<std::ffi::OsStr>>(s: S) -> bool {
+    Path::new(s.as_ref()).components().count() > 1
+}
+
+/// Resolve a path to an absolute path without symbolic links or `..` components.
+///
+/// The path need not exist: the longest existing ancestor is canonicalized, and the remaining
+/// components are appended.
+fn resolve<P: AsRef<Path>>(path: P) -> std::path::PathBuf {
+    let path = path.as_ref();
+    let path = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    let mut ret = loop {
+        if let Ok(p) = existing.canonicalize() {
+            break p;
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => break existing.to_owned(),
+        }
+    };
+    for name in rest.into_iter().rev() {
+        ret.push(name);
+    }
+    let mut normalized = std::path::PathBuf::new();
+    for c in ret.components() {
+        match c {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => (),
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let dir = std::env::temp_dir().join("ergo-capabilities-test");
+        let caps = Capabilities::default().with_read([&dir]);
+        assert!(caps.allows_read(dir.join("a/b")));
+        assert!(!caps.allows_read(dir.join("../a")));
+        assert!(!caps.allows_read(dir.with_file_name("ergo-capabilities-test2")));
+        assert!(caps.allows_write(std::env::temp_dir()));
+    }
+
+    #[test]
+    fn restrict() {
+        let dir = std::env::temp_dir();
+        let outer = Capabilities::default()
+            .with_read([&dir])
+            .with_exec(["git"])
+            .with_net(["example.com"]);
+        let inner = Capabilities::none()
+            .with_read([dir.join("sub")])
+            .with_exec(["git", "rm"])
+            .with_net(["example.com:443"]);
+        let caps = outer.restrict(&inner);
+        assert!(caps.allows_read(dir.join("sub/file")));
+        assert!(!caps.allows_read(dir.join("other")));
+        assert!(caps.allows_exec("git"));
+        assert!(!caps.allows_exec("rm"));
+        assert!(caps.allows_net("example.com", Some(443)));
+        assert!(!caps.allows_net("example.com", Some(80)));
+        assert!(!caps.allows_write(&dir));
+        assert!(!caps.allows_plugin(dir.join("plugin.so")));
+    }
+}
@@ -144,6 +144,12 @@ impl Sources {
             .expect("file id must have been set")
     }
 
+    /// Forget the source of a file, so that it is read again when next added (e.g. because the
+    /// file changed).
+    pub fn invalidate_file(&self, path: &std::path::Path) {
+        self.file_ids.lock().remove(&PathBuf::from(path.to_owned()));
+    }
+
     /// Add a string to the set of sources.
     pub fn add_string(&self, name: String, content: String) -> SourceId {
         let mut hfn = crate::hash::HashFn::default();
@@ -189,6 +195,26 @@ impl Sources {
         })
     }
 
+    /// Get the (1-based) line and column of the start of a source location.
+    ///
+    /// Returns None if the source is missing.
+    pub fn line_column<T>(&self, src: &crate::Source<T>) -> Option<(usize, usize)> {
+        if src.source_id == 0 {
+            return None;
+        }
+        let location = csr_files::Files::location(self, src.source_id, src.location.start).ok()?;
+        Some((location.line_number, location.column_number))
+    }
+
+    /// Get a `name:line:column` description of the start of a source location.
+    ///
+    /// Returns None if the source is missing.
+    pub fn location_string<T>(&self, src: &crate::Source<T>) -> Option<String> {
+        let (line, column) = self.line_column(src)?;
+        let name = self.name(src.source_id)?;
+        Some(format!("{}:{}:{}", name, line, column))
+    }
+
     fn get(&self, id: SourceId) -> Option<&Source> {
         self.source_ids.cache_default(id).as_ref().into()
     }
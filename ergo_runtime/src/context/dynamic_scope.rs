@@ -2,7 +2,7 @@
 
 use crate::abi_stable::{
     bst::BstMap,
-    std_types::RArc,
+    std_types::{RArc, ROption},
     type_erase::{Eraseable, Erased, Ref},
     u128::U128,
     StableAbi,
@@ -16,6 +16,13 @@ pub trait DynamicScopeKey {
 
     /// Get the scope entry identifier for this key.
     fn id(&self) -> u128;
+
+    /// Get the script value of a stored value, if any.
+    ///
+    /// This is used to inspect the dynamic scope (for instance, when debugging).
+    fn script_value(_value: &Self::Value) -> Option<Value> {
+        None
+    }
 }
 
 impl DynamicScopeKey for IdentifiedValue {
@@ -24,6 +31,10 @@ impl DynamicScopeKey for IdentifiedValue {
     fn id(&self) -> u128 {
         *self.id()
     }
+
+    fn script_value(value: &Self::Value) -> Option<Value> {
+        Some(value.clone())
+    }
 }
 
 /// Runtime dynamic-scoped bindings.
@@ -38,6 +49,7 @@ pub struct DynamicScope {
 struct DynamicValue {
     key_source: Source<()>,
     value: Erased,
+    script_value: ROption<Value>,
 }
 
 /// A pointer to a dynamic entry value.
@@ -68,6 +80,15 @@ impl DynamicScope {
         self.scope.get(&key.id()).map(|v| v.key_source.clone())
     }
 
+    /// Get the source locations of the keys of all values in the dynamic scope, along with the
+    /// values which were set from scripts.
+    pub fn entries(&self) -> Vec<(Source<()>, Option<Value>)> {
+        self.scope
+            .iter()
+            .map(|(_, v)| (v.key_source.clone(), v.script_value.clone().into_option()))
+            .collect()
+    }
+
     /// Set a value in the dynamic scope.
     pub fn set<T: DynamicScopeKey>(&mut self, key: &Source<T>, value: T::Value) {
         let (key_source, k) = key.as_ref().map(|k| k.id()).take();
@@ -75,6 +96,7 @@ impl DynamicScope {
             k.into(),
             RArc::new(DynamicValue {
                 key_source,
+                script_value: T::script_value(&value).into(),
                 value: Erased::new(value),
             }),
         );
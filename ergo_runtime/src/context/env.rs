@@ -1,17 +1,39 @@
 //! Environmental values.
 
-use crate::abi_stable::{path::PathBuf, StableAbi};
+use crate::abi_stable::{external_types::RMutex, path::PathBuf, std_types::ROption, StableAbi};
 
-#[derive(Debug, StableAbi)]
+#[derive(StableAbi)]
 #[repr(C)]
 pub struct Environment {
     project_directory: PathBuf,
+    dry_run: bool,
+    lock_file: ROption<PathBuf>,
+    task_log_directory: RMutex<ROption<PathBuf>>,
+}
+
+impl std::fmt::Debug for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Environment")
+            .field("project_directory", &self.project_directory)
+            .field("dry_run", &self.dry_run)
+            .field("lock_file", &self.lock_file)
+            .field("task_log_directory", &*self.task_log_directory.lock())
+            .finish()
+    }
 }
 
 impl Environment {
-    pub fn new(project_dir: std::path::PathBuf) -> Self {
+    pub fn new(
+        project_dir: std::path::PathBuf,
+        dry_run: bool,
+        lock_file: Option<std::path::PathBuf>,
+        task_log_directory: Option<std::path::PathBuf>,
+    ) -> Self {
         Environment {
             project_directory: project_dir.into(),
+            dry_run,
+            lock_file: lock_file.map(|p| p.into()).into(),
+            task_log_directory: RMutex::new(task_log_directory.map(|p| p.into()).into()),
         }
     }
 
@@ -19,4 +41,30 @@ impl Environment {
     pub fn project_directory(&self) -> std::borrow::Cow<std::path::Path> {
         self.project_directory.as_ref()
     }
+
+    /// Get whether side effects should be skipped (and only logged).
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Get the configured lock file path, if any.
+    pub fn lock_file(&self) -> Option<std::borrow::Cow<std::path::Path>> {
+        self.lock_file.as_ref().map(|p| p.as_ref()).into()
+    }
+
+    /// Get the configured directory in which task logs are written, if any.
+    pub fn task_log_directory(&self) -> Option<std::path::PathBuf> {
+        self.task_log_directory
+            .lock()
+            .as_ref()
+            .map(|p| p.clone().into_pathbuf())
+            .into()
+    }
+
+    /// Set the directory in which task logs are written.
+    ///
+    /// This allows a context to be reused for separate runs, each with their own task logs.
+    pub fn set_task_log_directory(&self, dir: Option<std::path::PathBuf>) {
+        *self.task_log_directory.lock() = dir.map(|p| p.into()).into();
+    }
 }
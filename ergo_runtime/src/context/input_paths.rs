@@ -0,0 +1,70 @@
+//! The set of filesystem paths used as inputs to evaluation.
+//!
+//! Paths are recorded when their contents are read in a way that affects evaluation (such as
+//! loading scripts or tracking files), so that tools may react to changes of these paths.
+
+use crate as ergo_runtime;
+use crate::abi_stable::{bst::BstSet, external_types::RMutex, path::PathBuf, StableAbi};
+use crate::type_system::ErgoType;
+
+#[derive(StableAbi, ErgoType)]
+#[repr(C)]
+pub struct InputPaths {
+    paths: RMutex<BstSet<PathBuf>>,
+}
+
+impl Default for InputPaths {
+    fn default() -> Self {
+        InputPaths {
+            paths: RMutex::new(Default::default()),
+        }
+    }
+}
+
+impl InputPaths {
+    /// Add a path to the set of input paths.
+    pub fn add<P: AsRef<std::path::Path>>(&self, path: P) {
+        self.paths.lock().insert(path.as_ref().to_owned().into());
+    }
+
+    /// Remove a path from the set of input paths.
+    pub fn remove<P: AsRef<std::path::Path>>(&self, path: P) {
+        self.paths.lock().remove(&path.as_ref().to_owned().into());
+    }
+
+    /// Remove all input paths.
+    pub fn clear(&self) {
+        self.paths.lock().clear();
+    }
+
+    /// Get all input paths that have been recorded.
+    pub fn paths(&self) -> Vec<std::path::PathBuf> {
+        self.paths
+            .lock()
+            .iter()
+            .map(|p| p.clone().into_pathbuf())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InputPaths;
+    use std::path::PathBuf;
+
+    #[test]
+    fn add_remove_clear() {
+        let paths = InputPaths::default();
+        paths.add("/b");
+        paths.add("/a");
+        paths.add("/b");
+        assert_eq!(
+            paths.paths(),
+            vec![PathBuf::from("/a"), PathBuf::from("/b")]
+        );
+        paths.remove("/a");
+        assert_eq!(paths.paths(), vec![PathBuf::from("/b")]);
+        paths.clear();
+        assert!(paths.paths().is_empty());
+    }
+}
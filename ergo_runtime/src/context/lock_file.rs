@@ -0,0 +1,186 @@
+//! The lock file, which pins the content of retrieved resources.
+//!
+//! Entries map keys (such as urls) to content hashes. When a resource is first retrieved its hash
+//! is recorded, and subsequent retrievals are verified against the recorded hash. Entries may be
+//! selected for update, in which case new hashes are recorded rather than verified.
+
+use crate as ergo_runtime;
+use crate::abi_stable::{
+    bst::{BstMap, BstSet},
+    external_types::RMutex,
+    path::PathBuf,
+    std_types::{ROption, RString},
+    StableAbi,
+};
+use crate::type_system::ErgoType;
+
+const HEADER: &str = "# Content hashes of retrieved resources. Entries are `<hash> <key>`.\n";
+
+#[derive(StableAbi, ErgoType)]
+#[repr(C)]
+pub struct LockFile {
+    path: ROption<PathBuf>,
+    inner: RMutex<Inner>,
+}
+
+#[derive(Default, StableAbi)]
+#[repr(C)]
+struct Inner {
+    entries: BstMap<RString, RString>,
+    update: Update,
+    changed: bool,
+}
+
+#[derive(StableAbi)]
+#[repr(C)]
+enum Update {
+    Nothing,
+    All,
+    Keys(BstSet<RString>),
+}
+
+impl Default for Update {
+    fn default() -> Self {
+        Update::Nothing
+    }
+}
+
+impl LockFile {
+    /// Load the lock file at the given path.
+    ///
+    /// If the path is None, no lock file is used and all checks succeed. If the file does not
+    /// exist, it is created when entries are recorded.
+    pub fn load(path: Option<std::path::PathBuf>) -> std::io::Result<Self> {
+        let mut inner = Inner::default();
+        if let Some(path) = &path {
+            match std::fs::read_to_string(path) {
+                Ok(content) => {
+                    for (n, line) in content.lines().enumerate() {
+                        let line = line.trim();
+                        if line.is_empty() || line.starts_with('#') {
+                            continue;
+                        }
+                        match line.split_once(' ') {
+                            Some((hash, key)) => {
+                                inner.entries.insert(key.trim().into(), hash.into());
+                            }
+                            None => {
+                                return Err(std::io::Error::new(
+                                    std::io::ErrorKind::InvalidData,
+                                    format!("{}:{}: invalid lock entry", path.display(), n + 1),
+                                ))
+                            }
+                        }
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(LockFile {
+            path: path.map(|p| p.into()).into(),
+            inner: RMutex::new(inner),
+        })
+    }
+
+    /// Get the path of the lock file, if any.
+    pub fn path(&self) -> Option<std::path::PathBuf> {
+        self.path.as_ref().map(|p| p.clone().into_pathbuf()).into()
+    }
+
+    /// Select entries to update rather than verify.
+    ///
+    /// If `keys` is None, all entries are updated.
+    pub fn set_update(&self, keys: Option<Vec<String>>) {
+        self.inner.lock().update = match keys {
+            None => Update::All,
+            Some(keys) => Update::Keys(keys.into_iter().map(|k| k.into()).collect()),
+        };
+    }
+
+    /// Get all entries, as `(key, hash)` pairs.
+    pub fn entries(&self) -> Vec<(String, String)> {
+        self.inner
+            .lock()
+            .entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// Check the content hash of the resource with the given key.
+    ///
+    /// If there is no entry for the key (or the entry is selected for update), the hash is
+    /// recorded. Otherwise, an error is returned if the hash differs from the recorded hash.
+    pub fn check(&self, key: &str, hash: &str) -> Result<(), String> {
+        if self.path.is_none() {
+            return Ok(());
+        }
+        let mut inner = self.inner.lock();
+        let update = match &inner.update {
+            Update::Nothing => false,
+            Update::All => true,
+            Update::Keys(keys) => keys.contains(key),
+        };
+        match inner.entries.get(key) {
+            Some(h) if h.as_str() == hash => return Ok(()),
+            Some(h) if !update => {
+                return Err(format!(
+                    "the content of {} does not match the lock file (expected {}, found {})",
+                    key, h, hash
+                ))
+            }
+            _ => (),
+        }
+        inner.entries.insert(key.into(), hash.into());
+        inner.changed = true;
+        Ok(())
+    }
+
+    /// Write the lock file if entries have changed.
+    pub fn write(&self) -> std::io::Result<()> {
+        let path = match self.path() {
+            None => return Ok(()),
+            Some(p) => p,
+        };
+        let mut inner = self.inner.lock();
+        if !inner.changed {
+            return Ok(());
+        }
+        let mut content = String::from(HEADER);
+        for (key, hash) in inner.entries.iter() {
+            content += &format!("{} {}\n", hash, key);
+        }
+        std::fs::write(path, content)?;
+        inner.changed = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_and_verify() -> std::io::Result<()> {
+        let path = std::env::temp_dir().join(format!("ergo-lock-test-{}", std::process::id()));
+        drop(std::fs::remove_file(&path));
+
+        let lock = LockFile::load(Some(path.clone()))?;
+        assert!(lock.check("https://example.com/a", "sha256:aa").is_ok());
+        lock.write()?;
+
+        let lock = LockFile::load(Some(path.clone()))?;
+        assert!(lock.check("https://example.com/a", "sha256:aa").is_ok());
+        assert!(lock.check("https://example.com/a", "sha256:bb").is_err());
+        lock.set_update(Some(vec!["https://example.com/a".into()]));
+        assert!(lock.check("https://example.com/a", "sha256:bb").is_ok());
+        lock.write()?;
+
+        assert_eq!(
+            LockFile::load(Some(path.clone()))?.entries(),
+            vec![("https://example.com/a".into(), "sha256:bb".into())]
+        );
+        std::fs::remove_file(&path)
+    }
+}
@@ -16,7 +16,7 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 /// Log output levels.
-#[derive(Debug, PartialEq, PartialOrd, StableAbi)]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, StableAbi)]
 #[repr(u8)]
 pub enum LogLevel {
     Debug,
@@ -132,6 +132,10 @@ pub trait LogTarget: Send + Sync {
     /// Resume log output.
     #[sabi(last_prefix_field)]
     fn resume_logging(&self) {}
+
+    /// Indicate that a cache with the given id was accessed, and whether the cached value was
+    /// present.
+    fn cache_access(&self, _id: RSlice<RString>, _hit: bool) {}
 }
 
 pub type Logger = LogTarget_TO<'static, RBox<()>>;
@@ -192,7 +196,7 @@ macro_rules! log_level {
     ( $name:ident, $level:ident ) => {
         /// Write a log message.
         pub fn $name<T: std::string::ToString>(&self, message: T) {
-            self.logger.log(LogEntry {
+            self.log(LogEntry {
                 level: LogLevel::$level,
                 context: self.context.clone(),
                 args: message.to_string().into(),
@@ -255,6 +259,15 @@ impl Log {
         }
     }
 
+    /// Record an access of the cache with the given name.
+    ///
+    /// `hit` indicates whether the cached value was present.
+    pub fn cache_access<T: Into<String>>(&self, name: T, hit: bool) {
+        let mut v: Vec<RString> = self.context.iter().map(|v| v.clone()).collect();
+        v.push(name.into().into());
+        self.logger.cache_access(RSlice::from_slice(&v), hit);
+    }
+
     /// Add a running task to the log.
     ///
     /// When the returned LogTask is dropped, the task is suspended from the log.
@@ -266,6 +279,14 @@ impl Log {
         }
     }
 
+    /// Write a log entry, also writing it to the current task log (if any).
+    fn log(&self, entry: LogEntry) {
+        if let Some(task_log) = super::TaskLog::current() {
+            task_log.write_entry(&entry);
+        }
+        self.logger.log(entry);
+    }
+
     log_level!(debug, Debug);
     log_level!(info, Info);
     log_level!(warn, Warn);
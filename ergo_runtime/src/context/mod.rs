@@ -7,17 +7,22 @@ use crate::{
 };
 use std::fmt;
 
+mod artifacts;
 mod backtrace;
+mod capabilities;
 mod diagnostic_sources;
 mod dynamic_scope;
 mod env;
 mod error_scope;
 mod hooks;
+mod input_paths;
+mod lock_file;
 mod log;
 mod owned_paths;
 mod progress;
 mod shared_state;
 pub(crate) mod task;
+mod task_log;
 mod traits;
 
 use self::log::EmptyLogTarget;
@@ -26,16 +31,23 @@ pub use self::log::{
     logger_ref, Log, LogEntry, LogLevel, LogTarget, LogTask, LogTaskKey, Logger, LoggerRef,
     RecordingWork, Work,
 };
+pub use artifacts::{Artifact, Artifacts};
 pub use backtrace::Backtrace;
+pub use capabilities::{Allowed, Capabilities};
 pub use diagnostic_sources::{SourceId, Sources};
 pub use dynamic_scope::{DynamicScope, DynamicScopeKey, DynamicScopeRef};
 pub use env::Environment;
 pub use error_scope::ErrorScope;
 pub use hooks::Hooks;
+pub use input_paths::InputPaths;
+pub use lock_file::LockFile;
 pub use owned_paths::OwnedPaths;
 pub use progress::Progress;
 pub use shared_state::SharedState;
-pub use task::{LocalKey, TaskManager, TaskPermit};
+pub use task::{JobServer, JobServerMode, LocalKey, TaskManager, TaskPermit};
+pub use task_log::{
+    task_directory_name, ChildUsage, TaskLog, TaskLogKey, TASK_LOG_ENTRIES, TASK_LOG_USAGE,
+};
 pub use traits::{TraitGenerator, TraitGeneratorByTrait, TraitGeneratorByType, Traits};
 
 /// Runtime context which is immutable.
@@ -69,6 +81,11 @@ impl GlobalContext {
         self.shared_state.get(|| Ok(Hooks::default())).unwrap()
     }
 
+    /// Get the input paths registry.
+    pub fn input_paths(&self) -> shared_state::SharedStateRef<InputPaths> {
+        self.shared_state.get(|| Ok(InputPaths::default())).unwrap()
+    }
+
     /// Get the owned paths registry.
     pub fn owned_paths(&self) -> shared_state::SharedStateRef<OwnedPaths> {
         let mut created = false;
@@ -85,6 +102,40 @@ impl GlobalContext {
         }
         ret
     }
+
+    /// Get the artifact store.
+    ///
+    /// The store is in the `artifacts` directory of the project directory.
+    pub fn artifacts(&self) -> shared_state::SharedStateRef<Artifacts> {
+        self.shared_state
+            .get(|| {
+                Ok(Artifacts::new(
+                    self.env.project_directory().join("artifacts"),
+                ))
+            })
+            .unwrap()
+    }
+
+    /// Get the lock file, loading it if necessary.
+    ///
+    /// The lock file is written when the runtime is shutdown, if it changed.
+    pub fn lock_file(&self) -> crate::Result<shared_state::SharedStateRef<LockFile>> {
+        let mut created = false;
+        let ret = self.shared_state.get(|| {
+            created = true;
+            LockFile::load(self.env.lock_file().map(|p| p.into_owned()))
+                .map_err(|e| crate::error::Diagnostic::from(e).into())
+        })?;
+        if created {
+            let lock_file = ret.clone();
+            self.hooks().add_shutdown(move || {
+                if let Err(e) = lock_file.write() {
+                    ::log::warn!("error while writing lock file: {}", e);
+                }
+            });
+        }
+        Ok(ret)
+    }
 }
 
 /// Runtime context.
@@ -98,6 +149,8 @@ pub struct Context {
     pub error_scope: ErrorScope,
     /// The backtrace storage interface.
     pub backtrace: Backtrace,
+    /// The capabilities granted to evaluation.
+    pub capabilities: Capabilities,
 }
 
 /// A builder for a Context.
@@ -108,6 +161,11 @@ pub struct ContextBuilder {
     threads: Option<usize>,
     aggregate_errors: Option<bool>,
     error_scope: Option<ErrorScope>,
+    jobserver: Option<JobServerMode>,
+    dry_run: Option<bool>,
+    lock_file: Option<std::path::PathBuf>,
+    task_log_dir: Option<std::path::PathBuf>,
+    capabilities: Option<Capabilities>,
 }
 
 trait Fork {
@@ -175,6 +233,41 @@ impl ContextBuilder {
         self
     }
 
+    /// Set whether task slots are shared with child processes through a GNU make jobserver.
+    /// Default is `JobServerMode::Disabled`.
+    pub fn jobserver(mut self, mode: JobServerMode) -> Self {
+        self.jobserver = Some(mode);
+        self
+    }
+
+    /// Set whether side-effecting functions should only log what they would do.
+    /// Default is false.
+    pub fn dry_run(mut self, value: bool) -> Self {
+        self.dry_run = Some(value);
+        self
+    }
+
+    /// Set the lock file used to pin the content of retrieved resources.
+    /// Default is no lock file.
+    pub fn lock_file(mut self, path: std::path::PathBuf) -> Self {
+        self.lock_file = Some(path);
+        self
+    }
+
+    /// Set the directory in which task logs are written.
+    /// Default is no task logs.
+    pub fn task_log_directory(mut self, dir: std::path::PathBuf) -> Self {
+        self.task_log_dir = Some(dir);
+        self
+    }
+
+    /// Set the capabilities granted to evaluation.
+    /// Default is `Capabilities::default()` (all capabilities).
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
     /// Set the top-level error handler of the context.
     pub fn error_handler<F>(mut self, on_error: F) -> Self
     where
@@ -193,12 +286,18 @@ impl ContextBuilder {
                     self.logger
                         .unwrap_or_else(|| logger_ref(EmptyLogTarget).into()),
                 ),
-                env: Environment::new(self.storage_dir.unwrap_or(std::env::temp_dir())),
+                env: Environment::new(
+                    self.storage_dir.unwrap_or(std::env::temp_dir()),
+                    self.dry_run.unwrap_or(false),
+                    self.lock_file,
+                    self.task_log_dir,
+                ),
                 shared_state: SharedState::new(),
                 task: TaskManager::new(
                     self.threads,
                     self.aggregate_errors.unwrap_or(false),
                     progress.clone(),
+                    self.jobserver.unwrap_or(JobServerMode::Disabled),
                 )
                 .map_err(BuilderError::TaskManagerError)?,
                 traits: Default::default(),
@@ -207,6 +306,7 @@ impl ContextBuilder {
             dynamic_scope: Default::default(),
             error_scope: self.error_scope.unwrap_or_default(),
             backtrace: Default::default(),
+            capabilities: self.capabilities.unwrap_or_default(),
         })
     }
 }
@@ -389,6 +489,7 @@ impl Fork for Context {
             dynamic_scope: self.dynamic_scope.fork(),
             error_scope: self.error_scope.fork(),
             backtrace: self.backtrace.fork(),
+            capabilities: self.capabilities.fork(),
         }
     }
 
@@ -397,5 +498,6 @@ impl Fork for Context {
         self.dynamic_scope.join(forked.dynamic_scope);
         self.error_scope.join(forked.error_scope);
         self.backtrace.join(forked.backtrace);
+        self.capabilities.join(forked.capabilities);
     }
 }
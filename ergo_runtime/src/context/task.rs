@@ -18,8 +18,11 @@ use crate::error::DiagnosticInfo;
 use crate::Error;
 use futures::future::{abortable, try_join_all, AbortHandle, Aborted, Future, FutureExt};
 use std::cell::Cell;
+mod jobserver;
 pub mod runtime;
 
+pub use jobserver::{JobServer, JobServerMode, JobTokens};
+
 /// Create a task local value.
 #[macro_export]
 macro_rules! task_local {
@@ -265,6 +268,7 @@ pub struct TaskManager {
     abort_handles: RArc<RMutex<RVec<AbortHandleInterface_TO<'static, RBox<()>>>>>,
     threads: usize,
     aggregate_errors: bool,
+    jobserver: ROption<JobServer>,
 }
 
 impl std::fmt::Debug for TaskManager {
@@ -275,6 +279,7 @@ impl std::fmt::Debug for TaskManager {
             .field("abort_handles", &self.abort_handles.lock())
             .field("threads", &self.threads)
             .field("aggregate_errors", &self.aggregate_errors)
+            .field("jobserver", &self.jobserver)
             .finish()
     }
 }
@@ -285,12 +290,27 @@ impl TaskManager {
     /// The number of threads, if None, will be the number of cpus of the system.
     /// Aggregate errors determines whether joins will fail as soon as an error occurs (false) or
     /// whether it will wait for all results/errors (true).
+    /// The jobserver mode determines whether task slots are shared with child processes through a
+    /// GNU make jobserver.
     pub fn new(
         num_threads: Option<usize>,
         aggregate_errors: bool,
         progress: super::progress::Progress,
+        jobserver: JobServerMode,
     ) -> Result<Self, futures::io::Error> {
         let threads = std::cmp::max(1, num_threads.unwrap_or_else(num_cpus::get));
+        let create_jobserver = || match JobServer::new(threads - 1) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                log::warn!("failed to create jobserver: {}", e);
+                None
+            }
+        };
+        let jobserver = match jobserver {
+            JobServerMode::Disabled => None,
+            JobServerMode::Create => create_jobserver(),
+            JobServerMode::Join => JobServer::from_env().or_else(create_jobserver),
+        };
         let abort_handles: RArc<RMutex<RVec<AbortHandleInterface_TO<'static, RBox<()>>>>> =
             RArc::new(RMutex::new(Default::default()));
 
@@ -340,6 +360,7 @@ impl TaskManager {
             abort_handles,
             threads,
             aggregate_errors,
+            jobserver: jobserver.into(),
         })
     }
 
@@ -431,8 +452,23 @@ impl TaskManager {
     ///
     /// Until the returned permit is dropped, `n` active tasks will be counted against the total
     /// permissible concurrent tasks.
+    ///
+    /// If a jobserver is in use, `n` tokens are also acquired from it.
     pub async fn task_acquire(&self, count: u32) -> TaskPermit {
-        acquire_owned(&self.tasks, count).await
+        let permit = acquire_owned(&self.tasks, count).await;
+        match &self.jobserver {
+            ROption::RNone => permit,
+            ROption::RSome(jobserver) => {
+                let count = std::cmp::min(count as usize, self.threads) as u32;
+                let tokens = jobserver.acquire(count, self).await;
+                SemaphorePermit::new((permit, tokens))
+            }
+        }
+    }
+
+    /// Get the jobserver shared with child processes, if any.
+    pub fn jobserver(&self) -> Option<&JobServer> {
+        self.jobserver.as_ref().into_option()
     }
 
     /// Return whether the runtime is configured for error aggregation.
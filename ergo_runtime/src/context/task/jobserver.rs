@@ -0,0 +1,416 @@
+//! GNU make jobserver support.
+//!
+//! A jobserver is a pipe (or fifo) containing tokens (single bytes), each of which allows one
+//! additional job to run concurrently; every process implicitly holds one token. Tasks acquire
+//! tokens from the jobserver, and child processes (like `make` or `cargo`) are passed the jobserver
+//! so that task slots are shared by the whole process tree.
+
+use crate::abi_stable::{
+    external_types::RMutex,
+    std_types::{RArc, RString},
+    StableAbi,
+};
+
+/// How the task manager uses a jobserver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobServerMode {
+    /// Do not use a jobserver.
+    Disabled,
+    /// Create a jobserver with the task manager's task slots.
+    Create,
+    /// Join the jobserver from the environment (`MAKEFLAGS`), creating one if none is present.
+    Join,
+}
+
+impl std::str::FromStr for JobServerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(JobServerMode::Disabled),
+            "on" => Ok(JobServerMode::Create),
+            "join" => Ok(JobServerMode::Join),
+            other => Err(format!(
+                "invalid jobserver mode: {} (expected one of off, on, or join)",
+                other
+            )),
+        }
+    }
+}
+
+/// The delay before retrying to acquire tokens after releasing partially-acquired tokens.
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// A GNU make jobserver.
+#[derive(Clone, StableAbi)]
+#[repr(C)]
+pub struct JobServer {
+    inner: RArc<Inner>,
+    /// The jobserver auth argument (`R,W` or `fifo:PATH`).
+    auth: RString,
+}
+
+#[derive(StableAbi)]
+#[repr(C)]
+struct Inner {
+    read: i32,
+    write: i32,
+    /// Whether the file descriptors should be closed when dropped.
+    owned: bool,
+    /// Whether the implicit token of this process is available.
+    implicit: RMutex<bool>,
+}
+
+#[cfg(unix)]
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {
+                libc::close(self.read);
+                if self.write != self.read {
+                    libc::close(self.write);
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for JobServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("JobServer")
+            .field("auth", &self.auth)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Tokens acquired from a jobserver, which are released when dropped.
+#[derive(Debug)]
+pub struct JobTokens {
+    server: JobServer,
+    implicit: bool,
+    tokens: Vec<u8>,
+}
+
+impl Drop for JobTokens {
+    fn drop(&mut self) {
+        if !self.tokens.is_empty() {
+            use std::io::Write;
+            // Tokens must be returned with the same byte values that were read.
+            if let Err(e) = self
+                .server
+                .file(self.server.inner.write)
+                .write_all(&self.tokens)
+            {
+                log::error!("failed to release jobserver tokens: {}", e);
+            }
+        }
+        if self.implicit {
+            *self.server.inner.implicit.lock() = true;
+        }
+    }
+}
+
+impl JobServer {
+    fn with_fds(read: i32, write: i32, owned: bool, auth: String) -> Self {
+        JobServer {
+            inner: RArc::new(Inner {
+                read,
+                write,
+                owned,
+                implicit: RMutex::new(true),
+            }),
+            auth: auth.into(),
+        }
+    }
+
+    /// The flags to pass to child processes in `MAKEFLAGS`.
+    pub fn make_flags(&self) -> String {
+        if self.auth.starts_with("fifo:") {
+            format!("-j --jobserver-auth={}", self.auth)
+        } else {
+            // Older versions of make use `--jobserver-fds`.
+            format!("-j --jobserver-fds={0} --jobserver-auth={0}", self.auth)
+        }
+    }
+
+    /// Acquire `count` tokens, blocking until they are available.
+    ///
+    /// Tokens are only held while waiting if no more are needed: when some tokens have been
+    /// acquired but no more are available, the acquired tokens are released and acquisition is
+    /// retried once tokens become available. This avoids deadlocks with other processes which
+    /// each hold some of the tokens they need.
+    ///
+    /// If the jobserver fails (e.g. is closed), fewer tokens may be returned.
+    fn acquire_blocking(&self, count: u32) -> JobTokens {
+        use std::io::Read;
+
+        loop {
+            let implicit = count > 0 && std::mem::replace(&mut *self.inner.implicit.lock(), false);
+            let mut tokens = JobTokens {
+                server: self.clone(),
+                implicit,
+                tokens: Vec::new(),
+            };
+            let mut file = self.file(self.inner.read);
+            let mut complete = true;
+            while (tokens.tokens.len() + implicit as usize) < count as usize {
+                let holding = implicit || !tokens.tokens.is_empty();
+                if holding && !self.wait_readable(0) {
+                    complete = false;
+                    break;
+                }
+                let mut byte = [0u8];
+                match file.read(&mut byte) {
+                    Ok(1) => tokens.tokens.push(byte[0]),
+                    Ok(_) => {
+                        log::warn!("jobserver closed, continuing without its tokens");
+                        break;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        log::warn!("failed to acquire jobserver token: {}", e);
+                        break;
+                    }
+                }
+            }
+            if complete {
+                return tokens;
+            }
+
+            // Release the tokens and wait for more to be available. Back off briefly, as the
+            // released tokens will immediately be available.
+            drop(tokens);
+            std::thread::sleep(RETRY_DELAY);
+            self.wait_readable(-1);
+        }
+    }
+
+    /// Acquire `count` tokens.
+    pub async fn acquire(&self, count: u32, task: &super::TaskManager) -> Option<JobTokens> {
+        let server = self.clone();
+        // If the task is aborted, the tokens are dropped (and released) once acquired.
+        task.spawn_blocking(move || server.acquire_blocking(count))
+            .await
+            .ok()
+    }
+}
+
+#[cfg(unix)]
+impl JobServer {
+    /// Create a jobserver with the given number of tokens (in addition to the implicit token).
+    pub fn new(tokens: usize) -> std::io::Result<Self> {
+        use std::io::Write;
+
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        for fd in fds {
+            set_cloexec(fd, true)?;
+        }
+        let server = Self::with_fds(fds[0], fds[1], true, format!("{},{}", fds[0], fds[1]));
+        server
+            .file(server.inner.write)
+            .write_all(&vec![b'|'; tokens])?;
+        Ok(server)
+    }
+
+    /// Get the jobserver from the environment (`CARGO_MAKEFLAGS`, `MAKEFLAGS`, or `MFLAGS`), if
+    /// any.
+    pub fn from_env() -> Option<Self> {
+        ["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .find_map(|flags| {
+                flags
+                    .to_str()?
+                    .split_whitespace()
+                    .filter_map(|arg| {
+                        arg.strip_prefix("--jobserver-auth=")
+                            .or_else(|| arg.strip_prefix("--jobserver-fds="))
+                    })
+                    .last()
+                    .and_then(Self::from_auth)
+            })
+    }
+
+    fn from_auth(auth: &str) -> Option<Self> {
+        if let Some(path) = auth.strip_prefix("fifo:") {
+            use std::os::unix::io::IntoRawFd;
+            let fd = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .ok()?
+                .into_raw_fd();
+            return Some(Self::with_fds(fd, fd, true, auth.to_owned()));
+        }
+
+        let (read, write) = auth.split_once(',')?;
+        let (read, write) = (read.parse().ok()?, write.parse().ok()?);
+        // The file descriptors are not valid if the parent did not pass them to this process.
+        for fd in [read, write] {
+            if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                log::warn!("jobserver file descriptors from the environment are invalid");
+                return None;
+            }
+            set_cloexec(fd, true).ok()?;
+        }
+        Some(Self::with_fds(read, write, false, auth.to_owned()))
+    }
+
+    /// Configure a command to use the jobserver.
+    ///
+    /// This sets the `MAKEFLAGS`, `MFLAGS`, and `CARGO_MAKEFLAGS` environment variables (unless
+    /// they were explicitly set or removed for the command) and makes the jobserver file
+    /// descriptors inheritable by the child process.
+    pub fn configure(&self, command: &mut std::process::Command) {
+        use std::os::unix::process::CommandExt;
+
+        let flags = self.make_flags();
+        let explicit: Vec<_> = command.get_envs().map(|(k, _)| k.to_owned()).collect();
+        for var in ["MAKEFLAGS", "MFLAGS", "CARGO_MAKEFLAGS"] {
+            if !explicit.iter().any(|k| k == var) {
+                command.env(var, &flags);
+            }
+        }
+        let (read, write) = (self.inner.read, self.inner.write);
+        unsafe {
+            command.pre_exec(move || {
+                set_cloexec(read, false)?;
+                set_cloexec(write, false)
+            });
+        }
+    }
+
+    /// Get a File for a file descriptor, without taking ownership of it.
+    fn file(&self, fd: i32) -> std::mem::ManuallyDrop<std::fs::File> {
+        use std::os::unix::io::FromRawFd;
+        std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) })
+    }
+
+    /// Wait for a token to be available to read, with the given timeout in milliseconds (or
+    /// indefinitely if negative).
+    ///
+    /// Returns whether a token is available (or the jobserver is closed).
+    fn wait_readable(&self, timeout: i32) -> bool {
+        let mut fd = libc::pollfd {
+            fd: self.inner.read,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            match unsafe { libc::poll(&mut fd, 1, timeout) } {
+                -1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {
+                    continue
+                }
+                n => return n > 0,
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_cloexec(fd: i32, set: bool) -> std::io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let new_flags = if set {
+            flags | libc::FD_CLOEXEC
+        } else {
+            flags & !libc::FD_CLOEXEC
+        };
+        if new_flags != flags && libc::fcntl(fd, libc::F_SETFD, new_flags) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+impl JobServer {
+    /// Create a jobserver with the given number of tokens (in addition to the implicit token).
+    pub fn new(_tokens: usize) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "jobservers are only supported on unix platforms",
+        ))
+    }
+
+    /// Get the jobserver from the environment, if any.
+    pub fn from_env() -> Option<Self> {
+        None
+    }
+
+    /// Configure a command to use the jobserver.
+    pub fn configure(&self, _command: &mut std::process::Command) {}
+
+    fn file(&self, _fd: i32) -> std::mem::ManuallyDrop<std::fs::File> {
+        unreachable!("jobservers are only supported on unix platforms")
+    }
+
+    fn wait_readable(&self, _timeout: i32) -> bool {
+        unreachable!("jobservers are only supported on unix platforms")
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tokens_are_released() {
+        let server = JobServer::new(2).unwrap();
+        let tokens = server.acquire_blocking(3);
+        assert!(tokens.implicit);
+        assert_eq!(tokens.tokens.len(), 2);
+        drop(tokens);
+        let tokens = server.acquire_blocking(3);
+        assert!(tokens.implicit);
+        assert_eq!(tokens.tokens.len(), 2);
+    }
+
+    #[test]
+    fn partial_tokens_are_released() {
+        let server = JobServer::new(1).unwrap();
+        let held = server.acquire_blocking(2);
+        assert_eq!(held.tokens.len() + held.implicit as usize, 2);
+
+        let (send, recv) = std::sync::mpsc::channel();
+        let s = server.clone();
+        std::thread::spawn(move || {
+            let tokens = s.acquire_blocking(2);
+            send.send(tokens.tokens.len() + tokens.implicit as usize)
+                .unwrap();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        drop(held);
+        assert_eq!(recv.recv_timeout(std::time::Duration::from_secs(5)), Ok(2));
+    }
+
+    #[test]
+    fn configure_keeps_explicit_flags() {
+        let server = JobServer::new(0).unwrap();
+        let mut command = std::process::Command::new("true");
+        command.env("MAKEFLAGS", "-j1");
+        server.configure(&mut command);
+        let envs: std::collections::HashMap<_, _> = command.get_envs().collect();
+        assert_eq!(
+            envs[std::ffi::OsStr::new("MAKEFLAGS")],
+            Some(std::ffi::OsStr::new("-j1"))
+        );
+        assert!(envs[std::ffi::OsStr::new("MFLAGS")].is_some());
+    }
+
+    #[test]
+    fn auth() {
+        let server = JobServer::new(0).unwrap();
+        let joined = JobServer::from_auth(&server.auth).unwrap();
+        assert_eq!(joined.inner.read, server.inner.read);
+        assert_eq!(joined.inner.write, server.inner.write);
+        assert!(!joined.inner.owned);
+        assert!(server.make_flags().contains("--jobserver-auth="));
+    }
+}
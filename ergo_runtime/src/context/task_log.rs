@@ -0,0 +1,280 @@
+//! Persistent task logs.
+//!
+//! When a task log directory is configured, each task writes its log entries and the output of
+//! child processes to files in a directory named after the task description:
+//! * `log` contains the log entries written while the task was in scope,
+//! * `<n>.stdout`/`<n>.stderr` contain the output of the `n`th child process spawned by the task
+//!   (recorded as `child <n>: <command>` in `log`), and
+//! * `usage` contains the resource usage of each child process which exited, one per line.
+
+use super::{DynamicScopeKey, LogEntry, CURRENT_CONTEXT};
+use crate::abi_stable::{
+    external_types::RMutex,
+    path::PathBuf,
+    std_types::{RArc, RBox, ROption},
+    DynTrait, StableAbi,
+};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// The name of the file which contains task log entries.
+pub const TASK_LOG_ENTRIES: &str = "log";
+
+/// The name of the file which contains the resource usage of child processes.
+pub const TASK_LOG_USAGE: &str = "usage";
+
+/// The resource usage of a child process.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChildUsage {
+    /// The wall time from spawning the child until it exited.
+    pub wall: Duration,
+    /// The user CPU time, if available.
+    pub user: Option<Duration>,
+    /// The system CPU time, if available.
+    pub system: Option<Duration>,
+    /// The peak resident set size in bytes, if available.
+    pub max_rss: Option<u64>,
+}
+
+impl std::fmt::Display for ChildUsage {
+    /// Write the usage as space-separated `wall user system max_rss` fields, where durations are
+    /// in microseconds and unavailable fields are written as `-`.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fn field<T: std::fmt::Display>(v: Option<T>) -> String {
+            v.map(|v| v.to_string()).unwrap_or_else(|| "-".into())
+        }
+        write!(
+            f,
+            "{} {} {} {}",
+            self.wall.as_micros(),
+            field(self.user.map(|d| d.as_micros())),
+            field(self.system.map(|d| d.as_micros())),
+            field(self.max_rss)
+        )
+    }
+}
+
+impl std::str::FromStr for ChildUsage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn field(v: Option<&str>) -> Result<Option<u64>, String> {
+            match v {
+                None => Err("missing field".into()),
+                Some("-") => Ok(None),
+                Some(v) => v.parse().map(Some).map_err(|e| format!("{}", e)),
+            }
+        }
+        let mut fields = s.split_whitespace();
+        let wall = field(fields.next())?.ok_or("missing wall time")?;
+        Ok(ChildUsage {
+            wall: Duration::from_micros(wall),
+            user: field(fields.next())?.map(Duration::from_micros),
+            system: field(fields.next())?.map(Duration::from_micros),
+            max_rss: field(fields.next())?,
+        })
+    }
+}
+
+#[derive(StableAbi)]
+#[repr(C)]
+#[sabi(impl_InterfaceType(Send, Sync, Debug, IoWrite))]
+struct EntriesFileInterface;
+
+/// The open log entries file of a task.
+type EntriesFile = DynTrait<'static, RBox<()>, EntriesFileInterface>;
+
+/// The log of a single task.
+#[derive(Clone, Debug, StableAbi)]
+#[repr(C)]
+pub struct TaskLog {
+    directory: RArc<PathBuf>,
+    /// The log entries file, opened on the first write and shared by all clones.
+    entries: RArc<RMutex<ROption<EntriesFile>>>,
+}
+
+/// The dynamic scope key of the current task log.
+pub struct TaskLogKey;
+
+impl DynamicScopeKey for TaskLogKey {
+    type Value = TaskLog;
+
+    fn id(&self) -> u128 {
+        crate::nsid!(task_log).as_u128()
+    }
+}
+
+/// Get the name of the directory used for the task with the given description.
+///
+/// Characters which are not alphanumeric, `-`, `_`, or `.` are replaced with `_`. Distinct
+/// descriptions may have the same name, in which case the tasks share a directory (and their child
+/// processes are numbered uniquely within it).
+pub fn task_directory_name(description: &str) -> String {
+    let name: String = description
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() || name.chars().all(|c| c == '.') {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+impl TaskLog {
+    /// Create a task log for the task with the given description in the given (run) directory.
+    pub fn new(run_directory: &Path, description: &str) -> std::io::Result<Self> {
+        let directory = run_directory.join(task_directory_name(description));
+        std::fs::create_dir_all(&directory)?;
+        Ok(TaskLog {
+            directory: RArc::new(directory.into()),
+            entries: RArc::new(RMutex::new(ROption::RNone)),
+        })
+    }
+
+    /// Get the task log of the current task, if any.
+    ///
+    /// This returns None if there is no current context (e.g. when called from a blocking thread).
+    pub fn current() -> Option<Self> {
+        CURRENT_CONTEXT.with(|ctx| {
+            ctx.and_then(|ctx| ctx.dynamic_scope.get(&TaskLogKey))
+                .map(|l| (*l).clone())
+        })
+    }
+
+    /// Get the directory containing the task log files.
+    pub fn directory(&self) -> std::borrow::Cow<Path> {
+        PathBuf::as_ref(&self.directory)
+    }
+
+    /// Write a log entry.
+    pub fn write_entry(&self, entry: &LogEntry) {
+        if let Err(e) = self.append_entry(format!("{}\n", entry).as_bytes()) {
+            ::log::warn!("failed to write task log entry: {}", e);
+        }
+    }
+
+    /// Create the output files of a new child process, running the given command.
+    ///
+    /// The command is recorded in the log entries file. Returns the child number and the stdout
+    /// and stderr files.
+    pub fn child_output(
+        &self,
+        command: &str,
+    ) -> std::io::Result<(usize, std::fs::File, std::fs::File)> {
+        let dir = self.directory();
+        let mut n = 1;
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(dir.join(format!("{}.stdout", n)))
+            {
+                Ok(stdout) => {
+                    let stderr = std::fs::File::create(dir.join(format!("{}.stderr", n)))?;
+                    self.append_entry(format!("child {}: {}\n", n, command).as_bytes())?;
+                    return Ok((n, stdout, stderr));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Record the resource usage of the given child (as numbered by `child_output`).
+    pub fn write_child_usage(&self, child: usize, usage: &ChildUsage) {
+        if let Err(e) = self.append(TASK_LOG_USAGE, format!("{} {}\n", child, usage).as_bytes()) {
+            ::log::warn!("failed to write task child usage: {}", e);
+        }
+    }
+
+    fn append_entry(&self, data: &[u8]) -> std::io::Result<()> {
+        let mut guard = self.entries.lock();
+        if let ROption::RSome(file) = &mut *guard {
+            return file.write_all(data);
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory().join(TASK_LOG_ENTRIES))?;
+        file.write_all(data)?;
+        *guard = ROption::RSome(DynTrait::from_borrowing_value(file));
+        Ok(())
+    }
+
+    fn append(&self, name: &str, data: &[u8]) -> std::io::Result<()> {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory().join(name))?
+            .write_all(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn directory_names() {
+        assert_eq!(task_directory_name("build-1.0_x"), "build-1.0_x");
+        assert_eq!(task_directory_name("build: a/b c"), "build__a_b_c");
+        assert_eq!(task_directory_name("héllo"), "héllo");
+        assert_eq!(task_directory_name(""), "_");
+        assert_eq!(task_directory_name("."), "_.");
+        assert_eq!(task_directory_name(".."), "_..");
+        assert_eq!(task_directory_name("a b"), task_directory_name("a/b"));
+    }
+
+    #[test]
+    fn child_usage() {
+        let usage = ChildUsage {
+            wall: Duration::from_micros(1500),
+            user: Some(Duration::from_micros(20)),
+            system: None,
+            max_rss: Some(4096),
+        };
+        assert_eq!(usage.to_string(), "1500 20 - 4096");
+        assert_eq!("1500 20 - 4096".parse::<ChildUsage>(), Ok(usage));
+
+        let empty = ChildUsage::default();
+        assert_eq!(empty.to_string(), "0 - - -");
+        assert_eq!(empty.to_string().parse::<ChildUsage>(), Ok(empty));
+
+        assert!("".parse::<ChildUsage>().is_err());
+        assert!("- 1 2 3".parse::<ChildUsage>().is_err());
+        assert!("1 2 3".parse::<ChildUsage>().is_err());
+        assert!("1 x 2 3".parse::<ChildUsage>().is_err());
+    }
+
+    #[test]
+    fn child_numbering() {
+        let run = std::env::temp_dir().join(format!("ergo-task-log-{}", std::process::id()));
+        drop(std::fs::remove_dir_all(&run));
+
+        // Tasks with colliding names share a directory, and children are numbered across them.
+        let a = TaskLog::new(&run, "a b").unwrap();
+        let b = TaskLog::new(&run, "a/b").unwrap();
+        assert_eq!(a.directory(), b.directory());
+        assert_eq!(a.child_output("first").unwrap().0, 1);
+        assert_eq!(b.child_output("second").unwrap().0, 2);
+
+        // Existing output files are not reused.
+        std::fs::write(a.directory().join("3.stdout"), "").unwrap();
+        assert_eq!(a.child_output("third").unwrap().0, 4);
+
+        let dir = a.directory().into_owned();
+        drop((a, b));
+        let entries = std::fs::read_to_string(dir.join(TASK_LOG_ENTRIES)).unwrap();
+        assert_eq!(entries, "child 1: first\nchild 2: second\nchild 4: third\n");
+        assert!(dir.join("2.stderr").exists());
+        drop(std::fs::remove_dir_all(&run));
+    }
+}
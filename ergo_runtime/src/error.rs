@@ -8,7 +8,7 @@
 use crate as ergo_runtime;
 use crate::abi_stable::{
     rvec,
-    std_types::{RArc, RString, RVec},
+    std_types::{RArc, ROption, RString, RVec},
     StableAbi,
 };
 use crate::type_system::ErgoType;
@@ -81,6 +81,71 @@ impl Label {
     }
 }
 
+/// The value of a structured diagnostic field.
+#[derive(Clone, Debug, StableAbi, Serialize, Deserialize, Hash, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FieldValue {
+    String(RString),
+    Integer(i64),
+    Bool(bool),
+}
+
+impl std::fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FieldValue::String(s) => s.fmt(f),
+            FieldValue::Integer(i) => i.fmt(f),
+            FieldValue::Bool(b) => b.fmt(f),
+        }
+    }
+}
+
+impl From<&'_ str> for FieldValue {
+    fn from(s: &str) -> Self {
+        FieldValue::String(s.into())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(s: String) -> Self {
+        FieldValue::String(s.into())
+    }
+}
+
+impl From<RString> for FieldValue {
+    fn from(s: RString) -> Self {
+        FieldValue::String(s)
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(b: bool) -> Self {
+        FieldValue::Bool(b)
+    }
+}
+
+macro_rules! field_value_from_integer {
+    ( $( $t:ty ),* ) => {
+        $(
+        impl From<$t> for FieldValue {
+            fn from(i: $t) -> Self {
+                FieldValue::Integer(i.into())
+            }
+        }
+        )*
+    };
+}
+
+field_value_from_integer!(i8, i16, i32, i64, u8, u16, u32);
+
+/// A structured field of a diagnostic.
+#[derive(Clone, Debug, StableAbi, Serialize, Deserialize, Hash, PartialEq, Eq)]
+#[repr(C)]
+pub struct Field {
+    pub name: RString,
+    pub value: FieldValue,
+}
+
 /// A diagnostic to display to the user.
 #[derive(Clone, Debug, StableAbi, Serialize, Deserialize, Hash, PartialEq, Eq)]
 #[repr(C)]
@@ -89,6 +154,10 @@ pub struct Diagnostic {
     pub message: RString,
     pub labels: RVec<Label>,
     pub notes: RVec<RString>,
+    /// The kind of the diagnostic, which may be used to distinguish errors.
+    pub kind: ROption<RString>,
+    /// Structured data relating to the diagnostic.
+    pub fields: RVec<Field>,
 }
 
 impl Severity {
@@ -157,6 +226,13 @@ pub trait DiagnosticInfo {
     /// Add a note regarding the primary cause to the diagnostic info.
     fn add_note<S: ToString>(self, note: S) -> Self::Output;
 
+    /// Set the kind of the diagnostic info.
+    fn set_kind<S: ToString>(self, kind: S) -> Self::Output;
+
+    /// Add a structured field to the diagnostic info, replacing any existing field with the same
+    /// name.
+    fn add_field<S: ToString, V: Into<FieldValue>>(self, name: S, value: V) -> Self::Output;
+
     /// Add a label regarding the primary cause to the diagnostic info.
     fn add_primary_label<S: ToString>(self, label: crate::Source<S>) -> Self::Output
     where
@@ -275,6 +351,16 @@ impl DiagnosticInfo for Diagnostic {
         self.notes.push(note.to_string().into());
         self
     }
+
+    fn set_kind<S: ToString>(mut self, kind: S) -> Self::Output {
+        (&mut self).set_kind(kind);
+        self
+    }
+
+    fn add_field<S: ToString, V: Into<FieldValue>>(mut self, name: S, value: V) -> Self::Output {
+        (&mut self).add_field(name, value);
+        self
+    }
 }
 
 impl DiagnosticInfo for &'_ mut Diagnostic {
@@ -297,6 +383,19 @@ impl DiagnosticInfo for &'_ mut Diagnostic {
     fn add_note<S: ToString>(self, note: S) -> Self::Output {
         self.notes.push(note.to_string().into());
     }
+
+    fn set_kind<S: ToString>(self, kind: S) -> Self::Output {
+        self.kind = ROption::RSome(kind.to_string().into());
+    }
+
+    fn add_field<S: ToString, V: Into<FieldValue>>(self, name: S, value: V) -> Self::Output {
+        let name: RString = name.to_string().into();
+        let value = value.into();
+        match self.fields.iter_mut().find(|f| f.name == name) {
+            Some(f) => f.value = value,
+            None => self.fields.push(Field { name, value }),
+        }
+    }
 }
 
 impl<T, E: Into<Diagnostic>> DiagnosticInfo for std::result::Result<T, E> {
@@ -321,6 +420,14 @@ impl<T, E: Into<Diagnostic>> DiagnosticInfo for std::result::Result<T, E> {
     fn add_note<S: ToString>(self, note: S) -> Self::Output {
         self.map_err(|e| e.into().add_note(note))
     }
+
+    fn set_kind<S: ToString>(self, kind: S) -> Self::Output {
+        self.map_err(|e| e.into().set_kind(kind))
+    }
+
+    fn add_field<S: ToString, V: Into<FieldValue>>(self, name: S, value: V) -> Self::Output {
+        self.map_err(|e| e.into().add_field(name, value))
+    }
 }
 
 impl<T> DiagnosticInfo for Option<T> {
@@ -345,6 +452,14 @@ impl<T> DiagnosticInfo for Option<T> {
     fn add_note<S: ToString>(self, note: S) -> Self::Output {
         self.into_diagnostic().add_note(note)
     }
+
+    fn set_kind<S: ToString>(self, kind: S) -> Self::Output {
+        self.into_diagnostic().set_kind(kind)
+    }
+
+    fn add_field<S: ToString, V: Into<FieldValue>>(self, name: S, value: V) -> Self::Output {
+        self.into_diagnostic().add_field(name, value)
+    }
 }
 
 /// Emit diagnostics to the given writer.
@@ -418,15 +533,36 @@ where
     T: Into<Box<ExternalError>>,
 {
     fn from(v: T) -> Self {
+        let e = v.into();
         Diagnostic {
             severity: Severity::Error,
-            message: v.into().to_string().into(),
+            message: e.to_string().into(),
             labels: Default::default(),
             notes: Default::default(),
+            kind: e
+                .downcast_ref::<std::io::Error>()
+                .map(|e| RString::from(io_error_kind(e)))
+                .into(),
+            fields: Default::default(),
         }
     }
 }
 
+/// Get the diagnostic kind of an io error.
+///
+/// Errors which are not found, lack permission, or already exist have the kinds `io:not-found`,
+/// `io:permission-denied`, and `io:already-exists` respectively. All other errors have the kind
+/// `io`.
+pub fn io_error_kind(e: &std::io::Error) -> &'static str {
+    use std::io::ErrorKind::*;
+    match e.kind() {
+        NotFound => "io:not-found",
+        PermissionDenied => "io:permission-denied",
+        AlreadyExists => "io:already-exists",
+        _ => "io",
+    }
+}
+
 /// Either an Error or a Diagnostic.
 ///
 /// This is mainly used as a convenient way for the Try operator (`?`) to be used with error return
@@ -499,6 +635,16 @@ impl DiagnosticInfo for ErrorOrDiagnostic {
         self.modify_diagnostic(|d| d.add_note(note));
         self
     }
+
+    fn set_kind<S: ToString>(mut self, kind: S) -> Self::Output {
+        self.modify_diagnostic(|d| d.set_kind(kind));
+        self
+    }
+
+    fn add_field<S: ToString, V: Into<FieldValue>>(mut self, name: S, value: V) -> Self::Output {
+        self.modify_diagnostic(|d| d.add_field(name, value));
+        self
+    }
 }
 
 impl<T> DiagnosticInfo for std::result::Result<T, ErrorOrDiagnostic> {
@@ -535,6 +681,20 @@ impl<T> DiagnosticInfo for std::result::Result<T, ErrorOrDiagnostic> {
         }
         self
     }
+
+    fn set_kind<S: ToString>(mut self, kind: S) -> Self::Output {
+        if let Err(e) = &mut self {
+            e.modify_diagnostic(|d| d.set_kind(kind));
+        }
+        self
+    }
+
+    fn add_field<S: ToString, V: Into<FieldValue>>(mut self, name: S, value: V) -> Self::Output {
+        if let Err(e) = &mut self {
+            e.modify_diagnostic(|d| d.add_field(name, value));
+        }
+        self
+    }
 }
 
 /// Create a diagnostic.
@@ -616,6 +776,9 @@ macro_rules! error {
 ///     notes: [
 ///         "something interesting"
 ///     ],
+///     fields: [
+///         "path" = ...
+///     ],
 ///     <async> BLOCK_EXPRESSION
 /// }
 /// ```
@@ -623,7 +786,7 @@ macro_rules! error {
 /// The expression can return (possibly early with `return` or `?`) an Error, a Diagnostic, or a
 /// type implementing `std::error::Error`. If a Diagnostic or `std::error::Error` is returned, the
 /// given details are set on the resulting Diagnostic. Note that `severity`, `message`, `labels`,
-/// and `notes` are all optional, but must be provided in the above order.
+/// `notes`, and `fields` are all optional, but must be provided in the above order.
 ///
 /// The macro evaluates to a Result type with the same Ok type as the expression, and an Error Err
 /// type.
@@ -631,7 +794,8 @@ macro_rules! error {
 macro_rules! error_info {
     ( $(severity : $severity:ident ,)? $(message : ($old_message:pat) $message:expr ,)?
       $(labels : [$($labelfn:ident ( $($labelarg:expr),* )),+] ,)?
-      $(notes : [$($note:expr),+] ,)? { $($es:tt)+ } ) => {
+      $(notes : [$($note:expr),+] ,)?
+      $(fields : [$($fname:literal = $fvalue:expr),+] ,)? { $($es:tt)+ } ) => {
         {
             (|| -> std::result::Result<_,$crate::error::ErrorOrDiagnostic> { { $($es)+ }.map_err(|e| e.into()) })().map_err(|e| match e {
                 $crate::error::ErrorOrDiagnostic::Error(e) => e,
@@ -641,6 +805,7 @@ macro_rules! error_info {
                     $(d.message = { let $old_message = d.message; $message };)?
                     $(d.labels.extend($crate::abi_stable::rvec![$($crate::error::Label::$labelfn ($($labelarg),*)),+]);)?
                     $(d.notes.extend($crate::abi_stable::rvec![$($note.to_string().into()),+]);)?
+                    $($($crate::error::DiagnosticInfo::add_field(&mut d, $fname, $fvalue);)+)?
                     d.into()
                 }
             })
@@ -648,7 +813,8 @@ macro_rules! error_info {
     };
     ( $(severity : $severity:ident ,)? $(message : ($old_message:pat) $message:expr ,)?
       $(labels : [$($labelfn:ident ( $($labelarg:expr),* )),+] ,)?
-      $(notes : [$($note:expr),+] ,)? async { $($es:tt)+ } ) => {
+      $(notes : [$($note:expr),+] ,)?
+      $(fields : [$($fname:literal = $fvalue:expr),+] ,)? async { $($es:tt)+ } ) => {
         {
             let result: std::result::Result<_, $crate::error::ErrorOrDiagnostic> = async {
                 { $($es)+ }.map_err(|e| e.into())
@@ -661,6 +827,7 @@ macro_rules! error_info {
                     $(d.message = { let $old_message = d.message; $message };)?
                     $(d.labels.extend($crate::abi_stable::rvec![$($crate::error::Label::$labelfn ($($labelarg),*)),+]);)?
                     $(d.notes.extend($crate::abi_stable::rvec![$($note.to_string().into()),+]);)?
+                    $($($crate::error::DiagnosticInfo::add_field(&mut d, $fname, $fvalue);)+)?
                     d.into()
                 }
             })
@@ -812,6 +979,34 @@ impl Error {
         }
     }
 
+    /// Get the kind of this Error.
+    ///
+    /// This is the kind of the first diagnostic which has one.
+    pub fn kind(&self) -> Option<&str> {
+        match &self.inner {
+            InnerError::Errors(errs) => errs
+                .iter()
+                .find_map(|e| e.kind.as_ref().map(|k| k.as_str()).into_option()),
+            _ => None,
+        }
+    }
+
+    /// Get the structured fields of this Error.
+    ///
+    /// If multiple diagnostics have fields with the same name, the field of the first diagnostic is
+    /// used.
+    pub fn fields(&self) -> Vec<&Field> {
+        let mut ret: Vec<&Field> = Vec::new();
+        if let InnerError::Errors(errs) = &self.inner {
+            for f in errs.iter().flat_map(|e| e.fields.iter()) {
+                if !ret.iter().any(|r| r.name == f.name) {
+                    ret.push(f);
+                }
+            }
+        }
+        ret
+    }
+
     /// Call a function on all diagnostics in this Error.
     pub fn visit_diagnostics<F>(&self, mut f: F)
     where
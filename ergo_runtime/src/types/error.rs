@@ -7,6 +7,11 @@ use crate::{traits, TypedValue};
 /// Script error type.
 pub use crate::Error;
 
+/// The format version of stored errors.
+///
+/// This must be incremented whenever the stored representation changes.
+const STORED_FORMAT_VERSION: u32 = 1;
+
 impl From<Error> for TypedValue<Error> {
     fn from(v: Error) -> Self {
         Self::constant(v)
@@ -65,6 +70,25 @@ where
     }
 }
 
+/// Serialize an Error with the stored format version.
+fn store<W: std::io::Write>(error: &Error, w: W) -> bincode::Result<()> {
+    bincode::serialize_into(w, &(STORED_FORMAT_VERSION, error))
+}
+
+/// Deserialize an Error serialized with `store`.
+///
+/// Errors stored with a different format version are rejected rather than misread.
+fn load<R: std::io::Read>(mut r: R) -> crate::Result<Error> {
+    let version: u32 = bincode::deserialize_from(&mut r)?;
+    if version != STORED_FORMAT_VERSION {
+        Err::<(), _>(format!(
+            "unsupported stored error format version {}",
+            version
+        ))?;
+    }
+    Ok(bincode::deserialize_from(r)?)
+}
+
 ergo_traits_fn! {
     impl traits::Stored for Error {
         async fn put(&self, data: &mut traits::PutData<'_>) -> crate::RResult<()> {
@@ -77,13 +101,13 @@ ergo_traits_fn! {
                 labels: [
                     primary(crate::metadata::Source::get(SELF_VALUE).with("while storing this value"))
                 ],
-                { bincode::serialize_into(data, self) }
+                { store(self, data) }
             ).into()
         }
 
         async fn get(data: &mut traits::GetData<'_>) -> crate::RResult<crate::abi_stable::type_erase::Erased> {
             crate::error_info!({
-                bincode::deserialize_from(data).map(|e: Error| crate::abi_stable::type_erase::Erased::new(e))
+                load(data).map(crate::abi_stable::type_erase::Erased::new)
             }).into()
         }
     }
@@ -93,3 +117,35 @@ ergo_traits_fn! {
     crate::ergo_display_basic!(traits, Error);
     crate::ergo_type_name!(traits, Error);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::{Diagnostic, DiagnosticInfo};
+
+    fn error() -> Error {
+        Diagnostic::from("failed")
+            .add_note("a note")
+            .set_kind("failure")
+            .add_field("count", 2)
+            .into()
+    }
+
+    #[test]
+    fn stored_roundtrip() {
+        let error = error();
+        let mut data = Vec::new();
+        store(&error, &mut data).unwrap();
+        let loaded = load(data.as_slice()).unwrap();
+        assert_eq!(loaded.to_string(), error.to_string());
+        assert_eq!(loaded.kind(), Some("failure"));
+        assert_eq!(loaded.fields(), error.fields());
+    }
+
+    #[test]
+    fn stored_version_mismatch() {
+        let mut data = Vec::new();
+        bincode::serialize_into(&mut data, &(STORED_FORMAT_VERSION + 1, &error())).unwrap();
+        assert!(load(data.as_slice()).is_err());
+    }
+}
@@ -4,12 +4,19 @@ use crate as ergo_runtime;
 use crate::abi_stable::{
     path::PathBuf, type_erase::Erased, StableAbi,
 };
+use crate::context::Artifact;
+use crate::error::DiagnosticInfo;
 use crate::metadata::Source;
 use crate::traits;
 use crate::type_system::{ergo_traits_fn, ErgoType};
-use crate::TypedValue;
+use crate::{Context, TypedValue};
 use bincode;
 
+/// The format version of stored paths.
+///
+/// This must be incremented whenever the stored representation changes.
+const STORED_FORMAT_VERSION: u32 = 1;
+
 /// Script path type.
 #[derive(Debug, Default, ErgoType, StableAbi)]
 #[repr(C)]
@@ -120,13 +127,44 @@ ergo_traits_fn! {
         async fn put(&self, data: &mut traits::PutData<'_>) -> crate::RResult<()> {
             crate::error_info!(
                 labels: [ primary(Source::get(SELF_VALUE).with("while storing this value")) ],
-                { bincode::serialize_into(data, self.path.as_ref().as_ref()) }
+                {
+                    let artifacts = Context::global().artifacts();
+                    let path = self.path.as_ref();
+                    let artifact = if artifacts.contains(&self.path) {
+                        Some(artifacts.store(path.as_ref())?)
+                    } else {
+                        None
+                    };
+                    bincode::serialize_into(data, &(STORED_FORMAT_VERSION, path.as_ref(), artifact))
+                }
             ).into()
         }
 
         async fn get(data: &mut traits::GetData<'_>) -> crate::RResult<Erased> {
             crate::error_info!(
-                { bincode::deserialize_from(data).map(|p: std::path::PathBuf| Erased::new(Path::from(p))) }
+                {
+                    let version: u32 = bincode::deserialize_from(&mut *data)?;
+                    if version != STORED_FORMAT_VERSION {
+                        Err::<(), _>(format!("unsupported stored path format version {}", version))?;
+                    }
+                    let (path, artifact): (std::path::PathBuf, Option<Artifact>) =
+                        bincode::deserialize_from(data)?;
+                    if let Some(artifact) = artifact {
+                        let ctx = Context::global();
+                        if ctx.env.dry_run() {
+                            ctx.log
+                                .sublog("dry-run")
+                                .info(format_args!("restore artifact {}", path.display()));
+                        } else {
+                            Context::with(|ctx| ctx.capabilities.check_write(&path))
+                                .add_note("not permitted by the current capabilities")
+                                .set_kind("not-permitted")?;
+                            ctx.artifacts().restore(&path, &artifact)?;
+                        }
+                        ctx.artifacts().add(&path.clone().into());
+                    }
+                    crate::Result::Ok(Erased::new(Path::from(path)))
+                }
             ).into()
         }
     }
@@ -231,6 +231,14 @@ pub trait ValueDataInterface: Clone + Send + Sync + 'static {
     fn eval_for_id_hint(&self) -> future::BoxFuture<bool> {
         future::BoxFuture::new(async { false })
     }
+
+    /// Get the dependencies from which the identity of a value is derived.
+    ///
+    /// This is only used to inspect identities; it may be empty if the identity is not derived
+    /// from other dependencies.
+    fn dependencies(&self) -> Dependencies {
+        Dependencies::new()
+    }
 }
 
 #[derive(Clone, StableAbi)]
@@ -319,6 +327,10 @@ impl ValueDataInterface for ValueData {
     fn eval_for_id_hint(&self) -> future::BoxFuture<bool> {
         self.0.eval_for_id_hint()
     }
+
+    fn dependencies(&self) -> Dependencies {
+        self.0.dependencies()
+    }
 }
 
 impl<T> IdInfo<T> {
@@ -759,6 +771,12 @@ impl<T: ErgoType + InnerValues + Clone + Send + Sync + 'static> ValueDataInterfa
     fn get(&self) -> ValueType {
         ValueType::typed(&self.0)
     }
+
+    fn dependencies(&self) -> Dependencies {
+        let mut info = Vec::new();
+        self.0.visit_info(|v| info.push(v));
+        crate::depends![dyn T::ergo_type(), ^@info]
+    }
 }
 
 #[derive(Clone)]
@@ -828,6 +846,11 @@ pub trait LazyValueId:
     LateBind + lazy_value_id_clone::LazyValueIdClone + Send + Sync + 'static
 {
     fn id(&self) -> futures::future::BoxFuture<Identity>;
+
+    /// Get the dependencies from which the identity is derived.
+    fn dependencies(&self) -> Dependencies {
+        Dependencies::new()
+    }
 }
 
 impl LateBind for Value {
@@ -844,6 +867,10 @@ impl LazyValueId for Value {
     fn id(&self) -> futures::future::BoxFuture<Identity> {
         futures::FutureExt::boxed(async move { self.clone().eval_id().await })
     }
+
+    fn dependencies(&self) -> Dependencies {
+        crate::depends![dyn self]
+    }
 }
 
 impl LateBind for Dependencies {
@@ -868,6 +895,10 @@ impl LazyValueId for Dependencies {
     fn id(&self) -> futures::future::BoxFuture<Identity> {
         futures::FutureExt::boxed(Self::id(self))
     }
+
+    fn dependencies(&self) -> Dependencies {
+        self.clone()
+    }
 }
 
 impl From<DependenciesConstant> for ValueId {
@@ -931,6 +962,14 @@ impl ValueId {
             ValueId::Override { id, .. } => id.0.late_bound(),
         }
     }
+
+    pub fn dependencies(&self) -> Dependencies {
+        match self {
+            ValueId::Id(_) => Default::default(),
+            ValueId::Lazy(l) => l.0.dependencies(),
+            ValueId::Override { id, .. } => id.0.dependencies(),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -968,6 +1007,10 @@ impl<T: ValueDataInterface> ValueDataInterface for IdValueData<T> {
             }
         })
     }
+
+    fn dependencies(&self) -> Dependencies {
+        self.id.dependencies()
+    }
 }
 
 /// Lazy value helpers.
@@ -1035,6 +1078,11 @@ pub mod lazy {
         fn eval_for_id_hint(&self) -> futures::future::BoxFuture<bool> {
             futures::FutureExt::boxed(async { false })
         }
+
+        /// The dependencies of the captures.
+        fn dependencies(&self) -> Dependencies {
+            Dependencies::new()
+        }
     }
 
     impl LazyCaptures for () {
@@ -1065,6 +1113,10 @@ pub mod lazy {
         fn eval_for_id_hint(&self) -> futures::future::BoxFuture<bool> {
             futures::FutureExt::boxed(self.inner.data.eval_for_id_hint())
         }
+
+        fn dependencies(&self) -> Dependencies {
+            crate::depends![dyn self]
+        }
     }
 
     impl<T: Send + Sync + 'static> LazyCaptures for TypedValue<T> {
@@ -1083,6 +1135,10 @@ pub mod lazy {
         fn eval_for_id_hint(&self) -> futures::future::BoxFuture<bool> {
             LazyCaptures::eval_for_id_hint(&self.inner)
         }
+
+        fn dependencies(&self) -> Dependencies {
+            LazyCaptures::dependencies(&self.inner)
+        }
     }
 
     impl<T: LazyCaptures> LazyCaptures for Vec<T> {
@@ -1121,6 +1177,10 @@ pub mod lazy {
                 false
             })
         }
+
+        fn dependencies(&self) -> Dependencies {
+            self.iter().map(|c| c.dependencies()).sum()
+        }
     }
 
     macro_rules! impl_tuple {
@@ -1160,6 +1220,14 @@ pub mod lazy {
                         false
                     })
                 }
+
+                fn dependencies(&self) -> Dependencies {
+                    #[allow(non_snake_case)]
+                    let ($($name,)+) = self;
+                    let mut deps = Dependencies::new();
+                    $(deps += $name.dependencies();)+
+                    deps
+                }
             }
         }
     }
@@ -1227,6 +1295,14 @@ pub mod lazy {
         fn eval_for_id_hint(&self) -> future::BoxFuture<bool> {
             future::BoxFuture::new(self.captures.eval_for_id_hint())
         }
+
+        fn dependencies(&self) -> Dependencies {
+            if USE_CAPS {
+                self.id.dependencies() + self.captures.dependencies()
+            } else {
+                self.id.dependencies()
+            }
+        }
     }
 }
 
@@ -1318,6 +1394,14 @@ impl Value {
         v.eval_id().await.id
     }
 
+    /// Get the dependencies from which the value's immediate identity is derived.
+    ///
+    /// This is intended for inspecting identities (e.g. to display a dependency tree). To get the
+    /// dependencies of the value's (evaluated) identity, call `eval_id` first.
+    pub fn dependencies(&self) -> Dependencies {
+        self.inner.data.dependencies()
+    }
+
     /// Get the value's referential identity, which is unique to a particular Value instance in
     /// memory.
     pub fn referential_id(&self) -> usize {
@@ -851,14 +851,7 @@ pub fn load(
     source: Source<&str>,
     ctx: &mut Context,
     lint: LintLevel,
-) -> Result<
-    (
-        Expr,
-        HashMap<CaptureKey, Expr>,
-        Vec<Source<std::string::String>>,
-    ),
-    Error,
-> {
+) -> Result<(Expr, HashMap<CaptureKey, Expr>, Vec<Source<LintMessage>>), Error> {
     load_ext(
         source,
         ctx,
@@ -873,14 +866,7 @@ fn load_ext(
     ctx: &mut Context,
     lint: LintLevel,
     #[cfg(test)] disable_scope_keys: bool,
-) -> Result<
-    (
-        Expr,
-        HashMap<CaptureKey, Expr>,
-        Vec<Source<std::string::String>>,
-    ),
-    Error,
-> {
+) -> Result<(Expr, HashMap<CaptureKey, Expr>, Vec<Source<LintMessage>>), Error> {
     let toks = tokenize::Tokens::from(source);
     let tree_parser = parse_tree::Parser::from(toks);
     let parser = parse::Parser::from(tree_parser);
@@ -944,11 +930,52 @@ impl Default for LintLevel {
     }
 }
 
+/// A lint message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LintMessage {
+    pub message: std::string::String,
+    /// Replacement text for the source of the message which fixes the lint, if any.
+    pub fix: Option<std::string::String>,
+    /// Whether the fix preserves the meaning of the script, so that it may be applied
+    /// automatically.
+    pub fix_is_safe: bool,
+}
+
+impl LintMessage {
+    pub fn new<S: Into<std::string::String>>(message: S) -> Self {
+        LintMessage {
+            message: message.into(),
+            fix: None,
+            fix_is_safe: false,
+        }
+    }
+
+    /// Set a fix which may change the meaning of the script.
+    pub fn with_fix<S: Into<std::string::String>>(mut self, fix: S) -> Self {
+        self.fix = Some(fix.into());
+        self.fix_is_safe = false;
+        self
+    }
+
+    /// Set a fix which preserves the meaning of the script.
+    pub fn with_safe_fix<S: Into<std::string::String>>(mut self, fix: S) -> Self {
+        self.fix = Some(fix.into());
+        self.fix_is_safe = true;
+        self
+    }
+}
+
+impl std::fmt::Display for LintMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.message.fmt(f)
+    }
+}
+
 struct Lint {
     level: LintLevel,
     unused_bindings: HashSet<Source<CaptureKey>>,
     ignore_string_binding_conflict: bool,
-    messages: Vec<Source<std::string::String>>,
+    messages: Vec<Source<LintMessage>>,
 }
 
 impl Lint {
@@ -1011,10 +1038,18 @@ impl<'a> ExpressionCompiler<'a> {
         self.disable_scope_keys = disable;
     }
 
-    #[allow(dead_code)]
     fn add_lint(&mut self, src: Source<()>, lint: &str) {
         if let Some(v) = &mut self.lint {
-            v.messages.push(src.with(lint.into()));
+            v.messages.push(src.with(LintMessage::new(lint)));
+        }
+    }
+
+    fn shadowed_binding(&mut self, src: Source<()>) {
+        if let Some(v) = &mut self.lint {
+            if v.level == LintLevel::Aggressive {
+                v.messages
+                    .push(src.with(LintMessage::new("binding shadows an existing binding")));
+            }
         }
     }
 
@@ -1045,20 +1080,29 @@ impl<'a> ExpressionCompiler<'a> {
                 && e.expr_type() == ExpressionType::String
                 && self.capture_mapping.get(e).is_some()
             {
-                v.messages.push(e.source().with(
-                    "string matches a binding in scope; did you mean to use the binding?".into(),
-                ));
+                let mut message = LintMessage::new(
+                    "string matches a binding in scope; did you mean to use the binding?",
+                );
+                if let Some(s) = e.value().as_ref::<String>() {
+                    if s.0
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+                    {
+                        message = message.with_fix(format!("${}", s.0));
+                    }
+                }
+                v.messages.push(e.source().with(message));
             }
         }
     }
 
-    pub fn lint_messages(&mut self) -> Vec<Source<std::string::String>> {
+    pub fn lint_messages(&mut self) -> Vec<Source<LintMessage>> {
         self.lint
             .take()
             .map(|lint| {
                 let mut messages = lint.messages;
                 for v in lint.unused_bindings {
-                    messages.push(v.with("unused binding".into()));
+                    messages.push(v.with(LintMessage::new("unused binding").with_safe_fix("_")));
                 }
                 messages
             })
@@ -1110,7 +1154,17 @@ impl<'a> ExpressionCompiler<'a> {
                 let ignore_unused_bindings = v.items.last()
                         .map(|e| match e { BlockItem::Expr(_) => false, _ => true })
                         .unwrap_or_default();
-                for i in &mut v.items {
+                let items = v.items.len();
+                for (n, i) in v.items.iter_mut().enumerate() {
+                    // Values of items other than the last are only evaluated, so constant values
+                    // have no effect.
+                    if let BlockItem::Expr(e) = i {
+                        if n + 1 < items && matches!(e.expr_type(),
+                            ExpressionType::Unit | ExpressionType::BindAny | ExpressionType::String | ExpressionType::Function)
+                        {
+                            self.add_lint(e.source(), "block item has no effect; its value is discarded");
+                        }
+                    }
                     match i {
                         BlockItem::Bind(key, value) => {
                             self.compile_captures(&mut *value, e_caps);
@@ -1163,6 +1217,9 @@ impl<'a> ExpressionCompiler<'a> {
             },
             Set(v) => {
                 if v.value.expr_type() == ExpressionType::String {
+                    if self.capture_mapping.get(v.value.value()).is_some() {
+                        self.shadowed_binding(src);
+                    }
                     let key = self.capture_context.key();
                     if let Some(scope_key) = self.capture_mapping.insert(v.value.value().clone(), key) {
                         v.scope_key = scope_key;
@@ -1611,12 +1668,54 @@ mod test {
             assert_no_lint_message("{:a,:b} = $c");
         }
 
+        #[test]
+        fn shadowed_binding() {
+            assert_lint_message_text(
+                "a = 1; f = fn :a -> $a; [$a,$f 2]",
+                "binding shadows an existing binding",
+            );
+            assert_no_lint_message("a = 1; f = fn :b -> [$a,$b]; $f 2");
+        }
+
+        #[test]
+        fn no_effect_block_item() {
+            assert_lint_message("a; $b");
+            assert_no_lint_message("$a; $b");
+        }
+
         #[test]
         fn string_binding_conflict() {
             assert_lint_message("fn :x -> x");
             assert_no_lint_message("fn :x :y -> { y, x = x:y }");
         }
 
+        #[test]
+        fn fixes() {
+            let mut ctx = super::super::Context::default();
+            let (_, _, lints) = super::super::load(
+                Source::missing("fn :x :y -> x"),
+                &mut ctx,
+                LintLevel::Aggressive,
+            )
+            .unwrap();
+            let mut fixes: Vec<_> = lints
+                .into_iter()
+                .filter_map(|l| {
+                    let l = l.unwrap();
+                    l.fix.map(|fix| (fix, l.fix_is_safe))
+                })
+                .collect();
+            fixes.sort();
+            assert_eq!(
+                fixes,
+                vec![
+                    ("$x".to_owned(), false),
+                    ("_".to_owned(), true),
+                    ("_".to_owned(), true)
+                ]
+            );
+        }
+
         fn assert_lint_message(s: &str) {
             let mut ctx = super::super::Context::default();
             let (_, _, lints) =
@@ -1625,6 +1724,14 @@ mod test {
             assert!(!lints.is_empty());
         }
 
+        fn assert_lint_message_text(s: &str, message: &str) {
+            let mut ctx = super::super::Context::default();
+            let (_, _, lints) =
+                super::super::load(Source::missing(s), &mut ctx, LintLevel::Aggressive).unwrap();
+            dbg!(&lints);
+            assert!(lints.iter().any(|l| l.message == message));
+        }
+
         fn assert_no_lint_message(s: &str) {
             let mut ctx = super::super::Context::default();
             let (_, _, lints) =
@@ -9,6 +9,11 @@ use ergo_runtime::{
     Context, Source, Value,
 };
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+mod site;
+
+pub use site::{summary, DocFormat};
 
 struct DocPathKey;
 
@@ -24,6 +29,8 @@ impl DynamicScopeKey for DocPathKey {
 struct DocPath {
     pub root: Source<PathBuf>,
     pub path: Source<PathBuf>,
+    pub format: DocFormat,
+    pub site: Arc<Mutex<site::Site>>,
 }
 
 impl DocPath {
@@ -35,9 +42,14 @@ impl DocPath {
         Self::get().map(|r| r.as_ref().clone())
     }
 
-    pub fn new(root: Source<PathBuf>) -> Self {
+    pub fn new(root: Source<PathBuf>, format: DocFormat) -> Self {
         let path = root.source().with(Default::default());
-        DocPath { root, path }
+        DocPath {
+            root,
+            path,
+            format,
+            site: Default::default(),
+        }
     }
 
     pub fn join(&mut self, p: Source<&std::path::Path>) {
@@ -54,37 +66,34 @@ impl DocPath {
     }
 }
 
-fn write_doc(path: &std::path::Path, md: &str) -> Result<(), String> {
-    use horrorshow::{helper::doctype, html, prelude::*};
-
-    struct Markdown<'a>(&'a str);
-
-    impl<'a> RenderOnce for Markdown<'a> {
-        fn render_once(self, tmpl: &mut TemplateBuffer) {
-            use pulldown_cmark::{html, Parser};
-            let p = Parser::new(self.0);
-            let mut s = String::new();
-            html::push_html(&mut s, p);
-            tmpl.write_raw(&s);
-        }
-    }
+/// The metadata key of doc categories, as set by the `std:doc` category attributes.
+const CATEGORY_KEY: &str = "std:doc:category";
 
-    let mut f = std::io::BufWriter::new(std::fs::File::create(path).map_err(|e| e.to_string())?);
-    let template = html! {
-        : doctype::HTML;
-        html {
-            head {
-                meta(charset = "UTF-8");
-                meta(name = "viewport", content = "width=device-width, initial-scale=1.0");
-                title: "Documentation";
-                link(rel = "stylesheet", href = "https://cdn.jsdelivr.net/npm/@exampledev/new.css@1.1.2/new.min.css");
-            }
-            body {
-                : Markdown(md);
+/// Get the doc category of a value.
+///
+/// If the value has no explicit category, the category is determined from its type (without
+/// evaluating it further).
+async fn doc_category(value: &Value) -> String {
+    let key: Value = types::String::from(CATEGORY_KEY).into();
+    let key = metadata::Runtime {
+        key: key.id().await,
+    };
+    if let Some(c) = value.get_metadata(&key) {
+        let mut c = c.owned();
+        if Context::eval(&mut c).await.is_ok() {
+            if let Some(s) = c.as_ref::<types::String>() {
+                return s.to_string();
             }
         }
-    };
-    template.write_to_io(&mut f).map_err(|e| e.to_string())
+    }
+    if value.is_type::<types::Unbound>() {
+        "Functions"
+    } else if value.is_type::<types::Type>() {
+        "Types"
+    } else {
+        "Values"
+    }
+    .into()
 }
 
 /// The doc function, supporting a number of indexed functions as well.
@@ -152,31 +161,77 @@ Evaluates to the doc Path, or Unset if no Path is present (documentation is not
         }
     };
 
+    let summary: Value = types::ergo_fn_value! {
+        /// Get the summary of documentation.
+        ///
+        /// Arguments: `(String :doc)`
+        ///
+        /// The summary is the first paragraph (where paragraphs are separated by two newlines)
+        /// which is not a heading, on a single line.
+        async fn summary(doc: types::String) -> Value {
+            types::String::from(site::summary(doc.as_ref().as_str())).into()
+        }
+    };
+
     let write: Value = types::ergo_fn_value! {
         /// Write documentation to the given path.
         ///
         /// Arguments: `(Into<Path> :path) :doc-value`
         ///
-        /// Returns the `Path` to the written documentation.
-        async fn write(path: _, value: _) -> Value {
+        /// Keyed Arguments:
+        /// * `String :format` - the output format, one of `html` (the default), `markdown`, or
+        ///   `mdbook`.
+        ///
+        /// The `html` format writes a static site, where each page has a sidebar of the pages
+        /// (grouped by doc category), a search box, syntax highlighting, and links for inline code
+        /// which names other pages (e.g. `std:fs:read`). Pages load their base stylesheet and
+        /// highlight.js from public CDNs. The `markdown` format writes the markdown
+        /// documentation of each page. The `mdbook` format writes markdown pages with titles and
+        /// links for inline code, and adds entries for the pages to the `SUMMARY.md` of the book
+        /// containing the path (under the existing entry of the written path). If there is no such
+        /// entry, the entries are written to `SUMMARY-entries.md` in the written path instead.
+        ///
+        /// The documentation is written within the directory at `path`, with the top-level page
+        /// written to `index.<ext>`. Returns the `Path` to the top-level page.
+        async fn write(path: _, value: _, (format): [types::String]) -> Value {
             let path_source = metadata::Source::get(&path);
             let mut doc_path = traits::into::<types::Path>(path).await?.into_owned().into_pathbuf();
 
-            if let Some(parent) = doc_path.parent() {
-                std::fs::create_dir_all(&parent)
-                    .add_primary_label(path_source.with("while creating directory from this value"))
-                    .add_note(format_args!("directory was {}", parent.display()))?;
-            }
+            let format = match format {
+                None => DocFormat::Html,
+                Some(format) => {
+                    let (format_source, format) = metadata::Source::extract(format).take();
+                    format
+                        .as_ref()
+                        .as_str()
+                        .parse::<DocFormat>()
+                        .add_primary_label(format_source.with(""))?
+                }
+            };
+
+            // The site (including any assets) is always written within the path, even if there
+            // are no child pages.
+            std::fs::create_dir_all(&doc_path)
+                .add_primary_label(path_source.with("while creating directory from this value"))
+                .add_note(format_args!("directory was {}", doc_path.display()))?;
+            let category = doc_category(&value).await;
+            let root = DocPath::new(path_source.with(doc_path.clone()), format);
+            let pages = root.site.clone();
             let doc = Context::fork(
-                    |ctx| DocPath::new(path_source.with(doc_path.clone())).context(ctx, ARGS_SOURCE),
+                    move |ctx| root.context(ctx, ARGS_SOURCE),
                     async move { Doc::get(&value).await }
                 ).await?;
 
-            if doc_path.is_dir() {
-                doc_path.push("index");
-            }
-            doc_path.set_extension("html");
-            write_doc(&doc_path, &doc)
+            let site_root = doc_path.clone();
+            doc_path.push("index");
+            doc_path.set_extension(format.extension());
+            let mut pages = pages.lock().unwrap();
+            pages.add(site::Page {
+                path: doc_path.clone(),
+                category,
+                content: doc,
+            });
+            pages.write(&site_root, format)
                 .add_primary_label(path_source.with("while writing to path from this value"))
                 .add_note(format_args!("path was {}", doc_path.display()))?;
             types::Path::from(doc_path).into()
@@ -212,6 +267,9 @@ Evaluates to the doc Path, or Unset if no Path is present (documentation is not
                         }
                     }
                     let old_doc_path = doc_path.current();
+                    let format = doc_path.format;
+                    let pages = doc_path.site.clone();
+                    let category = doc_category(&value).await;
                     doc_path.join(path_source.with(&rel_path));
                     let new_path = doc_path.current();
                     if let Some(parent) = new_path.parent() {
@@ -227,11 +285,13 @@ Evaluates to the doc Path, or Unset if no Path is present (documentation is not
                     if new_path.is_dir() {
                         rel_path.push("index");
                     }
-                    rel_path.set_extension("html");
-                    let output_file = old_doc_path.join(&rel_path);
-                    write_doc(&output_file, &doc)
-                        .add_primary_label(path_source.with("while writing to path from this value"))
-                        .add_note(format_args!("path was {}", output_file.display()))?;
+                    rel_path.set_extension(format.extension());
+                    // The page is written with the rest of the site once all pages are known.
+                    pages.lock().unwrap().add(site::Page {
+                        path: old_doc_path.join(&rel_path),
+                        category,
+                        content: doc.clone(),
+                    });
                     let mut m = types::Map(Default::default());
                     m.0.insert(types::String::from("path").into(), types::Path::from(rel_path).into());
                     m.0.insert(types::String::from("content").into(), types::String::from(doc).into());
@@ -255,6 +315,7 @@ Evaluates to the doc Path, or Unset if no Path is present (documentation is not
         //! ## Functions
         //! * `child` - Write documentation to a relative path.
         //! * `raw` - Get the raw documentation metadata for a value.
+        //! * `summary` - Get the summary of documentation.
         //! * `write` - Write documentation to the given output path.
         #![depends(const nsid!(ergo::doc))]
         let source = metadata::Source::get(&ARG);
@@ -278,6 +339,8 @@ Evaluates to the doc Path, or Unset if no Path is present (documentation is not
                     write
                 } else if s == "raw" {
                     raw
+                } else if s == "summary" {
+                    summary
                 } else {
                     metadata::Source::get(&ind).with("unknown index").into_error().into()
                 }
@@ -0,0 +1,745 @@
+//! Documentation site output.
+//!
+//! Pages are collected while documentation is generated, and written once all pages are known so
+//! that the output can have cross-links, a sidebar, a search index, or a book summary.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The format of written documentation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DocFormat {
+    /// A static html site.
+    Html,
+    /// Markdown files.
+    Markdown,
+    /// Markdown files for an mdbook, with entries added to the book summary.
+    Mdbook,
+}
+
+impl DocFormat {
+    /// The extension of written pages.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DocFormat::Html => "html",
+            DocFormat::Markdown | DocFormat::Mdbook => "md",
+        }
+    }
+}
+
+impl std::fmt::Display for DocFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            DocFormat::Html => "html",
+            DocFormat::Markdown => "markdown",
+            DocFormat::Mdbook => "mdbook",
+        })
+    }
+}
+
+impl std::str::FromStr for DocFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(DocFormat::Html),
+            "markdown" => Ok(DocFormat::Markdown),
+            "mdbook" => Ok(DocFormat::Mdbook),
+            _ => Err(format!("invalid doc format: {}", s)),
+        }
+    }
+}
+
+/// A documentation page.
+#[derive(Debug)]
+pub struct Page {
+    /// The output path of the page.
+    pub path: PathBuf,
+    /// The doc category of the documented value.
+    pub category: String,
+    /// The markdown content.
+    pub content: String,
+}
+
+/// The pages of a documentation site.
+#[derive(Debug, Default)]
+pub struct Site {
+    pages: Vec<Page>,
+}
+
+/// Categories which are listed first in the sidebar, in order.
+const ORDERED_CATEGORIES: &[&str] = &["Types", "Modules", "Functions", "Values"];
+
+/// The file to which summary entries are written when they cannot be added to a `SUMMARY.md`.
+const SUMMARY_ENTRIES: &str = "SUMMARY-entries.md";
+
+/// The syntax highlighting definition of the language.
+///
+/// This is written alongside the pages as `ergo.js`, and registers the language with highlight.js.
+const HIGHLIGHT_JS: &str = include_str!("../../../../extra/highlight.js/ergo.js");
+
+/// The CDN from which pages load highlight.js (and its default style).
+///
+/// Only the language definition is written with the site, so pages are only highlighted when the
+/// CDN is reachable.
+const HIGHLIGHT_JS_CDN: &str = "https://cdnjs.cloudflare.com/ajax/libs/highlight.js/10.7.3";
+
+/// The stylesheet loaded by pages (from a CDN), which `STYLE` extends.
+const STYLE_CDN: &str = "https://cdn.jsdelivr.net/npm/@exampledev/new.css@1.1.2/new.min.css";
+
+const STYLE: &str = "\
+body { display: grid; grid-template-columns: 18rem minmax(0, 1fr); max-width: none; gap: 2rem; }
+nav { position: sticky; top: 0; max-height: 100vh; overflow-y: auto; font-size: 0.9em; }
+nav ul { list-style: none; padding-left: 1rem; margin: 0; }
+nav .category { font-weight: bold; margin: 0.5rem 0 0 1rem; }
+nav .current { font-weight: bold; }
+#search { width: 100%; }
+#search-results { padding-left: 1rem; }
+";
+
+const SEARCH_JS: &str = r#"function ergoSearch(root) {
+    var input = document.getElementById("search");
+    var results = document.getElementById("search-results");
+    input.addEventListener("input", function() {
+        var query = input.value.toLowerCase();
+        results.innerHTML = "";
+        if (!query) {
+            return;
+        }
+        searchIndex.filter(function(e) {
+            return e.name.toLowerCase().indexOf(query) >= 0 ||
+                e.summary.toLowerCase().indexOf(query) >= 0;
+        }).slice(0, 50).forEach(function(e) {
+            var item = document.createElement("li");
+            var link = document.createElement("a");
+            link.href = root + e.path;
+            link.textContent = e.name;
+            item.appendChild(link);
+            item.appendChild(document.createTextNode(" " + e.summary));
+            results.appendChild(item);
+        });
+    });
+}
+"#;
+
+/// Information about a page relative to the site root.
+struct PageInfo<'a> {
+    page: &'a Page,
+    /// The path relative to the site root.
+    rel: String,
+    /// The names of the indices leading to the page.
+    names: Vec<String>,
+}
+
+impl PageInfo<'_> {
+    fn name(&self) -> String {
+        self.names.join(":")
+    }
+
+    /// The relative link from this page to the given path (relative to the site root).
+    fn link(&self, rel: &str) -> String {
+        format!("{}{}", self.root(), rel)
+    }
+
+    /// The relative path from this page to the site root.
+    fn root(&self) -> String {
+        "../".repeat(self.rel.matches('/').count())
+    }
+}
+
+impl Site {
+    /// Add a page to the site.
+    pub fn add(&mut self, page: Page) {
+        self.pages.push(page);
+    }
+
+    /// Write all pages, with the site root being the given directory.
+    pub fn write(&self, root: &Path, format: DocFormat) -> std::io::Result<()> {
+        match format {
+            DocFormat::Markdown => {
+                for page in &self.pages {
+                    std::fs::write(&page.path, &page.content)?;
+                }
+                Ok(())
+            }
+            DocFormat::Html => self.write_html(root),
+            DocFormat::Mdbook => self.write_mdbook(root),
+        }
+    }
+
+    /// Get the information of all pages, relative to the site root.
+    fn page_infos(&self, root: &Path) -> Vec<PageInfo> {
+        self.pages
+            .iter()
+            .map(|page| {
+                let rel_path = page.path.strip_prefix(root).unwrap_or(&page.path);
+                let mut names: Vec<_> = rel_path
+                    .with_extension("")
+                    .iter()
+                    .map(|c| c.to_string_lossy().replace("__slash__", "/"))
+                    .collect();
+                if names.last().map(|s| s.as_str()) == Some("index") {
+                    names.pop();
+                }
+                PageInfo {
+                    page,
+                    rel: rel_path
+                        .iter()
+                        .map(|c| c.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                    names,
+                }
+            })
+            .collect()
+    }
+
+    fn write_html(&self, root: &Path) -> std::io::Result<()> {
+        let pages = self.page_infos(root);
+        let by_name: HashMap<String, &PageInfo> = pages.iter().map(|p| (p.name(), p)).collect();
+
+        for page in &pages {
+            let title = if page.names.is_empty() {
+                "Documentation".into()
+            } else {
+                page.name()
+            };
+            let content = render_markdown(&page.page.content, |code| {
+                let name = resolve(&by_name, code)?;
+                (name.rel != page.rel).then(|| page.link(&name.rel))
+            });
+            let sidebar = sidebar(&pages, page);
+            write_page(&page.page.path, &title, &page.root(), &sidebar, &content)?;
+        }
+
+        let mut index = String::from("var searchIndex = [\n");
+        for page in &pages {
+            index.push_str(&format!(
+                "{{\"name\": {}, \"path\": {}, \"summary\": {}}},\n",
+                json_string(&page.name()),
+                json_string(&page.rel),
+                json_string(&summary(&page.page.content))
+            ));
+        }
+        index.push_str("];\n");
+
+        std::fs::write(root.join("search-index.js"), index)?;
+        std::fs::write(root.join("search.js"), SEARCH_JS)?;
+        std::fs::write(root.join("ergo.js"), HIGHLIGHT_JS)?;
+        std::fs::write(root.join("style.css"), STYLE)
+    }
+
+    fn write_mdbook(&self, root: &Path) -> std::io::Result<()> {
+        let pages = self.page_infos(root);
+        let by_name: HashMap<String, &PageInfo> = pages.iter().map(|p| (p.name(), p)).collect();
+
+        // Page titles include the name of the root directory (e.g. `std`).
+        let root_name = root
+            .file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let title = |page: &PageInfo| {
+            std::iter::once(root_name.as_str())
+                .chain(page.names.iter().map(|s| s.as_str()))
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+                .join(":")
+        };
+
+        for page in &pages {
+            let content = link_markdown(&page.page.content, |code| {
+                let name = resolve(&by_name, code)?;
+                (name.rel != page.rel).then(|| page.link(&name.rel))
+            });
+            std::fs::write(
+                &page.page.path,
+                format!("# `{}`\n\n{}", title(page), content),
+            )?;
+        }
+
+        update_summary(root, &pages, &root_name)
+    }
+}
+
+/// Group pages by their parent page names.
+fn children<'a, 'b>(pages: &'b [PageInfo<'a>]) -> HashMap<&'b [String], Vec<&'b PageInfo<'a>>> {
+    let mut children: HashMap<&[String], Vec<&PageInfo>> = HashMap::new();
+    for p in pages {
+        if let Some((_, parent)) = p.names.split_last() {
+            children.entry(parent).or_default().push(p);
+        }
+    }
+    children
+}
+
+/// The sort key of a doc category.
+fn category_order(category: &str) -> (usize, &str) {
+    (
+        ORDERED_CATEGORIES
+            .iter()
+            .position(|o| *o == category)
+            .unwrap_or(ORDERED_CATEGORIES.len()),
+        category,
+    )
+}
+
+/// Add entries for the pages to the mdbook summary.
+///
+/// The `SUMMARY.md` file in the site root or its nearest ancestor is updated, replacing any
+/// entries nested under the entry of the root page with entries for the pages. If there is no
+/// such file or entry, the entries are written to `SUMMARY_ENTRIES` in the site root (to be added
+/// to the book summary manually), so that an existing `SUMMARY.md` is never overwritten.
+fn update_summary(root: &Path, pages: &[PageInfo], root_title: &str) -> std::io::Result<()> {
+    let children = children(pages);
+    let root_page = pages.iter().find(|p| p.names.is_empty());
+
+    let summary = root
+        .ancestors()
+        .map(|dir| dir.join("SUMMARY.md"))
+        .find(|p| p.exists());
+
+    if let (Some(summary_path), Some(root_page)) = (summary, root_page) {
+        let book_dir = summary_path.parent().unwrap();
+        let prefix = root
+            .strip_prefix(book_dir)
+            .unwrap()
+            .iter()
+            .map(|c| format!("{}/", c.to_string_lossy()))
+            .collect::<String>();
+        let content = std::fs::read_to_string(&summary_path)?;
+        let mut lines: Vec<_> = content.lines().map(|l| l.to_owned()).collect();
+        let target = format!("]({}{})", prefix, root_page.rel);
+        if let Some(i) = lines.iter().position(|l| l.contains(&target)) {
+            let indent = lines[i].len() - lines[i].trim_start().len();
+            let end = lines[i + 1..]
+                .iter()
+                .position(|l| l.len() - l.trim_start().len() <= indent || l.trim().is_empty())
+                .map(|n| i + 1 + n)
+                .unwrap_or(lines.len());
+            let mut entries = Vec::new();
+            summary_entries(&mut entries, &children, &[], indent + 2, &prefix);
+            lines.splice(i + 1..end, entries);
+            let mut content = lines.join("\n");
+            content.push('\n');
+            return std::fs::write(&summary_path, content);
+        }
+    }
+
+    let mut entries = vec![format!(
+        "- [{}]({})",
+        root_title,
+        root_page.map(|p| p.rel.as_str()).unwrap_or_default()
+    )];
+    summary_entries(&mut entries, &children, &[], 2, "");
+    entries.push(String::new());
+    std::fs::write(root.join(SUMMARY_ENTRIES), entries.join("\n"))
+}
+
+fn summary_entries(
+    entries: &mut Vec<String>,
+    children: &HashMap<&[String], Vec<&PageInfo>>,
+    parent: &[String],
+    indent: usize,
+    prefix: &str,
+) {
+    let mut pages = match children.get(parent) {
+        Some(pages) => pages.clone(),
+        None => return,
+    };
+    pages.sort_by(|a, b| {
+        (category_order(&a.page.category), &a.names)
+            .cmp(&(category_order(&b.page.category), &b.names))
+    });
+    for p in pages {
+        entries.push(format!(
+            "{:indent$}- [{}]({}{})",
+            "",
+            p.names.last().unwrap(),
+            prefix,
+            p.rel,
+            indent = indent
+        ));
+        summary_entries(entries, children, &p.names, indent + 2, prefix);
+    }
+}
+
+/// Resolve an inline code reference (such as `std:fs:read`) to a page.
+///
+/// The reference may include the name of the documented root value (e.g. `std`).
+fn resolve<'a>(
+    by_name: &HashMap<String, &'a PageInfo<'a>>,
+    code: &str,
+) -> Option<&'a PageInfo<'a>> {
+    if code.is_empty()
+        || !code
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ':' || c == '/')
+    {
+        return None;
+    }
+    by_name
+        .get(code)
+        .or_else(|| code.split_once(':').and_then(|(_, rest)| by_name.get(rest)))
+        .copied()
+}
+
+/// Render markdown as html, linking inline code for which `link` returns a target.
+fn render_markdown<F: Fn(&str) -> Option<String>>(md: &str, link: F) -> String {
+    use pulldown_cmark::{escape::escape_html, html, Event, Parser, Tag};
+    let mut in_link = 0;
+    let events = Parser::new(md).map(|e| match e {
+        Event::Start(Tag::Link(..)) => {
+            in_link += 1;
+            e
+        }
+        Event::End(Tag::Link(..)) => {
+            in_link -= 1;
+            e
+        }
+        Event::Code(code) if in_link == 0 => match link(&code) {
+            Some(target) => {
+                let mut s = String::from("<a href=\"");
+                escape_html(&mut s, &target).unwrap();
+                s.push_str("\"><code>");
+                escape_html(&mut s, &code).unwrap();
+                s.push_str("</code></a>");
+                Event::Html(s.into())
+            }
+            None => Event::Code(code),
+        },
+        e => e,
+    });
+    let mut s = String::new();
+    html::push_html(&mut s, events);
+    s
+}
+
+/// Link inline code in markdown for which `link` returns a target.
+fn link_markdown<F: Fn(&str) -> Option<String>>(md: &str, link: F) -> String {
+    use pulldown_cmark::{Event, Parser, Tag};
+    let mut in_link = 0;
+    let mut ret = String::new();
+    let mut last = 0;
+    for (e, range) in Parser::new(md).into_offset_iter() {
+        match e {
+            Event::Start(Tag::Link(..)) => in_link += 1,
+            Event::End(Tag::Link(..)) => in_link -= 1,
+            Event::Code(code) if in_link == 0 => {
+                if let Some(target) = link(&code) {
+                    ret.push_str(&md[last..range.start]);
+                    ret.push('[');
+                    ret.push_str(&md[range.clone()]);
+                    ret.push_str("](");
+                    ret.push_str(&target);
+                    ret.push(')');
+                    last = range.end;
+                }
+            }
+            _ => (),
+        }
+    }
+    ret.push_str(&md[last..]);
+    ret
+}
+
+/// Render the sidebar for the given page.
+///
+/// The sidebar has the pages of each module grouped by doc category.
+fn sidebar(pages: &[PageInfo], current: &PageInfo) -> String {
+    let children = children(pages);
+
+    let mut s = String::new();
+    if let Some(root) = pages.iter().find(|p| p.names.is_empty()) {
+        s.push_str(&format!(
+            "<p><a href=\"{}\">Documentation</a></p>",
+            current.link(&root.rel)
+        ));
+    }
+    sidebar_children(&mut s, &children, &[], current);
+    s
+}
+
+fn sidebar_children(
+    s: &mut String,
+    children: &HashMap<&[String], Vec<&PageInfo>>,
+    parent: &[String],
+    current: &PageInfo,
+) {
+    let entries = match children.get(parent) {
+        Some(entries) => entries,
+        None => return,
+    };
+    let mut categories: Vec<&str> = entries.iter().map(|p| p.page.category.as_str()).collect();
+    categories.sort_by_key(|c| category_order(c));
+    categories.dedup();
+
+    for category in categories {
+        let mut entries: Vec<_> = entries
+            .iter()
+            .filter(|p| p.page.category == category)
+            .collect();
+        entries.sort_by(|a, b| a.names.cmp(&b.names));
+        s.push_str(&format!(
+            "<p class=\"category\">{}</p><ul>",
+            escape(category)
+        ));
+        for p in entries {
+            let name = escape(p.names.last().unwrap());
+            let class = if p.rel == current.rel {
+                " class=\"current\""
+            } else {
+                ""
+            };
+            let link = format!("<a href=\"{}\"{}>{}</a>", current.link(&p.rel), class, name);
+            if children.contains_key(p.names.as_slice()) {
+                let open = if current.names.starts_with(&p.names) {
+                    " open"
+                } else {
+                    ""
+                };
+                s.push_str(&format!("<li><details{}><summary>{}</summary>", open, link));
+                sidebar_children(s, children, &p.names, current);
+                s.push_str("</details></li>");
+            } else {
+                s.push_str(&format!("<li>{}</li>", link));
+            }
+        }
+        s.push_str("</ul>");
+    }
+}
+
+fn write_page(
+    path: &Path,
+    page_title: &str,
+    root: &str,
+    sidebar: &str,
+    content: &str,
+) -> std::io::Result<()> {
+    use horrorshow::{helper::doctype, html, prelude::*};
+
+    let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let template = html! {
+        : doctype::HTML;
+        html {
+            head {
+                meta(charset = "UTF-8");
+                meta(name = "viewport", content = "width=device-width, initial-scale=1.0");
+                title: page_title;
+                link(rel = "stylesheet", href = STYLE_CDN);
+                link(rel = "stylesheet", href = format!("{}/styles/default.min.css", HIGHLIGHT_JS_CDN));
+                link(rel = "stylesheet", href = format!("{}style.css", root));
+            }
+            body {
+                nav {
+                    input(id = "search", placeholder = "Search");
+                    ul(id = "search-results");
+                    : Raw(sidebar);
+                }
+                main {
+                    : Raw(content);
+                }
+                script(src = format!("{}/highlight.min.js", HIGHLIGHT_JS_CDN));
+                script(src = format!("{}ergo.js", root));
+                script(src = format!("{}search-index.js", root));
+                script(src = format!("{}search.js", root));
+                script: Raw(format!("ergoSearch({});", json_string(root)));
+            }
+        }
+    };
+    template
+        .write_to_io(&mut f)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+}
+
+/// Get the summary of documentation.
+///
+/// The summary is the first paragraph (where paragraphs are separated by two newlines) which is
+/// not a heading, on a single line.
+pub fn summary(md: &str) -> String {
+    let paragraph = md
+        .split("\n\n")
+        .map(|p| p.trim())
+        .find(|p| !p.is_empty() && !p.starts_with('#'))
+        .unwrap_or_default();
+    paragraph.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn escape(s: &str) -> String {
+    let mut ret = String::new();
+    pulldown_cmark::escape::escape_html(&mut ret, s).unwrap();
+    ret
+}
+
+fn json_string(s: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            // Avoid closing the script element when inlined.
+            '<' => ret.push_str("\\u003c"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn site(root: &Path, pages: &[&str]) -> Site {
+        let mut site = Site::default();
+        for p in pages {
+            site.add(Page {
+                path: root.join(p),
+                category: "Values".into(),
+                content: String::new(),
+            });
+        }
+        site
+    }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a \"b\" \\c"), r#""a \"b\" \\c""#);
+        assert_eq!(json_string("a\nb\tc"), r#""a\nb\u0009c""#);
+        assert_eq!(json_string("</script>"), r#""\u003c/script>""#);
+        assert_eq!(json_string("ünïcode"), "\"ünïcode\"");
+    }
+
+    #[test]
+    fn summaries() {
+        assert_eq!(summary(""), "");
+        assert_eq!(summary("First\nparagraph.\n\nSecond."), "First paragraph.");
+        assert_eq!(
+            summary("\n\n## Heading\n\n  The   summary.  "),
+            "The summary."
+        );
+        assert_eq!(summary("## Only a heading"), "");
+    }
+
+    #[test]
+    fn resolve_names() {
+        let root = Path::new("/doc");
+        let site = site(
+            root,
+            &[
+                "index.html",
+                "fs/index.html",
+                "fs/read.html",
+                "a__slash__b.html",
+            ],
+        );
+        let pages = site.page_infos(root);
+        let by_name: HashMap<String, &PageInfo> = pages.iter().map(|p| (p.name(), p)).collect();
+        let rel = |code| resolve(&by_name, code).map(|p| p.rel.as_str());
+
+        assert_eq!(rel("fs"), Some("fs/index.html"));
+        assert_eq!(rel("fs:read"), Some("fs/read.html"));
+        // The name of the documented root value may be included.
+        assert_eq!(rel("std:fs:read"), Some("fs/read.html"));
+        assert_eq!(rel("a/b"), Some("a__slash__b.html"));
+        assert_eq!(rel("fs:write"), None);
+        assert_eq!(rel("fs read"), None);
+        assert_eq!(rel(""), None);
+    }
+
+    #[test]
+    fn render_markdown_escaping() {
+        let link = |code: &str| (code == "a<b").then(|| "x\"y.html".to_owned());
+        assert_eq!(
+            render_markdown("See `a<b` and `c<d`.", link),
+            "<p>See <a href=\"x&quot;y.html\"><code>a&lt;b</code></a> and <code>c&lt;d</code>.</p>\n"
+        );
+        // Code within links is not linked.
+        assert_eq!(
+            render_markdown("[`a<b`](other.html)", link),
+            "<p><a href=\"other.html\"><code>a&lt;b</code></a></p>\n"
+        );
+    }
+
+    /// Write an mdbook site to `book/reference/std`, returning the book directory.
+    fn write_mdbook(name: &str, summary: &str) -> PathBuf {
+        let book = std::env::temp_dir().join(format!("ergo-doc-{}-{}", name, std::process::id()));
+        drop(std::fs::remove_dir_all(&book));
+        let root = book.join("reference").join("std");
+        std::fs::create_dir_all(root.join("fs")).unwrap();
+        std::fs::write(book.join("SUMMARY.md"), summary).unwrap();
+
+        let mut site = Site::default();
+        for (path, category) in [
+            ("index.md", "Modules"),
+            ("value.md", "Values"),
+            ("fs/read.md", "Functions"),
+            ("fs/index.md", "Modules"),
+            ("Path.md", "Types"),
+        ] {
+            site.add(Page {
+                path: root.join(path),
+                category: category.into(),
+                content: "Docs.\n\nSee `fs:read` and `std:value`.".into(),
+            });
+        }
+        site.write(&root, DocFormat::Mdbook).unwrap();
+        book
+    }
+
+    #[test]
+    fn mdbook_summary() {
+        let book = write_mdbook(
+            "summary",
+            "# Summary\n\n- [Intro](intro.md)\n- [Std](reference/std/index.md)\n  - [old](reference/std/old.md)\n- [After](after.md)\n",
+        );
+        let root = book.join("reference/std");
+        assert_eq!(
+            std::fs::read_to_string(book.join("SUMMARY.md")).unwrap(),
+            "# Summary
+
+- [Intro](intro.md)
+- [Std](reference/std/index.md)
+  - [Path](reference/std/Path.md)
+  - [fs](reference/std/fs/index.md)
+    - [read](reference/std/fs/read.md)
+  - [value](reference/std/value.md)
+- [After](after.md)
+"
+        );
+        assert!(!root.join(SUMMARY_ENTRIES).exists());
+        assert_eq!(
+            std::fs::read_to_string(root.join("fs/read.md")).unwrap(),
+            "# `std:fs:read`\n\nDocs.\n\nSee `fs:read` and [`std:value`](../value.md)."
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("index.md")).unwrap(),
+            "# `std`\n\nDocs.\n\nSee [`fs:read`](fs/read.md) and [`std:value`](value.md)."
+        );
+        drop(std::fs::remove_dir_all(&book));
+    }
+
+    #[test]
+    fn mdbook_summary_unpatched() {
+        // A summary without an entry for the site root is never overwritten.
+        let summary = "# Summary\n\n- [Intro](intro.md)\n";
+        let book = write_mdbook("unpatched", summary);
+        let root = book.join("reference/std");
+        assert_eq!(
+            std::fs::read_to_string(book.join("SUMMARY.md")).unwrap(),
+            summary
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join(SUMMARY_ENTRIES)).unwrap(),
+            "- [std](index.md)
+  - [Path](Path.md)
+  - [fs](fs/index.md)
+    - [read](fs/read.md)
+  - [value](value.md)
+"
+        );
+        drop(std::fs::remove_dir_all(&book));
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::constants::{DIR_NAME, EXTENSION, PLUGIN_ENTRY, WORKSPACE_NAME};
 use ergo_runtime::abi_stable::external_types::RMutex;
+use ergo_runtime::context::Capabilities;
 use ergo_runtime::{
     error::{DiagnosticInfo, RResult},
     metadata, nsid, traits,
@@ -9,39 +10,100 @@ use ergo_runtime::{
     types, Context, Source, Value,
 };
 use libloading as dl;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct LoadData {
     // The load cache `Value` is always a dynamic value which (when evaluated once) will do the
-    // actual loading of the script/plugin to take advantage of evaluation caching.
-    pub load_cache: Arc<RMutex<HashMap<PathBuf, Value>>>,
+    // actual loading of the script/plugin to take advantage of evaluation caching. Scripts are
+    // loaded separately for each set of restricted capabilities.
+    pub load_cache: Arc<RMutex<HashMap<(PathBuf, Option<Capabilities>), Value>>>,
+    // The scripts which loaded each script (including as `std` or `workspace`), used to
+    // invalidate cached loads when scripts change.
+    dependents: Arc<RMutex<HashMap<PathBuf, HashSet<PathBuf>>>>,
+    // The plugins which have been loaded (which cannot be reloaded).
+    plugins: Arc<RMutex<HashSet<PathBuf>>>,
     pub load_path: Arc<Vec<PathBuf>>,
     top_level_env: Arc<RMutex<HashMap<String, Value>>>,
     pub ast_context: Arc<RMutex<crate::ast::Context>>,
     pub lint: Arc<RMutex<crate::ast::LintLevel>>,
     pub backtrace: Arc<std::sync::atomic::AtomicBool>,
+    debugger: Arc<RMutex<Option<crate::debug::Debugger>>>,
 }
 
 impl LoadData {
     fn new(load_path: Vec<PathBuf>) -> Self {
         LoadData {
             load_cache: Arc::new(RMutex::new(HashMap::default())),
+            dependents: Arc::new(RMutex::new(HashMap::default())),
+            plugins: Arc::new(RMutex::new(HashSet::default())),
             load_path: Arc::new(load_path),
             top_level_env: Arc::new(RMutex::new(Default::default())),
             ast_context: Arc::new(RMutex::new(Default::default())),
             lint: Arc::new(RMutex::new(Default::default())),
             backtrace: Arc::new(false.into()),
+            debugger: Arc::new(RMutex::new(None)),
         }
     }
 
     /// Reset the inner state of the load data.
     pub fn reset(&self) {
         *self.load_cache.lock() = Default::default();
+        *self.dependents.lock() = Default::default();
+        *self.plugins.lock() = Default::default();
         *self.top_level_env.lock() = Default::default();
         *self.ast_context.lock() = Default::default();
+        if let Some(d) = self.debugger.lock().take() {
+            d.set_load_data(None);
+        }
+    }
+
+    /// Remove cached loads which may be affected by changes to the given (canonical) paths.
+    ///
+    /// If all changed paths are loaded scripts, the loads of those scripts and of the scripts
+    /// which (transitively) loaded them are removed. Otherwise any loaded script may be affected
+    /// (as scripts may read other files), so all loads are removed.
+    ///
+    /// Returns false if any changed path is a loaded plugin, as plugins cannot be reloaded.
+    pub fn invalidate(&self, changed: &[PathBuf]) -> bool {
+        if changed.iter().any(|p| self.plugins.lock().contains(p)) {
+            return false;
+        }
+
+        let mut cache = self.load_cache.lock();
+        let mut dependents = self.dependents.lock();
+        if !changed
+            .iter()
+            .all(|p| cache.keys().any(|(path, _)| path == p))
+        {
+            cache.clear();
+            dependents.clear();
+            return true;
+        }
+
+        let mut invalid = HashSet::new();
+        let mut pending = changed.to_vec();
+        while let Some(path) = pending.pop() {
+            if let Some(d) = dependents.remove(&path) {
+                pending.extend(d);
+            }
+            invalid.insert(path);
+        }
+        cache.retain(|(path, _), _| !invalid.contains(path));
+        true
+    }
+
+    /// Record that the script at `dependent` (if any) loaded the script at `path`.
+    fn add_dependent(&self, path: &Path, dependent: Option<PathBuf>) {
+        if let (Ok(path), Some(dependent)) = (path.canonicalize(), dependent) {
+            self.dependents
+                .lock()
+                .entry(path)
+                .or_default()
+                .insert(dependent);
+        }
     }
 
     /// Set the top-level environment used when loading scripts.
@@ -70,6 +132,19 @@ impl LoadData {
             .store(backtrace, std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// Get the debugger used when evaluating scripts.
+    pub fn debugger(&self) -> Option<crate::debug::Debugger> {
+        self.debugger.lock().clone()
+    }
+
+    /// Set the debugger used when evaluating scripts, returning the prior debugger.
+    pub fn set_debugger(
+        &self,
+        debugger: Option<crate::debug::Debugger>,
+    ) -> Option<crate::debug::Debugger> {
+        std::mem::replace(&mut *self.debugger.lock(), debugger)
+    }
+
     /// Resolve a path to the full script path, based on the load path.
     ///
     /// If resolution succeeds, the returned path will be to a file (not directory).
@@ -89,10 +164,12 @@ impl LoadData {
     /// Create the top-level env for the given script file.
     pub fn script_top_level_env(&self, script_file: Option<&Path>) -> HashMap<String, Value> {
         let mut env = self.top_level_env.lock().clone();
+        let script = script_file.map(Path::to_path_buf);
 
         // Add `std`, which will act like `ergo std` relative to the path.
         {
             let ld = self.clone();
+            let script = script.clone();
             let working_dir = script_file.and_then(|p| p.parent()).map(|p| p.to_owned());
             let std = ergo_runtime::lazy_value! {
                 //! Get the value as if `ergo std` were run.
@@ -114,6 +191,7 @@ impl LoadData {
                     }
                 };
 
+                ld.add_dependent(&path, script);
                 ld.load_script(&path).await
             };
             env.insert("std".into(), std);
@@ -177,6 +255,7 @@ impl LoadData {
                 .set_message("no ancestor workspace found")
                 .add_note(format_args!("for path {}", path_basis.display()))?;
 
+                ld.add_dependent(&path, script);
                 ld.load_script(&path).await
             };
             env.insert("workspace".into(), workspace);
@@ -187,22 +266,55 @@ impl LoadData {
 
     /// Load a script at the given path.
     ///
-    /// The path should already be verified as an existing file.
+    /// The path should already be verified as an existing file. Scripts are loaded with the
+    /// capabilities of the current context.
     pub async fn load_script(&self, path: &Path) -> Value {
+        let capabilities = Context::with(|ctx| ctx.capabilities.clone());
+        self.load_script_with(path, capabilities).await
+    }
+
+    /// Load a script at the given path, restricting evaluation of the script to the given
+    /// capabilities.
+    ///
+    /// The path should already be verified as an existing file. The capabilities of the current
+    /// context must allow reading a script or loading a plugin. Plugins are not restricted by
+    /// capabilities once loaded (though the standard library checks the capabilities of the
+    /// context in which functions are called).
+    pub async fn load_script_with(&self, path: &Path, capabilities: Capabilities) -> Value {
         debug_assert!(path.is_file());
         let path = path.canonicalize().unwrap(); // unwrap because is_file() should guarantee that canonicalize will succeed.
 
+        let plugin = is_plugin(&path);
+        let permitted = Context::with(|ctx| {
+            if plugin {
+                ctx.capabilities.check_plugin(&path)
+            } else {
+                ctx.capabilities.check_read(&path)
+            }
+        })
+        .add_note("not permitted by the current capabilities")
+        .set_kind("not-permitted");
+        if let Err(e) = permitted {
+            return ergo_runtime::Error::from(e).into();
+        }
+
+        Context::global().input_paths().add(&path);
+        if plugin {
+            self.plugins.lock().insert(path.clone());
+        }
+        let capabilities = (!plugin && !capabilities.is_unrestricted()).then(|| capabilities);
+
         let mut loaded = self
             .load_cache
             .lock()
-            .entry(path.clone())
+            .entry((path.clone(), capabilities.clone()))
             .or_insert_with(|| {
                 let me = self.clone();
                 ergo_runtime::lazy_value! {
-                    #![depends(const nsid!(load), path)]
+                    #![depends(const nsid!(load), path, capabilities)]
                     let sources = Context::global().diagnostic_sources();
 
-                    if !is_plugin(&path) {
+                    if !plugin {
                         let source = Source::new(sources
                             .add_file(path.clone())
                             .map_err(|e| {
@@ -230,6 +342,8 @@ impl LoadData {
                                 if me.backtrace() {
                                     s.enable_backtrace();
                                 }
+                                s.debugger(me.debugger());
+                                s.capabilities(capabilities.clone());
                                 s.evaluate().await
                             }
                         }
@@ -304,25 +418,56 @@ impl LoadFunctions {
             ///       that path is used and step (2) is repeated.
             ///
             /// If the directory-resolved script exists as a file, it is loaded.
+            ///
+            /// ## Capabilities
+            /// Keyed Arguments:
+            /// * `Map :capabilities` - restrict the capabilities of the loaded script. The map may
+            ///   contain the following keys, each with an Array value:
+            ///   * `exec` - the programs (`String`) which may be executed,
+            ///   * `read` - the paths (`Into<Path>`) which may be read (including descendants),
+            ///   * `write` - the paths (`Into<Path>`) which may be written (including descendants),
+            ///   * `net` - the hosts (`String`, optionally with `:port`) which may be accessed,
+            ///   * `plugin` - the paths (`Into<Path>`) of plugins which may be loaded (including
+            ///     descendants).
+            ///
+            ///   Omitted keys allow nothing. Relative paths are relative to the directory of the
+            ///   loaded script, so `~capabilities={read = [.], write = [.]}` limits the script to its
+            ///   own directory. Capabilities can only be restricted: the loaded script never has
+            ///   capabilities that the caller does not have. Scripts loaded by a restricted script
+            ///   are also restricted.
             #[eval_for_id]
-            async fn load(mut path: _) -> Value {
+            async fn load(mut path: _, (capabilities): [types::Map]) -> Value {
                 Context::eval(&mut path).await?;
                 let target_source = metadata::Source::get(&path);
                 let target = traits::into::<types::Path>(path).await?.into_owned().into_pathbuf();
 
-                let working_dir = Context::source_path(&ARGS_SOURCE);
-                let working_dir = working_dir.as_ref().and_then(|p| p.parent());
+                let caller = Context::source_path(&ARGS_SOURCE);
+                let working_dir = caller.as_ref().and_then(|p| p.parent());
 
                 // Try to find target in the load path.
                 let target = match ld.resolve_script_path(working_dir, &target) {
                     Some(path) => path,
                     None => {
-                        Err(target_source.with(format!("could not resolve script path: {}", target.display())).into_error())?
+                        let mut e = ergo_runtime::error::Diagnostic::from(format!("could not resolve script path: {}", target.display()));
+                        if Context::global().env.dry_run() {
+                            // Scripts retrieved with side effects (like `std:load-remote`) will not exist.
+                            e = e
+                                .add_note("in dry-run mode, files which would be created by side effects (such as downloaded archives) do not exist")
+                                .set_kind("dry-run");
+                        }
+                        Err(target_source.with(e).into_error())?
                     }
                 };
 
+                let mut allowed = Context::with(|ctx| ctx.capabilities.clone());
+                if let Some(caps) = capabilities {
+                    let dir = target.parent().unwrap_or(&target).to_owned();
+                    allowed = allowed.restrict(&parse_capabilities(caps, &dir).await?);
+                }
+
                 // Load if some module was found.
-                ld.load_script(&target).await
+                ld.add_dependent(&target, caller);
+                ld.load_script_with(&target, allowed).await
             }
         };
 
@@ -330,6 +475,56 @@ impl LoadFunctions {
     }
 }
 
+/// Parse a capabilities map, resolving relative paths from `dir`.
+async fn parse_capabilities(
+    caps: ergo_runtime::TypedValue<types::Map>,
+    dir: &Path,
+) -> ergo_runtime::Result<Capabilities> {
+    let mut ret = Capabilities::none();
+    for (k, v) in caps.into_owned().0 {
+        let k = Context::eval_as::<types::String>(k.into()).await?;
+        let k_source = metadata::Source::get(&k);
+        let items = Context::eval_as::<types::Array>(v).await?.into_owned().0;
+        match k.as_ref().as_str() {
+            "exec" | "net" => {
+                let mut strings = Vec::new();
+                for item in items {
+                    strings.push(
+                        Context::eval_as::<types::String>(item)
+                            .await?
+                            .into_owned()
+                            .into_string(),
+                    );
+                }
+                ret = if k.as_ref().as_str() == "exec" {
+                    ret.with_exec(strings)
+                } else {
+                    ret.with_net(strings)
+                };
+            }
+            "read" | "write" | "plugin" => {
+                let mut paths = Vec::new();
+                for item in items {
+                    let p = traits::into::<types::Path>(item).await?;
+                    paths.push(dir.join(p.as_ref().as_ref()));
+                }
+                ret = match k.as_ref().as_str() {
+                    "read" => ret.with_read(paths),
+                    "write" => ret.with_write(paths),
+                    _ => ret.with_plugin(paths),
+                };
+            }
+            other => Err(k_source
+                .with(format!(
+                    "unknown capability: {} (expected one of exec, read, write, net, or plugin)",
+                    other
+                ))
+                .into_error())?,
+        }
+    }
+    Ok(ret)
+}
+
 /// Look at the file contents to determine if the file is a plugin (dynamic library).
 fn is_plugin(f: &Path) -> bool {
     use std::fs::File;
@@ -448,3 +643,69 @@ fn script_path_exists<'a, P: 'a + AsRef<Path>>(
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Create load data with the given scripts loaded, and the given (path, dependent) loads.
+    fn load_data(scripts: &[&str], loads: &[(&str, &str)]) -> LoadData {
+        let ld = LoadData::new(vec![]);
+        {
+            let mut cache = ld.load_cache.lock();
+            for s in scripts {
+                cache.insert((PathBuf::from(s), None), types::Unit.into());
+            }
+            let mut dependents = ld.dependents.lock();
+            for (path, dependent) in loads {
+                dependents
+                    .entry(PathBuf::from(path))
+                    .or_default()
+                    .insert(PathBuf::from(dependent));
+            }
+        }
+        ld
+    }
+
+    fn loaded(ld: &LoadData) -> Vec<PathBuf> {
+        let mut ret: Vec<_> = ld
+            .load_cache
+            .lock()
+            .keys()
+            .map(|(p, _)| p.clone())
+            .collect();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn invalidate_dependents() {
+        let ld = load_data(
+            &["/a", "/b", "/c", "/d"],
+            &[("/c", "/b"), ("/b", "/a"), ("/d", "/e")],
+        );
+        assert!(ld.invalidate(&["/c".into()]));
+        assert_eq!(loaded(&ld), vec![PathBuf::from("/d")]);
+
+        // Only the changed script and its dependents are removed.
+        let ld = load_data(&["/a", "/b", "/c"], &[("/b", "/a")]);
+        assert!(ld.invalidate(&["/b".into()]));
+        assert_eq!(loaded(&ld), vec![PathBuf::from("/c")]);
+    }
+
+    #[test]
+    fn invalidate_unknown() {
+        // Changes to paths which are not loaded scripts may affect any script.
+        let ld = load_data(&["/a", "/b"], &[("/b", "/a")]);
+        assert!(ld.invalidate(&["/a".into(), "/data".into()]));
+        assert!(loaded(&ld).is_empty());
+    }
+
+    #[test]
+    fn invalidate_plugin() {
+        let ld = load_data(&["/a"], &[]);
+        ld.plugins.lock().insert("/plugin.so".into());
+        assert!(!ld.invalidate(&["/plugin.so".into()]));
+        assert_eq!(loaded(&ld), vec![PathBuf::from("/a")]);
+    }
+}
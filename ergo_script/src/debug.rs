@@ -0,0 +1,553 @@
+//! Script evaluation debugging.
+//!
+//! A `Debugger` attached to a `Runtime` pauses the evaluation of script expressions at breakpoints,
+//! when stepping, and when a pause is requested. While an expression is paused, the `DebugClient`
+//! may inspect the values captured by the expression and the dynamic scope, and may evaluate
+//! scripts with the captured values bound.
+
+use crate::ast::{self, Expr, Subexpressions};
+use crate::base::LoadData;
+use crate::eval::Captures;
+use ergo_runtime::abi_stable::external_types::RMutex;
+use ergo_runtime::{traits, types, Context, Result, Source, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+/// The reason that evaluation stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint,
+    Step,
+    Pause,
+}
+
+/// The maximum number of characters of a displayed variable value.
+const MAX_DISPLAY_LENGTH: usize = 200;
+
+/// A value captured by a paused expression.
+#[derive(Clone, Debug)]
+pub struct Variable {
+    pub name: String,
+    /// The type name of the value, if the value has been evaluated.
+    pub type_name: Option<String>,
+    /// The displayed value, if the value and its nested values have been evaluated.
+    pub value: Option<String>,
+}
+
+/// A binding in the dynamic scope of a paused expression.
+#[derive(Clone, Debug)]
+pub struct DynamicBinding {
+    /// The location at which the binding was set.
+    pub location: Source<()>,
+    /// The type name of the value, if the binding was set by a script and the value has been
+    /// evaluated.
+    pub type_name: Option<String>,
+    /// The displayed value, if the binding was set by a script and the value and its nested values
+    /// have been evaluated.
+    pub value: Option<String>,
+}
+
+/// The state of a paused expression.
+#[derive(Clone, Debug)]
+pub struct Stopped {
+    pub reason: StopReason,
+    /// The location of the paused expression.
+    pub location: Source<()>,
+    /// The evaluation backtrace, ordered from most to least recent.
+    pub backtrace: Vec<Source<String>>,
+    /// The values captured by the paused expression.
+    pub captures: Vec<Variable>,
+    /// The bindings in the dynamic scope.
+    pub dynamic_scope: Vec<DynamicBinding>,
+}
+
+enum Command {
+    Continue,
+    Step,
+    Evaluate(String, mpsc::Sender<Result<String>>),
+}
+
+struct Shared {
+    /// Breakpoint lines (1-based) by canonical script path.
+    breakpoints: RMutex<HashMap<PathBuf, BTreeSet<usize>>>,
+    step: AtomicBool,
+    pause: AtomicBool,
+    is_paused: AtomicBool,
+    last_stop: RMutex<Option<Source<()>>>,
+    /// Held while an expression is paused, so that only one expression is paused at a time.
+    paused: futures::lock::Mutex<()>,
+    commands: Arc<std::sync::Mutex<mpsc::Receiver<Command>>>,
+    stops: std::sync::Mutex<mpsc::Sender<Stopped>>,
+    load_data: RMutex<Option<LoadData>>,
+}
+
+/// A script debugger, which may be attached to a `Runtime`.
+#[derive(Clone)]
+pub struct Debugger {
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Debugger").finish_non_exhaustive()
+    }
+}
+
+/// The client interface of a `Debugger`.
+pub struct DebugClient {
+    shared: Arc<Shared>,
+    commands: mpsc::Sender<Command>,
+    stops: mpsc::Receiver<Stopped>,
+}
+
+impl Debugger {
+    /// Create a new debugger and its client.
+    pub fn new() -> (Self, DebugClient) {
+        let (command_send, command_recv) = mpsc::channel();
+        let (stop_send, stop_recv) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            breakpoints: RMutex::new(Default::default()),
+            step: AtomicBool::new(false),
+            pause: AtomicBool::new(false),
+            is_paused: AtomicBool::new(false),
+            last_stop: RMutex::new(None),
+            paused: futures::lock::Mutex::new(()),
+            commands: Arc::new(std::sync::Mutex::new(command_recv)),
+            stops: std::sync::Mutex::new(stop_send),
+            load_data: RMutex::new(None),
+        });
+        (
+            Debugger {
+                shared: shared.clone(),
+            },
+            DebugClient {
+                shared,
+                commands: command_send,
+                stops: stop_recv,
+            },
+        )
+    }
+
+    /// Set the load data to use when evaluating scripts while paused.
+    pub(crate) fn set_load_data(&self, load_data: Option<LoadData>) {
+        *self.shared.load_data.lock() = load_data;
+    }
+
+    /// Return whether the expression at the given source location should stop.
+    ///
+    /// If `take` is true, any step or pause request is consumed.
+    fn should_stop(&self, src: &Source<()>, take: bool) -> Option<StopReason> {
+        // Children which start at the same location as the most recently stopped expression (for
+        // instance, the key of a binding or the value of an index) shouldn't stop again.
+        if let Some(last) = &*self.shared.last_stop.lock() {
+            if last.source_id == src.source_id
+                && last.location.start == src.location.start
+                && last.location.length > src.location.length
+            {
+                return None;
+            }
+        }
+
+        let flag = |f: &AtomicBool| {
+            if take {
+                f.swap(false, Ordering::Relaxed)
+            } else {
+                f.load(Ordering::Relaxed)
+            }
+        };
+        if flag(&self.shared.step) {
+            return Some(StopReason::Step);
+        }
+        if flag(&self.shared.pause) {
+            return Some(StopReason::Pause);
+        }
+
+        let breakpoints = self.shared.breakpoints.lock();
+        if breakpoints.is_empty() {
+            return None;
+        }
+        let sources = Context::global().diagnostic_sources();
+        let lines = breakpoints.get(&sources.path(src.source_id)?)?;
+        let (line, _) = sources.line_column(src)?;
+        if !lines.contains(&line) {
+            return None;
+        }
+        // Only stop at the first expression of the line.
+        let content = sources.content(src.source_id)?;
+        let before = &content[..src.location.start];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        if before[line_start..].trim().is_empty() {
+            Some(StopReason::Breakpoint)
+        } else {
+            None
+        }
+    }
+
+    /// Called when an expression is reached by the evaluator.
+    ///
+    /// If the expression should stop, this waits for the client to continue or step.
+    pub(crate) async fn reached(&self, expr: &Expr, captures: &Captures) {
+        use ast::ExpressionType::*;
+        // Don't stop at leaf expressions.
+        match expr.value().expr_type() {
+            Unit | BindAny | String | Get | LateGet => return,
+            _ => (),
+        }
+
+        let src = expr.source();
+        if self.should_stop(&src, false).is_none() {
+            return;
+        }
+
+        let _paused = self.shared.paused.lock().await;
+        // The step or pause may have been consumed by another expression while waiting.
+        let reason = match self.should_stop(&src, true) {
+            None => return,
+            Some(r) => r,
+        };
+        *self.shared.last_stop.lock() = Some(src.clone());
+
+        let named = named_captures(expr, captures);
+        let mut variables = Vec::new();
+        for (name, v) in &named {
+            variables.push(Variable {
+                name: name.clone(),
+                type_name: v.is_evaluated().then(|| traits::type_name(v)),
+                value: display_value(v).await,
+            });
+        }
+        let mut dynamic_scope = Vec::new();
+        for (location, v) in Context::with(|ctx| ctx.dynamic_scope.entries()) {
+            dynamic_scope.push(DynamicBinding {
+                location,
+                type_name: v
+                    .as_ref()
+                    .filter(|v| v.is_evaluated())
+                    .map(traits::type_name),
+                value: match &v {
+                    Some(v) => display_value(v).await,
+                    None => None,
+                },
+            });
+        }
+        let stopped = Stopped {
+            reason,
+            location: src,
+            backtrace: Context::with(|ctx| {
+                ctx.backtrace
+                    .iter()
+                    .map(|s| s.clone().map(|s| s.to_string()))
+                    .collect()
+            }),
+            captures: variables,
+            dynamic_scope,
+        };
+        // Mark the expression as paused before notifying the client, so that the client may
+        // immediately resume or evaluate.
+        self.shared.is_paused.store(true, Ordering::Relaxed);
+        if self.shared.stops.lock().unwrap().send(stopped).is_err() {
+            // The client is gone.
+            self.shared.is_paused.store(false, Ordering::Relaxed);
+            return;
+        }
+
+        loop {
+            // Wait in a blocking task so that the runtime does not consider evaluation to be
+            // deadlocked while paused.
+            let commands = self.shared.commands.clone();
+            let command = Context::global()
+                .task
+                .spawn_blocking(move || commands.lock().unwrap().recv().ok())
+                .await;
+            match command {
+                Ok(Some(Command::Continue)) => break,
+                Ok(Some(Command::Step)) => {
+                    self.shared.step.store(true, Ordering::Relaxed);
+                    break;
+                }
+                Ok(Some(Command::Evaluate(script, reply))) => {
+                    drop(reply.send(self.evaluate(&script, expr, &named).await));
+                }
+                // The client is gone, or the task was aborted.
+                Ok(None) | Err(_) => break,
+            }
+        }
+        self.shared.is_paused.store(false, Ordering::Relaxed);
+    }
+
+    /// Evaluate a script with the given captures bound, displaying the result.
+    async fn evaluate(
+        &self,
+        script: &str,
+        paused: &Expr,
+        captures: &HashMap<String, Value>,
+    ) -> Result<String> {
+        let load_data = self.shared.load_data.lock().clone().ok_or_else(|| {
+            ergo_runtime::error! {
+                error: "the debugger is not attached to a runtime"
+            }
+        })?;
+        let sources = Context::global().diagnostic_sources();
+        let source_id = sources.add_string("<debug>".into(), script.to_owned());
+        let content = sources.content(source_id).unwrap();
+        // The script is loaded without a debugger, so it will not stop.
+        let mut script = {
+            let mut guard = load_data.ast_context.lock();
+            crate::Script::load(
+                Source::new(source_id).with(content),
+                &mut *guard,
+                load_data.lint_level(),
+            )?
+        };
+        let path = sources.path(paused.source().source_id);
+        script.top_level_env(load_data.script_top_level_env(path.as_deref()));
+        script.extend_top_level_env(captures.clone());
+
+        let value = script.evaluate().await?;
+        traits::eval_nested(value.clone()).await?;
+        traits::to_string(value).await
+    }
+}
+
+/// Display a value, if the value and its nested values have been evaluated.
+///
+/// Values are never evaluated (which may have side effects), and the displayed value is truncated
+/// to `MAX_DISPLAY_LENGTH` characters.
+async fn display_value(v: &Value) -> Option<String> {
+    if !is_displayable(v) {
+        return None;
+    }
+    let mut s = traits::to_string(v.clone()).await.ok()?;
+    if let Some((end, _)) = s.char_indices().nth(MAX_DISPLAY_LENGTH) {
+        s.truncate(end);
+        s.push_str("...");
+    }
+    Some(s)
+}
+
+/// Return whether a value may be displayed without evaluating anything.
+fn is_displayable(v: &Value) -> bool {
+    use traits::NestedValues;
+
+    // Displaying iterators and byte streams consumes them.
+    if !v.is_evaluated() || v.is_type::<types::Iter>() || v.is_type::<types::ByteStream>() {
+        return false;
+    }
+    let nested = if let Some(a) = v.as_ref::<types::Array>() {
+        a.nested_values()
+    } else if let Some(m) = v.as_ref::<types::Map>() {
+        m.nested_values()
+    } else if let Some(e) = v.as_ref::<types::MapEntry>() {
+        e.nested_values()
+    } else if let Some(i) = v.as_ref::<types::Index>() {
+        i.nested_values()
+    } else if let Some(a) = v.as_ref::<types::Args>() {
+        a.nested_values()
+    } else {
+        vec![]
+    };
+    nested.into_iter().all(is_displayable)
+}
+
+/// Get the values of the captures of an expression which are bound to names.
+fn named_captures(expr: &Expr, captures: &Captures) -> HashMap<String, Value> {
+    fn visit(e: &Expr, captures: &Captures, out: &mut HashMap<String, Value>) {
+        let get = e
+            .value()
+            .as_ref::<ast::Get>()
+            .map(|g| (g.capture_key, &g.value))
+            .or_else(|| {
+                e.value()
+                    .as_ref::<ast::LateGet>()
+                    .map(|g| (g.capture_key, &g.value))
+            });
+        if let Some((Some(key), name)) = get {
+            if let (Some(name), Some(v)) = (name.value().as_ref::<ast::String>(), captures.get(key))
+            {
+                out.insert(name.0.clone(), v.clone());
+            }
+        }
+        e.subexpressions(|s| {
+            if let ast::SubExpr::SubExpr(s) = s {
+                visit(s, captures, out);
+            }
+        });
+    }
+
+    let mut out = HashMap::new();
+    visit(expr, captures, &mut out);
+    out
+}
+
+impl DebugClient {
+    /// Set the breakpoint lines (1-based) of the script at the given path.
+    pub fn set_breakpoints<I: IntoIterator<Item = usize>>(&self, path: &std::path::Path, lines: I) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        let lines: BTreeSet<usize> = lines.into_iter().collect();
+        let mut breakpoints = self.shared.breakpoints.lock();
+        if lines.is_empty() {
+            breakpoints.remove(&path);
+        } else {
+            breakpoints.insert(path, lines);
+        }
+    }
+
+    /// Request that evaluation stops at the next expression.
+    pub fn pause(&self) {
+        self.shared.pause.store(true, Ordering::Relaxed);
+    }
+
+    /// Return whether an expression is paused.
+    pub fn is_paused(&self) -> bool {
+        self.shared.is_paused.load(Ordering::Relaxed)
+    }
+
+    /// Continue the paused expression.
+    ///
+    /// Returns false if no expression is paused.
+    pub fn resume(&self) -> bool {
+        self.is_paused() && self.commands.send(Command::Continue).is_ok()
+    }
+
+    /// Continue the paused expression, stopping at the next expression.
+    ///
+    /// Returns false if no expression is paused.
+    pub fn step(&self) -> bool {
+        self.is_paused() && self.commands.send(Command::Step).is_ok()
+    }
+
+    /// Evaluate a script in the scope of the paused expression.
+    ///
+    /// Returns the displayed result, or None if no expression is paused.
+    pub fn evaluate(&self, script: &str) -> Option<Result<String>> {
+        if !self.is_paused() {
+            return None;
+        }
+        let (send, recv) = mpsc::channel();
+        self.commands
+            .send(Command::Evaluate(script.to_owned(), send))
+            .ok()?;
+        recv.recv().ok()
+    }
+
+    /// Wait for evaluation to stop, up to the given timeout.
+    pub fn next_stop(&self, timeout: std::time::Duration) -> Option<Stopped> {
+        self.stops.recv_timeout(timeout).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Runtime;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Evaluate a script on another thread, returning the displayed result.
+    fn evaluate(
+        debugger: Option<Debugger>,
+        script: &'static str,
+    ) -> std::thread::JoinHandle<String> {
+        std::thread::spawn(move || {
+            let runtime =
+                Runtime::new(Default::default(), vec![]).expect("failed to create runtime");
+            runtime.debugger(debugger);
+            let value = runtime
+                .evaluate_string("<test>", script)
+                .expect("failed to load script");
+            runtime
+                .block_on(async move {
+                    traits::eval_nested(value.clone()).await?;
+                    traits::to_string(value).await
+                })
+                .expect("failed to evaluate script")
+        })
+    }
+
+    #[test]
+    fn pause() {
+        let (debugger, client) = Debugger::new();
+        client.pause();
+        let result = evaluate(Some(debugger), "[a, b]");
+        let stopped = client.next_stop(TIMEOUT).expect("evaluation did not stop");
+        assert_eq!(stopped.reason, StopReason::Pause);
+        assert!(client.is_paused());
+        assert_eq!(
+            client
+                .evaluate("hello")
+                .map(|r| r.expect("evaluation failed")),
+            Some("hello".to_owned())
+        );
+        assert!(client.resume());
+        assert_eq!(result.join().unwrap(), "[a, b]");
+        assert!(client.next_stop(Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn resume_while_not_paused() {
+        let (debugger, client) = Debugger::new();
+        assert!(!client.is_paused());
+        assert!(!client.resume());
+        assert!(!client.step());
+        assert!(client.evaluate("hello").is_none());
+
+        // The ignored commands must not resume the next stop.
+        client.pause();
+        let result = evaluate(Some(debugger), "[a, b]");
+        client.next_stop(TIMEOUT).expect("evaluation did not stop");
+        assert!(client.is_paused());
+        assert!(client.resume());
+        assert_eq!(result.join().unwrap(), "[a, b]");
+    }
+
+    #[test]
+    fn detach() {
+        // Evaluation completes when the client is dropped while paused.
+        let (debugger, client) = Debugger::new();
+        client.pause();
+        let result = evaluate(Some(debugger), "[a, b]");
+        client.next_stop(TIMEOUT).expect("evaluation did not stop");
+        drop(client);
+        assert_eq!(result.join().unwrap(), "[a, b]");
+
+        // Evaluation does not stop once the debugger is detached from the runtime.
+        let (debugger, client) = Debugger::new();
+        client.pause();
+        let result = std::thread::spawn(move || {
+            let runtime =
+                Runtime::new(Default::default(), vec![]).expect("failed to create runtime");
+            runtime.debugger(Some(debugger));
+            runtime.debugger(None);
+            runtime
+                .evaluate_string("<test>", "[a, b]")
+                .and_then(|v| runtime.block_on(traits::to_string(v)))
+                .expect("failed to evaluate script")
+        });
+        assert_eq!(result.join().unwrap(), "[a, b]");
+        assert!(client.next_stop(Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn display_values() {
+        let runtime = Runtime::new(Default::default(), vec![]).expect("failed to create runtime");
+        runtime.block_on(async {
+            let s: Value = types::String::from("hello").into();
+            assert_eq!(display_value(&s).await.as_deref(), Some("hello"));
+
+            let long: Value = types::String::from("a".repeat(MAX_DISPLAY_LENGTH + 1)).into();
+            assert_eq!(
+                display_value(&long).await,
+                Some(format!("{}...", "a".repeat(MAX_DISPLAY_LENGTH)))
+            );
+
+            // Iterators are not displayed, since displaying them consumes them.
+            let iter = Value::with_id(types::Iter::from_iter(vec![s.clone()].into_iter()), 0u128);
+            assert!(display_value(&iter).await.is_none());
+            let array: Value = types::Array(vec![s, iter].into()).into();
+            assert!(display_value(&array).await.is_none());
+        });
+    }
+}
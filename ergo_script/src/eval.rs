@@ -20,8 +20,14 @@ use std::collections::{BTreeMap, HashMap};
 
 pub const EVAL_TASK_PRIORITY: u32 = 100;
 
-#[derive(Clone, Copy, Debug, Default)]
-pub struct Evaluator {}
+#[derive(Clone, Debug, Default)]
+pub struct Evaluator {
+    pub debugger: Option<crate::debug::Debugger>,
+    /// Capabilities to which evaluation is restricted.
+    ///
+    /// These are shared, since the evaluator is cloned for every child expression.
+    pub capabilities: Option<std::sync::Arc<ergo_runtime::context::Capabilities>>,
+}
 
 #[derive(Clone, Debug)]
 pub enum Capture {
@@ -107,6 +113,24 @@ impl Captures {
         self.inner.insert(key, Capture::Evaluated(value));
     }
 
+    /// Get the string gets which will not be resolved by the given environment.
+    pub fn unresolved_string_gets(
+        &self,
+        with: &HashMap<String, Value>,
+    ) -> Vec<ergo_runtime::Source<String>> {
+        self.inner
+            .values()
+            .filter_map(|c| match c {
+                Capture::Expr(expression) => expression
+                    .value()
+                    .as_ref::<ast::String>()
+                    .filter(|s| !with.contains_key(s.0.as_str()))
+                    .map(|s| expression.source().with(s.0.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
     pub fn resolve_string_gets(&mut self, with: &HashMap<String, Value>) -> Result<()> {
         let mut resolves = vec![];
         for (k, c) in self.inner.iter_mut() {
@@ -478,7 +502,7 @@ impl ExprEvaluator {
     fn child(&self, child: &Expr) -> Self {
         ExprEvaluator {
             expr: child.clone(),
-            evaluator: self.evaluator,
+            evaluator: self.evaluator.clone(),
             captures: child
                 .captures()
                 .map(|c| self.captures.subset(c))
@@ -798,6 +822,9 @@ impl ExprEvaluator {
 
     async fn evaluate_impl(&self) -> Value {
         log::trace!("evaluating {:?}", self.source());
+        if let Some(debugger) = &self.evaluator.debugger {
+            debugger.reached(&self.expr, &self.captures).await;
+        }
         macro_rules! delayed {
             ( $self:ident , $v:ident , $( $body:tt )* ) => {{
                 let $self = self.clone();
@@ -805,7 +832,13 @@ impl ExprEvaluator {
                 let v_type = witness($v);
                 ergo_runtime::lazy_value! {
                     #![contains($self)]
-                    Context::spawn(EVAL_TASK_PRIORITY, move |ctx| ctx.backtrace.push(src.with("")), async move {
+                    let capabilities = $self.evaluator.capabilities.clone();
+                    Context::spawn(EVAL_TASK_PRIORITY, move |ctx| {
+                        ctx.backtrace.push(src.with(""));
+                        if let Some(c) = capabilities {
+                            ctx.capabilities = ctx.capabilities.restrict(&c);
+                        }
+                    }, async move {
                         log::trace!("evaluating (delayed) {:?}", $self.source());
                         let v: Value = async {
                             // Safety: v_type (from $v) must have been from a previous checked call
@@ -6,6 +6,7 @@ use std::collections::HashMap;
 
 pub mod ast;
 mod base;
+pub mod debug;
 mod eval;
 pub mod testing;
 
@@ -20,10 +21,12 @@ pub mod constants {
     pub const EXTENSION: &'static str = program_name!();
     pub const WORKSPACE_NAME: &'static str = concat!("workspace.", program_name!());
     pub const DIR_NAME: &'static str = concat!("dir.", program_name!());
+    pub const LOCK_NAME: &'static str = concat!(program_name!(), ".lock");
     pub const PLUGIN_ENTRY: &'static str = concat!("_", program_name!(), "_plugin");
 }
 
 pub use ast::LintLevel;
+pub use base::{summary as doc_summary, DocFormat};
 
 use eval::*;
 
@@ -71,6 +74,16 @@ impl Runtime {
         self.load_data.set_backtrace(backtrace);
     }
 
+    /// Set the debugger used when evaluating scripts.
+    pub fn debugger(&self, debugger: Option<debug::Debugger>) {
+        if let Some(d) = &debugger {
+            d.set_load_data(Some(self.load_data.clone()));
+        }
+        if let Some(d) = self.load_data.set_debugger(debugger) {
+            d.set_load_data(None);
+        }
+    }
+
     /// Load a script from a string.
     pub fn load_string(&self, name: &str, script: &str) -> Result<Script, Error> {
         let source_id = self
@@ -100,6 +113,7 @@ impl Runtime {
         if self.load_data.backtrace() {
             s.enable_backtrace();
         }
+        s.debugger(self.load_data.debugger());
         Ok(s)
     }
 
@@ -155,6 +169,48 @@ impl Runtime {
         val
     }
 
+    /// Invalidate loaded scripts which may be affected by changes to the given paths, so that they
+    /// are loaded again when next used.
+    ///
+    /// Returns false if the runtime cannot be reused with the changes (because a changed path is a
+    /// loaded plugin).
+    pub fn invalidate(&self, changed: &[std::path::PathBuf]) -> bool {
+        let sources = self.ctx.global.diagnostic_sources();
+        for path in changed {
+            sources.invalidate_file(path);
+        }
+
+        let loaded = self.loaded_scripts();
+        if !self.load_data.invalidate(changed) {
+            return false;
+        }
+
+        // Input paths are recorded again as they are used in the next evaluation. If all loads
+        // were removed, everything will be evaluated again. Otherwise the remaining loads may
+        // have recorded paths (like files read by the scripts) which will not be recorded again,
+        // so only the paths of the removed loads are forgotten.
+        let input_paths = self.ctx.global.input_paths();
+        let remaining = self.loaded_scripts();
+        if remaining.is_empty() {
+            input_paths.clear();
+        } else {
+            for path in loaded.difference(&remaining) {
+                input_paths.remove(path);
+            }
+        }
+        true
+    }
+
+    /// Get the paths of all cached loaded scripts.
+    fn loaded_scripts(&self) -> std::collections::HashSet<std::path::PathBuf> {
+        self.load_data
+            .load_cache
+            .lock()
+            .keys()
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
     /// Clear the load cache of any loaded scripts.
     pub fn clear_load_cache(&self) {
         self.load_data.load_cache.lock().clear();
@@ -174,8 +230,10 @@ pub struct Script {
     ast: ast::Expr,
     captures: eval::Captures,
     top_level_env: HashMap<String, Value>,
-    lint_messages: Vec<Source<String>>,
+    lint_messages: Vec<Source<ast::LintMessage>>,
     backtrace: bool,
+    debugger: Option<debug::Debugger>,
+    capabilities: Option<ergo_runtime::context::Capabilities>,
 }
 
 impl Script {
@@ -192,6 +250,8 @@ impl Script {
                 top_level_env: Default::default(),
                 lint_messages,
                 backtrace: false,
+                debugger: None,
+                capabilities: None,
             };
             script
                 .captures
@@ -220,6 +280,30 @@ impl Script {
         self.backtrace = true;
     }
 
+    /// Set the debugger used when evaluating this script.
+    pub fn debugger(&mut self, debugger: Option<debug::Debugger>) {
+        self.debugger = debugger;
+    }
+
+    /// Restrict the capabilities used when evaluating values from this script.
+    pub fn capabilities(&mut self, capabilities: Option<ergo_runtime::context::Capabilities>) {
+        self.capabilities = capabilities;
+    }
+
+    /// Get the lint messages produced when loading the script.
+    pub fn lint_messages(&self) -> &[Source<ast::LintMessage>] {
+        &self.lint_messages
+    }
+
+    /// Get the names which are not bound in any enclosing scope nor in the top-level environment.
+    ///
+    /// Evaluating the script will fail if there are any such names.
+    pub fn unbound_names(&self) -> Vec<Source<String>> {
+        let mut names = self.captures.unresolved_string_gets(&self.top_level_env);
+        names.sort_by_key(|s| s.location.start);
+        names
+    }
+
     /// Evaluate the script.
     ///
     /// This must be called with the Context set.
@@ -230,6 +314,8 @@ impl Script {
             mut captures,
             ast,
             backtrace: _backtrace,
+            debugger,
+            capabilities,
         } = self;
 
         if !lint_messages.is_empty() {
@@ -239,7 +325,7 @@ impl Script {
                     diagnostics_to_string, Diagnostic, DiagnosticInfo, Severity,
                 };
                 let (src, m) = m.take();
-                let diag = Diagnostic::from(m)
+                let diag = Diagnostic::from(m.message)
                     .set_severity(Severity::Warning)
                     .add_primary_label(src.with(""));
                 lint_log.warn(diagnostics_to_string(
@@ -250,7 +336,10 @@ impl Script {
             }
         }
 
-        let evaluator = Evaluator {};
+        let evaluator = Evaluator {
+            debugger,
+            capabilities: capabilities.map(std::sync::Arc::new),
+        };
         captures.resolve_string_gets(&top_level_env)?;
         Ok(evaluator.evaluate(ast, captures).await)
     }
@@ -404,6 +493,21 @@ mod test {
                 SRString("doc comment"),
             )
         }
+
+        #[test]
+        fn summary() -> Result<(), String> {
+            script_eval_to(
+                "## # Heading
+                ##
+                ## The
+                ## summary.
+                ##
+                ## More docs.
+                a = ()
+                doc:summary <| doc $a",
+                SRString("The summary."),
+            )
+        }
     }
 
     #[test]
@@ -550,6 +654,46 @@ mod test {
         script_eval_to("{~something}", SRMap(&[("something", SRUnit)]))
     }
 
+    mod capabilities {
+        use super::*;
+        use ergo_runtime::context::Capabilities;
+
+        /// Create a file with the given content in a new temporary directory.
+        fn temp_file(name: &str, content: &[u8]) -> std::path::PathBuf {
+            let dir = std::env::temp_dir().join(format!("ergo-test-{}", name));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        }
+
+        /// Load the given path with the given capabilities, returning the error message.
+        fn load_error(path: &std::path::Path, capabilities: Capabilities) -> String {
+            let runtime = Runtime::new(Context::builder().capabilities(capabilities), vec![])
+                .expect("failed to create runtime");
+            let mut v = script_eval(&runtime, &format!("load \"{}\"", path.display()))
+                .expect("failed to evaluate script");
+            runtime
+                .block_on(async move { Context::eval(&mut v).await })
+                .expect_err("load unexpectedly succeeded")
+                .to_string()
+        }
+
+        #[test]
+        fn load_read() {
+            let path = temp_file("load-read.ergo", b"()");
+            assert!(load_error(&path, Capabilities::none()).contains("is not allowed"));
+        }
+
+        #[test]
+        #[cfg(target_os = "linux")]
+        fn load_plugin() {
+            let path = temp_file("load-plugin.so", b"\x7fELF");
+            let caps = Capabilities::none().with_read([path.parent().unwrap()]);
+            assert!(load_error(&path, caps).contains("loading plugin"));
+        }
+    }
+
     mod merge {
         use super::*;
 
@@ -14,11 +14,22 @@ impl Test {
     pub fn new(
         plugin_entry: extern "C" fn(ergo_runtime::plugin::Context) -> RResult<Value>,
     ) -> Self {
-        let rt = crate::Runtime::new(
-            Context::builder().threads(Some(1)).keep_going(false),
-            vec![],
-        )
-        .expect("failed to create runtime");
+        Self::with_context(plugin_entry, Context::builder())
+    }
+
+    /// Create a test which evaluates in dry-run mode.
+    pub fn dry_run(
+        plugin_entry: extern "C" fn(ergo_runtime::plugin::Context) -> RResult<Value>,
+    ) -> Self {
+        Self::with_context(plugin_entry, Context::builder().dry_run(true))
+    }
+
+    fn with_context(
+        plugin_entry: extern "C" fn(ergo_runtime::plugin::Context) -> RResult<Value>,
+        context: ergo_runtime::context::ContextBuilder,
+    ) -> Self {
+        let rt = crate::Runtime::new(context.threads(Some(1)).keep_going(false), vec![])
+            .expect("failed to create runtime");
 
         let plugin = rt
             .ctx
@@ -34,6 +45,12 @@ impl Test {
         Test { env, runtime: rt }
     }
 
+    /// Restrict the capabilities of evaluation.
+    pub fn capabilities(mut self, capabilities: ergo_runtime::context::Capabilities) -> Self {
+        self.runtime.ctx.capabilities = capabilities;
+        self
+    }
+
     pub fn eval(&self, script: &str) -> Result<IdentifiedValue> {
         let mut script = self.runtime.load_string("test", script)?;
         script.extend_top_level_env(self.env.clone());
@@ -135,6 +152,11 @@ impl Test {
             .expect_err("bind unexpectedly succeeded");
     }
 
+    /// Call the runtime shutdown hooks (e.g. to flush persistent caches).
+    pub fn shutdown(&self) {
+        self.runtime.ctx.global.hooks().shutdown();
+    }
+
     fn dbg(&self, v: &Value) {
         dbg!(self.block_on(async move { traits::type_name(v) }));
         match self.block_on(traits::to_string(v.clone())) {
@@ -0,0 +1,345 @@
+//! ByteStream functions.
+
+use ergo_runtime::{
+    error::DiagnosticInfo,
+    io::{self, AsyncWrite},
+    metadata::Source,
+    traits,
+    type_system::ErgoType,
+    types,
+    types::byte_stream::ByteStreamReader,
+    Value,
+};
+use futures::io::AsyncReadExt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+pub fn r#type() -> Value {
+    types::Type {
+        tp: types::ByteStream::ergo_type(),
+        index: crate::make_string_map! {
+            "concat" = concat(),
+            "length" = length(),
+            "split" = split(),
+            "take-bytes" = take_bytes(),
+            "tee" = tee()
+        },
+    }
+    .into()
+}
+
+const READ_BLOCK_SIZE: usize = 4096;
+
+/// A generator splitting a ByteStream on a delimiter.
+///
+/// Only the bytes of the current segment are buffered.
+#[derive(Clone)]
+pub struct Split {
+    reader: ByteStreamReader,
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    // The offset in `buffer` up to which the delimiter is known not to occur.
+    searched: usize,
+    done: bool,
+}
+
+impl Split {
+    pub fn new(reader: ByteStreamReader, delimiter: Vec<u8>) -> Self {
+        debug_assert!(!delimiter.is_empty());
+        Split {
+            reader,
+            delimiter,
+            buffer: Default::default(),
+            searched: 0,
+            done: false,
+        }
+    }
+
+    /// Get the next segment of bytes (without the delimiter).
+    pub async fn next_segment(&mut self) -> ergo_runtime::Result<Option<Vec<u8>>> {
+        let dlen = self.delimiter.len();
+        loop {
+            if let Some(pos) = self.buffer[self.searched..]
+                .windows(dlen)
+                .position(|w| w == self.delimiter.as_slice())
+            {
+                let end = self.searched + pos;
+                let rest = self.buffer.split_off(end + dlen);
+                let mut segment = std::mem::replace(&mut self.buffer, rest);
+                segment.truncate(end);
+                self.searched = 0;
+                return Ok(Some(segment));
+            }
+            // The delimiter may straddle the end of the buffer and the next block.
+            self.searched = self.buffer.len().saturating_sub(dlen - 1);
+
+            if self.done {
+                return Ok(if self.buffer.is_empty() {
+                    None
+                } else {
+                    self.searched = 0;
+                    Some(std::mem::take(&mut self.buffer))
+                });
+            }
+
+            let mut block = [0; READ_BLOCK_SIZE];
+            let read = self.reader.read(&mut block).await?;
+            if read == 0 {
+                self.done = true;
+            } else {
+                self.buffer.extend_from_slice(&block[..read]);
+            }
+        }
+    }
+}
+
+ergo_runtime::ImplGenerator!(Split => |self| {
+    Ok(self.next_segment().await?.map(|segment| types::ByteStream::from(segment).into()))
+});
+
+#[types::ergo_fn]
+/// Split a ByteStream on a delimiter.
+///
+/// Arguments: `(Into<ByteStream> :delimiter) (Into<ByteStream> :bytes)`
+///
+/// Returns an Iter of ByteStreams, where each ByteStream is the bytes between occurrences of
+/// `delimiter` (not including the delimiter). If `bytes` ends with the delimiter, there is no
+/// final empty ByteStream.
+///
+/// The Iter reads `bytes` lazily, so only a single segment is held in memory at a time.
+async fn split(delimiter: _, bytes: _) -> Value {
+    let delimiter_source = Source::get(&delimiter);
+    let delimiter = traits::into::<types::ByteStream>(delimiter).await?;
+    let bytes = traits::into::<types::ByteStream>(bytes).await?;
+
+    let mut d = Vec::new();
+    delimiter.as_ref().read().read_to_end(&mut d).await?;
+    if d.is_empty() {
+        Err(delimiter_source
+            .with("delimiter must not be empty")
+            .into_error())?;
+    }
+
+    types::Iter::new(Split::new(bytes.as_ref().read(), d), CALL_DEPENDS).into()
+}
+
+#[types::ergo_fn]
+/// Take a number of bytes from the beginning of a ByteStream.
+///
+/// Arguments: `(Into<Number> :count) (Into<ByteStream> :bytes)`
+///
+/// Returns a ByteStream with at most `count` bytes from the beginning of `bytes`. `bytes` is only
+/// read as the returned ByteStream is read.
+async fn take_bytes(count: _, bytes: _) -> Value {
+    let count = traits::into::<types::Number>(count).await?;
+    let bytes = traits::into::<types::ByteStream>(bytes).await?;
+
+    let n = count
+        .as_ref()
+        .to_u64()
+        .add_primary_label(Source::get(&count).with("expected this to be unsigned integer"))?;
+
+    Value::with_id(
+        types::ByteStream::new(bytes.as_ref().read().take(n)),
+        CALL_DEPENDS,
+    )
+}
+
+/// An AsyncRead which reads from each reader in sequence.
+struct Concat {
+    readers: std::collections::VecDeque<ByteStreamReader>,
+}
+
+impl io::AsyncRead for Concat {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        while let Some(reader) = self.readers.front_mut() {
+            match Pin::new(reader).poll_read(cx, buf) {
+                Poll::Ready(Ok(0)) => {
+                    self.readers.pop_front();
+                }
+                other => return other,
+            }
+        }
+        Poll::Ready(Ok(0))
+    }
+}
+
+#[types::ergo_fn]
+/// Concatenate ByteStreams.
+///
+/// Arguments: `^(Array:Of Into<ByteStream>) :streams`
+///
+/// Returns a ByteStream with the bytes of each argument in order. The arguments are only read as
+/// the returned ByteStream is read.
+async fn concat(...) -> Value {
+    let mut readers = std::collections::VecDeque::new();
+    while let Some(v) = REST.next() {
+        readers.push_back(traits::into::<types::ByteStream>(v).await?.as_ref().read());
+    }
+
+    Value::with_id(types::ByteStream::new(Concat { readers }), CALL_DEPENDS)
+}
+
+/// An AsyncRead which writes all bytes that are read to a file.
+///
+/// The file is created when the stream is first read. Bytes are only returned once they have been
+/// written to the file.
+struct Tee {
+    reader: ByteStreamReader,
+    path: std::path::PathBuf,
+    file: Option<io::Blocking<std::fs::File>>,
+    /// The bytes most recently read from `reader`.
+    data: Vec<u8>,
+    /// The number of bytes of `data` written to the file.
+    written: usize,
+    /// The number of bytes of `data` returned to the caller.
+    returned: usize,
+}
+
+impl Tee {
+    fn new(reader: ByteStreamReader, path: std::path::PathBuf) -> Self {
+        Tee {
+            reader,
+            path,
+            file: None,
+            data: Vec::new(),
+            written: 0,
+            returned: 0,
+        }
+    }
+}
+
+impl io::AsyncRead for Tee {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = &mut *self;
+        if me.file.is_none() {
+            let file = std::fs::File::create(&me.path).map_err(|e| {
+                std::io::Error::new(e.kind(), format!("{}: {}", me.path.display(), e))
+            })?;
+            me.file = Some(io::Blocking::new(file));
+        }
+        let file = me.file.as_mut().unwrap();
+        loop {
+            if me.written < me.data.len() {
+                match Pin::new(&mut *file).poll_write(cx, &me.data[me.written..]) {
+                    Poll::Ready(Ok(0)) => {
+                        return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()))
+                    }
+                    Poll::Ready(Ok(n)) => me.written += n,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            } else if me.returned < me.data.len() {
+                let n = std::cmp::min(buf.len(), me.data.len() - me.returned);
+                buf[..n].copy_from_slice(&me.data[me.returned..me.returned + n]);
+                me.returned += n;
+                return Poll::Ready(Ok(n));
+            } else {
+                me.data.resize(READ_BLOCK_SIZE, 0);
+                match Pin::new(&mut me.reader).poll_read(cx, &mut me.data) {
+                    Poll::Ready(Ok(n)) => {
+                        me.data.truncate(n);
+                        me.written = 0;
+                        me.returned = 0;
+                        if n == 0 {
+                            // Ensure all bytes are written before the end of the stream.
+                            return Pin::new(&mut *file).poll_flush(cx).map_ok(|()| 0);
+                        }
+                    }
+                    other => {
+                        me.data.clear();
+                        return other;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[types::ergo_fn]
+/// Copy the bytes of a ByteStream to a file as they are read.
+///
+/// Arguments: `(Path :file) (Into<ByteStream> :bytes)`
+///
+/// Returns a ByteStream identical to `bytes`. When the returned ByteStream is first read, `file`
+/// is created (or overwritten), and as it is read the bytes are also written to `file`. If the
+/// returned ByteStream is never read completely, `file` will only contain the bytes that were
+/// read.
+///
+/// In a dry run, `file` is not written and `bytes` is returned unchanged.
+async fn tee(file: types::Path, bytes: _) -> Value {
+    let bytes = traits::into::<types::ByteStream>(bytes).await?;
+    let path = file.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(path))?;
+    if crate::dry_run(format_args!("tee to {}", path.display())) {
+        return Ok(bytes.into());
+    }
+
+    Value::with_id(
+        types::ByteStream::new(Tee::new(bytes.as_ref().read(), path.to_owned())),
+        CALL_DEPENDS,
+    )
+}
+
+#[types::ergo_fn]
+/// Get the number of bytes in a ByteStream.
+///
+/// Arguments: `(Into<ByteStream> :bytes)`
+///
+/// Returns the length as a Number. The ByteStream is read in its entirety, but is not held in
+/// memory (unless otherwise retained).
+async fn length(bytes: _) -> Value {
+    let bytes = traits::into::<types::ByteStream>(bytes).await?;
+    let mut reader = bytes.as_ref().read();
+    drop(bytes);
+    let len = io::copy(&mut reader, &mut futures::io::sink()).await?;
+    types::Number::from(len).into()
+}
+
+#[cfg(test)]
+mod test {
+    ergo_script::tests! {
+        fn concat(t) {
+            t.assert_eq("self:String:from <| self:ByteStream:concat hello \" \" world", "\"hello world\"");
+        }
+
+        fn length(t) {
+            t.assert_eq("self:ByteStream:length hello", "self:Number:from 5");
+        }
+
+        fn split(t) {
+            t.assert_eq("self:Array:from <| self:Iter:map self:String:from <| self:ByteStream:split \", \" \"a, b, c, \"", "[a,b,c]");
+            t.assert_eq("self:Array:from <| self:Iter:map self:String:from <| self:ByteStream:split \"--\" \"a--b\"", "[a,b]");
+        }
+
+        fn take_bytes(t) {
+            t.assert_eq("self:String:from <| self:ByteStream:take-bytes 3 hello", "hel");
+            t.assert_eq("self:String:from <| self:ByteStream:take-bytes 10 hello", "hello");
+        }
+
+        fn tee(t) {
+            let path = std::env::temp_dir().join(format!("ergo-tee-{}", std::process::id()));
+            t.assert_eq(&format!("self:String:from <| self:ByteStream:tee (self:Path:from \"{}\") hello", path.display()), "hello");
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello");
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        fn tee_not_permitted(t) {
+            let t = t.capabilities(ergo_runtime::context::Capabilities::none());
+            let path = std::env::temp_dir().join("ergo-tee-not-permitted");
+            t.assert_fail(&format!("self:ByteStream:tee (self:Path:from \"{}\") hello", path.display()));
+            assert!(!path.exists());
+        }
+    }
+}
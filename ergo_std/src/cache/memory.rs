@@ -74,16 +74,17 @@ impl MemCache {
             .clone();
 
         let mut guard = entry.lock().await;
-        match *guard {
-            None => {
-                let v = missing(value).await?;
-                *guard = Some((id, v));
-            }
-            Some((vid, _)) if vid != id => {
-                let v = missing(value).await?;
-                *guard = Some((id, v));
-            }
-            _ => (),
+        let hit = match *guard {
+            Some((vid, _)) => vid == id,
+            None => false,
+        };
+        ergo_runtime::Context::global()
+            .log
+            .sublog("cache")
+            .cache_access("memory", hit);
+        if !hit {
+            let v = missing(value).await?;
+            *guard = Some((id, v));
         }
         Ok(guard.as_ref().unwrap().1.clone())
     }
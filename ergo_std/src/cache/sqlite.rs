@@ -718,6 +718,9 @@ impl SqliteCache {
                             match self.read_value(id).await {
                                 Ok(v) => {
                                     db.log.debug(format_args!("successfully read cached value for {:032x}", id));
+                                    db.log.cache_access("sqlite", true);
+                                    // Only logged in dry-run mode.
+                                    crate::dry_run(format_args!("reuse cached value for {:032x}", id));
                                     break v;
                                 }
                                 Err(err) => {
@@ -737,6 +740,11 @@ impl SqliteCache {
                     }
 
                     // Reading serialized data failed, write the value.
+                    db.log.cache_access("sqlite", false);
+                    if crate::dry_run(format_args!("cache value for {:032x}", id)) {
+                        // Don't persist values, which may depend on placeholders.
+                        break value.clone();
+                    }
                     let writer = SqliteCacheWriter::new(self, error_handling);
 
                     // Deeply evaluate the value to make cache overlap between values more likely
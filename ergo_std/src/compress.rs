@@ -0,0 +1,201 @@
+//! Compression module.
+
+use async_compression::futures::bufread as codec;
+use ergo_runtime::{
+    error::{Diagnostic, DiagnosticInfo},
+    io,
+    metadata::Source,
+    traits, types, Value,
+};
+use futures::io::{AsyncReadExt, BufReader};
+
+pub fn module() -> Value {
+    crate::make_string_map! {
+        "decode" = decode(),
+        "encode" = encode()
+    }
+}
+
+/// A compression format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Format {
+    /// The longest magic byte sequence of the formats.
+    pub const MAGIC_LEN: usize = 6;
+
+    /// Detect the format from the leading (magic) bytes of the data.
+    pub fn detect(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::Gzip)
+        } else if magic.starts_with(b"BZh") {
+            Some(Format::Bzip2)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Some(Format::Xz)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" | "gz" => Ok(Format::Gzip),
+            "bzip2" | "bz2" => Ok(Format::Bzip2),
+            "xz" => Ok(Format::Xz),
+            "zstd" | "zst" => Ok(Format::Zstd),
+            other => Err(format!(
+                "unsupported format: {} (expected one of gzip, bzip2, xz, or zstd)",
+                other
+            )),
+        }
+    }
+}
+
+fn get_format(format: ergo_runtime::TypedValue<types::String>) -> ergo_runtime::Result<Format> {
+    format
+        .as_ref()
+        .as_str()
+        .parse()
+        .map_err(|e: String| Source::get(&format).with(e).into_error())
+}
+
+#[types::ergo_fn]
+/// Compress a ByteStream.
+///
+/// Arguments: `(String :format) (Into<ByteStream> :bytes)`
+///
+/// Keyed Arguments:
+/// * `Into<Number> :level`: The compression level to use. The meaning (and valid range) of the
+/// level depends on the format. If unspecified, the default level of the format is used.
+///
+/// `format` may be one of `gzip`, `bzip2`, `xz`, or `zstd`.
+///
+/// Returns a ByteStream of the compressed data. `bytes` is read (and compressed) only as the
+/// returned ByteStream is read.
+async fn encode(format: types::String, bytes: _, (level): [_]) -> Value {
+    let format = get_format(format)?;
+    let bytes = traits::into::<types::ByteStream>(bytes).await?;
+    let level = match level {
+        None => async_compression::Level::Default,
+        Some(v) => {
+            let n = traits::into::<types::Number>(v).await?;
+            let level = n
+                .as_ref()
+                .to_u32()
+                .add_primary_label(Source::get(&n).with("expected this to be unsigned integer"))?;
+            async_compression::Level::Precise(level)
+        }
+    };
+
+    let reader = BufReader::new(bytes.as_ref().read());
+    let stream = match format {
+        Format::Gzip => types::ByteStream::new(codec::GzipEncoder::with_quality(reader, level)),
+        Format::Bzip2 => types::ByteStream::new(codec::BzEncoder::with_quality(reader, level)),
+        Format::Xz => types::ByteStream::new(codec::XzEncoder::with_quality(reader, level)),
+        Format::Zstd => types::ByteStream::new(codec::ZstdEncoder::with_quality(reader, level)),
+    };
+    Value::with_id(stream, CALL_DEPENDS)
+}
+
+#[types::ergo_fn]
+/// Decompress a ByteStream.
+///
+/// Arguments: `(Into<ByteStream> :bytes)`
+///
+/// Keyed Arguments:
+/// * `String :format`: The format of the compressed data. May be one of `gzip`, `bzip2`, `xz`, or
+/// `zstd`. If unspecified, the format is detected from the leading bytes of `bytes`.
+///
+/// Returns a ByteStream of the decompressed data. `bytes` is read (and decompressed) only as the
+/// returned ByteStream is read.
+async fn decode(bytes: _, (format): [types::String]) -> Value {
+    let bytes_source = Source::get(&bytes);
+    let bytes = traits::into::<types::ByteStream>(bytes).await?;
+    let reader = bytes.as_ref().read();
+
+    let format = match format {
+        Some(format) => get_format(format)?,
+        None => {
+            // Readers which are cloned continue from the same position, so the magic bytes are
+            // read from a clone and `reader` remains at the start of the stream.
+            let mut magic = Vec::with_capacity(Format::MAGIC_LEN);
+            reader
+                .clone()
+                .take(Format::MAGIC_LEN as u64)
+                .read_to_end(&mut magic)
+                .await
+                .add_primary_label(bytes_source.clone().with("while reading this value"))?;
+            Format::detect(&magic).ok_or_else(|| {
+                Diagnostic::from("could not detect compression format")
+                    .add_primary_label(bytes_source.with(""))
+                    .add_note("specify the format with the `format` keyed argument")
+            })?
+        }
+    };
+
+    let reader = BufReader::new(reader);
+    let stream = match format {
+        Format::Gzip => {
+            let mut decoder = codec::GzipDecoder::new(reader);
+            decoder.multiple_members(true);
+            types::ByteStream::new(decoder)
+        }
+        Format::Bzip2 => types::ByteStream::new(codec::BzDecoder::new(reader)),
+        Format::Xz => types::ByteStream::new(codec::XzDecoder::new(reader)),
+        Format::Zstd => types::ByteStream::new(codec::ZstdDecoder::new(reader)),
+    };
+    Value::with_id(stream, CALL_DEPENDS)
+}
+
+/// Wrap a synchronous reader of possibly-compressed data with the appropriate decoder.
+///
+/// The returned format is `None` if the data does not appear to be compressed.
+pub fn sync_decoder<'a, R: std::io::Read + 'a>(
+    mut reader: R,
+) -> io::Result<(Option<Format>, Box<dyn std::io::Read + 'a>)> {
+    use std::io::Read;
+
+    let mut magic = Vec::with_capacity(Format::MAGIC_LEN);
+    (&mut reader)
+        .take(Format::MAGIC_LEN as u64)
+        .read_to_end(&mut magic)?;
+    let format = Format::detect(&magic);
+    let reader = std::io::Cursor::new(magic).chain(reader);
+    Ok((
+        format,
+        match format {
+            None => Box::new(reader),
+            Some(Format::Gzip) => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Some(Format::Bzip2) => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+            Some(Format::Xz) => Box::new(xz::read::XzDecoder::new_multi_decoder(reader)),
+            Some(Format::Zstd) => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    ergo_script::tests! {
+        fn roundtrip(t) {
+            t.assert_eq("self:String:from <| self:compress:decode <| self:compress:encode gzip hello", "hello");
+            t.assert_eq("self:String:from <| self:compress:decode <| self:compress:encode bzip2 hello", "hello");
+            t.assert_eq("self:String:from <| self:compress:decode <| self:compress:encode xz hello", "hello");
+            t.assert_eq("self:String:from <| self:compress:decode <| self:compress:encode zstd hello", "hello");
+        }
+
+        fn explicit_format(t) {
+            t.assert_eq("self:String:from <| self:compress:decode ~format=zstd <| self:compress:encode ~level=3 zstd hello", "hello");
+        }
+    }
+}
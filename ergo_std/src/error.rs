@@ -1,13 +1,26 @@
 //! Error functions.
 
-use ergo_runtime::{error::DiagnosticInfo, traits, type_system::ErgoType, types, Value};
+use ergo_runtime::{
+    abi_stable::bst::BstMap,
+    error::{Diagnostic, DiagnosticInfo, FieldValue},
+    metadata::Source,
+    traits,
+    type_system::ErgoType,
+    types,
+    value::match_value,
+    Context, Value,
+};
 
 pub fn r#type() -> Value {
     types::Type {
         tp: types::Error::ergo_type(),
         index: crate::make_string_map! {
+            "catch" = catch(),
             "display" = display(),
-            "new" = new()
+            "fields" = fields(),
+            "kind" = kind(),
+            "new" = new(),
+            "wrap" = wrap()
         },
     }
     .into()
@@ -31,17 +44,251 @@ async fn display(value: _) -> Value {
 /// Keyed Arguments:
 /// * `source` - The value whose source should be used as the source of the error. If omitted, the
 /// source of `msg` is used.
+/// * `String :kind` - The kind of the error.
+/// * `Map :fields` - Structured fields of the error. Keys must be Strings, and values must be
+/// Strings, Numbers (integers), or Bools.
 ///
 /// `message` is displayed as a string in the Error.
-async fn new(message: _, (source): [_]) -> Value {
+async fn new(message: _, (source): [_], (kind): [types::String], (fields): [types::Map]) -> Value {
     let source = source.unwrap_or_else(|| message.clone());
 
     let message = traits::to_string(message).await?;
-    ergo_runtime::diagnostic! {
+    let mut diagnostic = ergo_runtime::diagnostic! {
         message: message
     }
     .add_value_info("source", &source)
+    .await;
+    if let Some(kind) = kind {
+        diagnostic = diagnostic.set_kind(kind.as_ref().as_str());
+    }
+    if let Some(fields) = fields {
+        for (k, v) in fields.into_owned().0 {
+            let k = Context::eval_as::<types::String>(k.into()).await?;
+            let v = field_value(v).await?;
+            diagnostic = diagnostic.add_field(k.as_ref().as_str(), v);
+        }
+    }
+    diagnostic.into_error().into()
+}
+
+/// Convert a value to a field value.
+async fn field_value(mut v: Value) -> ergo_runtime::Result<FieldValue> {
+    Context::eval(&mut v).await?;
+    let source = Source::get(&v);
+    Ok(match_value! { v,
+        types::String(s) => FieldValue::String(s),
+        types::Bool(b) => FieldValue::Bool(b),
+        n@types::Number {..} => FieldValue::Integer(
+            n.to_i64().add_primary_label(source.with("expected this to be an integer"))?,
+        ),
+        o => Err(traits::type_error(o, "String, Number, or Bool"))?
+    })
+}
+
+#[types::ergo_fn]
+/// Get the kind of an Error.
+///
+/// Arguments: `(Error :error)`
+///
+/// Returns the kind of the error as a String, or Unset if the error has no kind. If the error is
+/// an aggregate of multiple errors, the first kind is returned.
+///
+/// Kinds set by the standard library include:
+/// * `io:not-found`, `io:permission-denied`, `io:already-exists`, and `io` - filesystem and other
+///   io errors (with a `path` field when a path is involved),
+/// * `not-permitted` - the operation was not permitted by the current capabilities,
+/// * `exec:failed` - a child process exited unsuccessfully (with `command` and `exit-code` fields;
+///   errors spawning a child process have an io kind and a `program` field),
+/// * `net:request` and `net:status` - an http request failed, or returned an unsuccessful status
+///   (with `url` and `status-code` fields),
+/// * `lock:mismatch` - retrieved content did not match the lock file (with a `key` field),
+/// * `dry-run` - a script could not be loaded in dry-run mode (as it would have been created by a
+///   skipped side effect).
+async fn kind(error: _) -> Value {
+    match eval_error(error).await?.kind() {
+        Some(k) => types::String::from(k).into(),
+        None => types::Unset.into(),
+    }
+}
+
+#[types::ergo_fn]
+/// Get the structured fields of an Error.
+///
+/// Arguments: `(Error :error)`
+///
+/// Returns a Map of field names to values (Strings, Numbers, or Bools). If the error is an
+/// aggregate of multiple errors, the fields of all errors are merged (earlier errors taking
+/// precedence).
+async fn fields(error: _) -> Value {
+    let error = eval_error(error).await?;
+    let mut ret = BstMap::new();
+    for f in error.fields() {
+        let value: Value = match &f.value {
+            FieldValue::String(s) => types::String::from(s.clone()).into(),
+            FieldValue::Integer(i) => types::Number::from(*i).into(),
+            FieldValue::Bool(b) => types::Bool(*b).into(),
+        };
+        ret.insert(
+            crate::make_string_src(ARGS_SOURCE.with(f.name.as_str())),
+            Source::imbue(ARGS_SOURCE.with(value)),
+        );
+    }
+    types::Map(ret).into()
+}
+
+/// Evaluate a value which is expected to be an Error, without propagating the Error.
+async fn eval_error(mut v: Value) -> ergo_runtime::Result<types::Error> {
+    drop(Context::ignore_errors(Context::eval(&mut v)).await);
+    match v.as_type::<types::Error>() {
+        Ok(e) => Ok(e.into_owned()),
+        Err(v) => Err(traits::type_error_for::<types::Error>(v).into()),
+    }
+}
+
+/// Return whether an error kind matches any of the given kinds.
+///
+/// A kind matches if it is equal to a given kind, or if the given kind is a prefix of the kind
+/// followed by `:` (so `io` matches `io:not-found`).
+fn kind_matches(kind: &str, kinds: &[String]) -> bool {
+    kinds
+        .iter()
+        .any(|k| kind == k || (kind.starts_with(k.as_str()) && kind[k.len()..].starts_with(':')))
+}
+
+#[types::ergo_fn]
+/// Evaluate a value, handling errors.
+///
+/// Arguments: `:value (Function :handler)`
+///
+/// Keyed Arguments:
+/// * `:kind` - A String or Array of Strings of the error kinds to handle. A kind matches errors of
+///   the same kind or with the kind as a prefix followed by `:` (so `io` matches `io:not-found`).
+///   If omitted, all errors are handled.
+///
+/// If `value` evaluates to an Error which matches `kind`, returns the result of calling `handler`
+/// with the Error. Otherwise returns the evaluated value.
+async fn catch(mut value: _, handler: _, (kind): [_]) -> Value {
+    let kinds = match kind {
+        None => None,
+        Some(mut v) => {
+            Context::eval(&mut v).await?;
+            Some(match_value! { v,
+                types::String(s) => vec![s.into()],
+                types::Array(arr) => {
+                    let mut ret = Vec::new();
+                    for v in arr {
+                        let s = Context::eval_as::<types::String>(v).await?;
+                        ret.push(s.as_ref().as_str().to_owned());
+                    }
+                    ret
+                }
+                o => Err(traits::type_error(o, "String or Array"))?
+            })
+        }
+    };
+
+    // Do not propagate errors which will be handled.
+    let result = Context::ignore_errors(async {
+        drop(Context::eval(&mut value).await);
+        value
+    })
+    .await;
+
+    let err = match result.as_type::<types::Error>() {
+        Ok(e) => e.into_owned(),
+        Err(v) => return Ok(v),
+    };
+
+    let handled = match &kinds {
+        None => !err.is_aborted(),
+        Some(kinds) => err.kind().map(|k| kind_matches(k, kinds)).unwrap_or(false),
+    };
+    if !handled {
+        Context::with(|ctx| ctx.error_scope.error(&err));
+        return Ok(err.into());
+    }
+
+    traits::bind(
+        handler,
+        Source::imbue(
+            ARGS_SOURCE.with(
+                types::Args {
+                    args: types::args::Arguments::positional(vec![err.into()]).unchecked(),
+                }
+                .into(),
+            ),
+        ),
+    )
     .await
-    .into_error()
-    .into()
+}
+
+#[types::ergo_fn]
+/// Add context to errors.
+///
+/// Arguments: `:value (String :note)`
+///
+/// Keyed Arguments:
+/// * `String :kind` - If present, the kind to set on the errors.
+///
+/// If `value` evaluates to an Error, returns the Error with `note` added to each of its
+/// diagnostics. Otherwise returns the evaluated value.
+async fn wrap(mut value: _, note: types::String, (kind): [types::String]) -> Value {
+    let result = Context::ignore_errors(async {
+        drop(Context::eval(&mut value).await);
+        value
+    })
+    .await;
+
+    let err = match result.as_type::<types::Error>() {
+        Ok(e) => e.into_owned(),
+        Err(v) => return Ok(v),
+    };
+    if err.is_aborted() {
+        return Ok(err.into());
+    }
+
+    let mut diagnostics = Vec::new();
+    err.visit_diagnostics(|d| diagnostics.push(d.clone()));
+    let err: types::Error = diagnostics
+        .into_iter()
+        .map(|d: Diagnostic| {
+            let d = d.add_note(note.as_ref().as_str());
+            match &kind {
+                Some(k) => d.set_kind(k.as_ref().as_str()),
+                None => d,
+            }
+            .into_error()
+        })
+        .collect();
+    Context::with(|ctx| ctx.error_scope.error(&err));
+    err.into()
+}
+
+#[cfg(test)]
+mod test {
+    ergo_script::tests! {
+        fn kind(t) {
+            t.assert_eq("self:Error:kind <| self:Error:new ~kind=mine oops", "mine");
+            t.assert_eq("self:Error:kind <| self:Error:new oops", "$unset");
+            t.assert_eq("self:Error:kind <| self:fs:read does-not-exist", "io:not-found");
+        }
+
+        fn fields(t) {
+            t.assert_eq("self:Error:fields <| self:Error:new ~fields={a = 1, b = two} oops", "{a = 1, b = two}");
+        }
+
+        fn catch(t) {
+            t.assert_eq("self:Error:catch (self:Error:new ~kind=mine oops) (fn :e -> handled)", "handled");
+            t.assert_eq("self:Error:catch ~kind=io (self:fs:read does-not-exist) (fn :e -> self:Error:kind $e)", "io:not-found");
+            t.assert_eq("self:Error:catch ~kind=[other,mine] (self:Error:new ~kind=mine oops) (fn _ -> handled)", "handled");
+            t.assert_fail("self:Error:catch ~kind=other (self:Error:new ~kind=mine oops) (fn _ -> handled)");
+            t.assert_eq("self:Error:catch value (fn _ -> handled)", "value");
+        }
+
+        fn wrap(t) {
+            t.assert_fail("self:Error:wrap (self:Error:new oops) context");
+            t.assert_eq("self:Error:kind <| self:Error:wrap ~kind=mine (self:Error:new oops) context", "mine");
+            t.assert_eq("self:Error:wrap value context", "value");
+        }
+    }
 }
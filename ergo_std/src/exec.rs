@@ -8,6 +8,7 @@ use ergo_runtime::abi_stable::{
     StableAbi,
 };
 use ergo_runtime::{
+    context::{ChildUsage, TaskLog},
     depends,
     error::DiagnosticInfo,
     io::{self, Blocking},
@@ -53,6 +54,7 @@ pub struct Child {
     stdout: Value,
     stderr: Value,
     exit_status: Value,
+    usage: Value,
 }
 
 // We know that `Child` contains constant values that both will be unaffected by late bindings and
@@ -137,6 +139,12 @@ impl From<ExitStatus> for ergo_runtime::TypedValue<ExitStatus> {
 ///   * `:stdout`: The standard output `ByteStream` of the child.
 ///   * `:stderr`: The standard error `ByteStream` of the child.
 ///   * `:exit`: The exit status of the child (waiting for the child to terminate), as an `ExitStatus`.
+///   * `:usage`: The resource usage of the child (waiting for the child to terminate), as a `Map`
+///   with keys:
+///     * `wall` - the Number of seconds from spawning the child until it exited
+///     * `user` - (unix only) the Number of seconds of user CPU time
+///     * `system` - (unix only) the Number of seconds of system CPU time
+///     * `max-rss` - (unix only) the peak resident set size of the child, in bytes
 ///   * `:success`: A convenience index to wait for the child to complete, evaluating to `Unit` on
 ///   successful exit or an `Error` indicating the child's exit status, stderr, stdout, and command
 ///   line. __This is the nested value that is evaluated if a `Child` type is evaluated in sequence
@@ -149,6 +157,9 @@ impl From<ExitStatus> for ergo_runtime::TypedValue<ExitStatus> {
 /// The `ExitStatus` type can be converted to `Bool` to check whether the program returned a
 /// successful status. It can also be converted to `Number`; if the process exited as the result of
 /// a signal (on unix OSes), it converts to `-1`.
+///
+/// If the child is spawned within a `std:task` and task logs are enabled, the resource usage is
+/// also recorded in the task log.
 pub async fn function(
     cmd: _,
     (env): [types::Map],
@@ -262,6 +273,48 @@ pub async fn function(
         s
     };
 
+    crate::permitted(&ARGS_SOURCE, |c| c.check_exec(command.get_program()))?;
+
+    if crate::dry_run(format_args!("execute {}", &command_string)) {
+        // Return a placeholder child which has no output and exits successfully. The placeholder
+        // values have distinct identities so they are not confused with those of a real child.
+        let stdin = types::ergo_fn_value! {
+            #[depends(^CALL_DEPENDS.clone(), nsid!(exec::dry_run))]
+            /// Discard data sent to stdin (in dry-run mode).
+            ///
+            /// Arguments: `^:values`
+            async fn stdin(...) -> Value {
+                REST.by_ref().for_each(drop);
+                REST.unused_arguments()?;
+                types::Unit.into()
+            }
+        };
+        let output = |id| {
+            Value::with_id(
+                types::ByteStream::from(Vec::new()),
+                depends![dyn ^CALL_DEPENDS.clone(), nsid!(exec::dry_run), id],
+            )
+        };
+        let stdout = output(nsid!(exec::stdout));
+        let stderr = output(nsid!(exec::stderr));
+        return Ok(Value::with_id(
+            Child {
+                command_string: command_string.into(),
+                stdin,
+                stdout,
+                stderr,
+                exit_status: ergo_runtime::TypedValue::from(ExitStatus(ROption::RSome(0))).into(),
+                usage: usage_value(&ChildUsage::default()),
+            },
+            depends![dyn nsid!(exec::child), nsid!(exec::dry_run), ^CALL_DEPENDS],
+        ));
+    }
+
+    // Share task slots with the child process (for instance, `make` or `cargo`).
+    if let Some(jobserver) = Context::global().task.jobserver() {
+        jobserver.configure(&mut command);
+    }
+
     command.stdin(Stdio::piped());
     command.stdout(Stdio::piped());
     command.stderr(Stdio::piped());
@@ -273,9 +326,29 @@ pub async fn function(
 
     log.debug(format_args!("spawning child process: {}", &command_string));
 
+    let start = std::time::Instant::now();
     let mut child = command
         .spawn()
-        .add_primary_label(ARGS_SOURCE.with("while spawning this process"))?;
+        .add_primary_label(ARGS_SOURCE.with("while spawning this process"))
+        .add_field(
+            "program",
+            command.get_program().to_string_lossy().into_owned(),
+        )?;
+
+    // Copy the child output to the current task log, if any.
+    let task_log = TaskLog::current();
+    let output_logs = task_log.as_ref().map(|l| l.child_output(&command_string));
+    let (child_number, stdout_log, stderr_log) = match output_logs {
+        Some(Ok((n, stdout, stderr))) => (Some(n), Some(stdout), Some(stderr)),
+        Some(Err(e)) => {
+            log.warn(format_args!(
+                "failed to create task log output files: {}",
+                e
+            ));
+            (None, None, None)
+        }
+        None => (None, None, None),
+    };
 
     // Handle stdin
     let stdin = std::sync::Arc::new(Mutex::new(Some(Blocking::new(child.stdin.take().unwrap()))));
@@ -329,7 +402,7 @@ pub async fn function(
         let (send, recv) = io_pipe::pipe();
         let stdout = child.stdout.take().unwrap();
         (
-            move || send.send(stdout),
+            move || send.send(stdout, stdout_log),
             Value::with_id(
                 types::ByteStream::new(recv),
                 depends![dyn ^CALL_DEPENDS.clone(), nsid!(exec::stdout)],
@@ -342,7 +415,7 @@ pub async fn function(
         let (send, recv) = io_pipe::pipe();
         let stderr = child.stderr.take().unwrap();
         (
-            move || send.send(stderr),
+            move || send.send(stderr, stderr_log),
             Value::with_id(
                 types::ByteStream::new(recv),
                 depends![dyn ^CALL_DEPENDS.clone(), nsid!(exec::stderr)],
@@ -356,26 +429,36 @@ pub async fn function(
     // same thread.
     drop(Context::global().task.spawn_blocking(copy_stderr));
 
-    // Handle running the child and getting the exit status
+    // Handle running the child and getting the exit status and resource usage
     let cs = command_string.clone();
-    let exit_status = Context::global()
+    let exited = Context::global()
         .task
         .spawn_blocking(move || {
             copy_stdout();
-            let ret = child.wait().map_err(|e| {
+            let ret = wait(child, start).map_err(|e| {
                 ergo_runtime::error! {
                     labels: [ primary(ARGS_SOURCE.with("while waiting for process to exit")) ],
                     error: e
                 }
             });
             log.debug(format_args!("child process exited ({:?}): {}", ret, &cs));
+            if let (Ok((_, usage)), Some(task_log), Some(n)) = (&ret, task_log, child_number) {
+                task_log.write_child_usage(n, usage);
+            }
             ret
         })
         .map(|r| r.and_then(|v| v))
         .shared();
-    let exit_status = ergo_runtime::lazy_value! {
-        #![depends(dyn nsid!(exec::exit_status), ^CALL_DEPENDS.clone())]
-        ExitStatus::from(exit_status.await?).into()
+    let exit_status = {
+        let exited = exited.clone();
+        ergo_runtime::lazy_value! {
+            #![depends(dyn nsid!(exec::exit_status), ^CALL_DEPENDS.clone())]
+            ExitStatus::from(exited.await?.0).into()
+        }
+    };
+    let usage = ergo_runtime::lazy_value! {
+        #![depends(dyn nsid!(exec::usage), ^CALL_DEPENDS.clone())]
+        usage_value(&exited.await?.1)
     };
 
     Value::with_id(
@@ -385,11 +468,88 @@ pub async fn function(
             stdout,
             stderr,
             exit_status,
+            usage,
         },
         depends![dyn nsid!(exec::child), ^CALL_DEPENDS],
     )
 }
 
+/// Wait for a child to exit, returning the exit status and resource usage.
+#[cfg(unix)]
+fn wait(
+    child: std::process::Child,
+    start: std::time::Instant,
+) -> std::io::Result<(std::process::ExitStatus, ChildUsage)> {
+    use std::os::unix::process::ExitStatusExt;
+    let mut status = 0;
+    let mut rusage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    let pid = child.id() as libc::pid_t;
+    while unsafe { libc::wait4(pid, &mut status, 0, rusage.as_mut_ptr()) } == -1 {
+        let e: std::io::Error = errno::errno().into();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    let rusage = unsafe { rusage.assume_init() };
+    let time =
+        |t: libc::timeval| std::time::Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    // `ru_maxrss` is in bytes on macOS, and kilobytes elsewhere.
+    let max_rss = rusage.ru_maxrss as u64;
+    #[cfg(not(target_os = "macos"))]
+    let max_rss = max_rss * 1024;
+    Ok((
+        std::process::ExitStatus::from_raw(status),
+        ChildUsage {
+            wall: start.elapsed(),
+            user: Some(time(rusage.ru_utime)),
+            system: Some(time(rusage.ru_stime)),
+            max_rss: Some(max_rss),
+        },
+    ))
+}
+
+/// Wait for a child to exit, returning the exit status and resource usage.
+#[cfg(windows)]
+fn wait(
+    mut child: std::process::Child,
+    start: std::time::Instant,
+) -> std::io::Result<(std::process::ExitStatus, ChildUsage)> {
+    let status = child.wait()?;
+    Ok((
+        status,
+        ChildUsage {
+            wall: start.elapsed(),
+            ..Default::default()
+        },
+    ))
+}
+
+/// Create the `usage` index value of a Child.
+fn usage_value(usage: &ChildUsage) -> Value {
+    fn seconds(d: std::time::Duration) -> Value {
+        types::Number::from_f64(d.as_secs_f64())
+            .map(|n| n.into())
+            .unwrap_or(types::Unset.into())
+    }
+    let mut entries: Vec<(&str, Value)> = vec![("wall", seconds(usage.wall))];
+    if let Some(d) = usage.user {
+        entries.push(("user", seconds(d)));
+    }
+    if let Some(d) = usage.system {
+        entries.push(("system", seconds(d)));
+    }
+    if let Some(n) = usage.max_rss {
+        entries.push(("max-rss", types::Number::from(n).into()));
+    }
+    types::Map(
+        entries
+            .into_iter()
+            .map(|(k, v)| (types::String::from(k).into(), v))
+            .collect(),
+    )
+    .into()
+}
+
 #[cfg(unix)]
 fn disown_pgroup(cmd: &mut Command) {
     use std::os::unix::process::CommandExt;
@@ -428,12 +588,18 @@ mod io_pipe {
     }
 
     impl PipeSend {
-        pub fn send<R: std::io::Read>(&self, mut r: R) {
+        /// Send all data read from `r`, also writing it to `log` (if any).
+        pub fn send<R: std::io::Read>(&self, mut r: R, mut log: Option<std::fs::File>) {
             let mut buf = [0; 2048];
             loop {
                 match r.read(&mut buf) {
                     Ok(0) => break,
                     Ok(s) => {
+                        if let Some(f) = &mut log {
+                            if std::io::Write::write_all(f, &buf[..s]).is_err() {
+                                log = None;
+                            }
+                        }
                         if self
                             .send
                             .unbounded_send(Ok(buf[..s].to_vec().into_boxed_slice()))
@@ -533,6 +699,7 @@ ergo_runtime::type_system::ergo_traits_fn! {
                 "stdout" => self.stdout.clone(),
                 "stderr" => self.stderr.clone(),
                 "exit" => self.exit_status.clone(),
+                "usage" => self.usage.clone(),
                 "success" => {
                     let command_string = self.command_string.clone();
                     let stdout = self.stdout.clone();
@@ -547,7 +714,7 @@ ergo_runtime::type_system::ergo_traits_fn! {
                             let stdout = traits::into::<types::String>(stdout).await?;
                             let stderr = traits::into::<types::String>(stderr).await?;
 
-                            ergo_runtime::error! {
+                            let mut d = ergo_runtime::diagnostic! {
                                 labels: [primary(src.with(""))],
                                 notes: [
                                     format_args!("command was: {}", command_string),
@@ -555,15 +722,21 @@ ergo_runtime::type_system::ergo_traits_fn! {
                                     format_args!("stdout was: {}", stdout.as_ref()),
                                     format_args!("stderr was: {}", stderr.as_ref())
                                 ],
-                                error: "command returned failure exit status"
-                            }.into()
+                                message: "command returned failure exit status"
+                            }
+                            .set_kind("exec:failed")
+                            .add_field("command", command_string.as_str());
+                            if let ROption::RSome(code) = exit_status.as_ref().0 {
+                                d = d.add_field("exit-code", code);
+                            }
+                            d.into_error().into()
                         }
                     }
                 }
                 _ => {
                     ergo_runtime::error! {
                         labels: [ primary(src.with("")) ],
-                        notes: [ "supported indices: `stdin`, `stdout`, `stderr`, `exit`, `success`" ],
+                        notes: [ "supported indices: `stdin`, `stdout`, `stderr`, `exit`, `usage`, `success`" ],
                         error: "unrecognized Child index"
                     }.into()
                 }
@@ -624,6 +797,11 @@ mod test {
             t.assert_eq("self:Bool:from <| self:exec true", "self:Bool:true");
         }
 
+        fn usage(t) {
+            t.assert_eq("usage = self:exec true |>:usage; self:Number:compare usage:wall (self:Number:from 0)", "self:Order:greater");
+            t.assert_eq("usage = self:exec true |>:usage; self:Number:compare usage:max-rss (self:Number:from 0)", "self:Order:greater");
+        }
+
         fn exec_unset_args(t) {
             t.assert_eq("self:String:from <| self:exec echo $unset hello $unset world |>:stdout", "\"hello world\\n\"");
             t.assert_fail("self:exec $unset echo |>:success");
@@ -649,4 +827,16 @@ mod test {
             t.assert_eq("self:String:from <| self:exec echo abc", "\"abc\\n\"");
         }
     }
+
+    #[test]
+    fn exec_dry_run() {
+        let t = ergo_script::testing::Test::dry_run(crate::_ergo_plugin);
+        let file = std::env::temp_dir().join(format!("ergo-exec-dry-run-{}", std::process::id()));
+        let touch = format!("self:exec touch \"{}\"", file.display());
+        t.assert_eq(&format!("{} |>:success", touch), "()");
+        t.assert_eq(&format!("self:String:from <| {} |>:stdout", touch), "\"\"");
+        t.assert_eq(&format!("self:String:from <| {} |>:stderr", touch), "\"\"");
+        t.assert_eq(&format!("self:Bool:from <| {}", touch), "self:Bool:true");
+        assert!(!file.exists());
+    }
 }
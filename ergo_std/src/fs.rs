@@ -19,17 +19,24 @@ pub fn module() -> Value {
     crate::make_string_map! {
         "append" = append(),
         "archive" = archive(),
+        "chmod" = chmod(),
         "copy" = copy(),
         "create-dir" = create_dir(),
         "file-size" = file_size(),
         "file-type" = file_type(),
         "glob" = glob_(),
+        "hardlink" = hardlink(),
         "lock" = lock(),
+        "metadata" = metadata(),
         "read" = read(),
+        "read-dir" = read_dir(),
         "read-link" = read_link(),
         "remove" = remove(),
         "rename" = rename(),
+        "set-permissions" = set_permissions_(),
         "sha1" = sha1(),
+        "symlink" = symlink(),
+        "touch" = touch(),
         "track" = track(),
         "unarchive" = unarchive(),
         "write" = write()
@@ -72,6 +79,17 @@ async fn glob_(pattern: _) -> Value {
         .unwrap_or_else(|| std::env::current_dir().unwrap());
     path.push(pattern.as_ref().as_str());
 
+    // Reading the directories searched by the glob must be permitted.
+    let base: std::path::PathBuf = path
+        .components()
+        .take_while(|c| {
+            !c.as_os_str()
+                .to_string_lossy()
+                .contains(|ch| matches!(ch, '*' | '?' | '['))
+        })
+        .collect();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(&base))?;
+
     match glob(path.to_str().unwrap()) {
         Err(e) => pattern_source.with(e).into_error().into(),
         Ok(paths) => {
@@ -84,6 +102,8 @@ async fn glob_(pattern: _) -> Value {
                     }
                 })?
                 .into_iter()
+                // Omit paths which may not be read.
+                .filter(|v| Context::with(|ctx| ctx.capabilities.allows_read(v)))
                 .map(|v| Source::imbue(ARGS_SOURCE.clone().with(types::Path::from(v).into())))
                 .collect();
             types::Array(paths.into()).into()
@@ -294,6 +314,12 @@ async fn copy(from: types::Path, to: types::Path, (shallow): [_], (follow_symlin
 
     let from = from.as_ref().as_ref();
     let to = to.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(&from))?;
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&to))?;
+
+    if crate::dry_run(format_args!("copy {} to {}", from.display(), to.display())) {
+        return Ok(types::Unit.into());
+    }
 
     // Special handling for the case where you are creating a symlink with a path relative to the
     // target, assuming it is a file rather than a directory (relevant on windows).
@@ -326,6 +352,7 @@ async fn copy(from: types::Path, to: types::Path, (shallow): [_], (follow_symlin
 ///
 /// If the path does not exist or the user does not have permission to the path, returns `Unset`.
 async fn file_size(path: types::Path) -> Value {
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(path.as_ref().as_ref()))?;
     std::fs::symlink_metadata(path.as_ref().as_ref())
         .map(|m| types::Number::from(m.len()).into())
         .unwrap_or(types::Unset.into())
@@ -338,6 +365,7 @@ async fn file_size(path: types::Path) -> Value {
 ///
 /// If the link does not exist or the user does not have permission to the path, returns `Unset`.
 async fn read_link(path: types::Path) -> Value {
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(path.as_ref().as_ref()))?;
     std::fs::read_link(path.as_ref().as_ref())
         .map(|p| types::Path::from(p).into())
         .unwrap_or(types::Unset.into())
@@ -356,22 +384,349 @@ async fn read_link(path: types::Path) -> Value {
 ///
 /// If the path does not exist or the user does not have permission to the path, returns `Unset`.
 async fn file_type(path: types::Path) -> Value {
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(path.as_ref().as_ref()))?;
     std::fs::symlink_metadata(path.as_ref().as_ref())
-        .map(|m| {
-            types::String::from(if m.file_type().is_dir() {
-                "directory"
-            } else if m.file_type().is_file() {
-                "file"
-            } else if m.file_type().is_symlink() {
-                "symlink"
-            } else {
-                "other"
-            })
-            .into()
-        })
+        .map(|m| types::String::from(file_type_name(m.file_type())).into())
         .unwrap_or(types::Unset.into())
 }
 
+fn file_type_name(ft: std::fs::FileType) -> &'static str {
+    if ft.is_dir() {
+        "directory"
+    } else if ft.is_file() {
+        "file"
+    } else if ft.is_symlink() {
+        "symlink"
+    } else {
+        "other"
+    }
+}
+
+#[types::ergo_fn]
+#[eval_for_id]
+/// Return metadata of the given path.
+///
+/// Arguments: `(Path :path)`
+///
+/// Keyed Arguments:
+/// * `:follow-symlinks` - If present, follow symlinks (returning the metadata of the target).
+///
+/// Returns a Map with the following keys:
+/// * `type` - the file type, as returned by `file-type`
+/// * `size` - the file size in bytes
+/// * `mtime` - the modification time as a Number of seconds since the unix epoch
+/// * `readonly` - a Bool indicating whether the path is read-only
+/// * `mode` - (unix only) the permission bits of the path as a Number
+/// * `uid` - (unix only) the user id of the owner
+/// * `gid` - (unix only) the group id of the owner
+///
+/// The result depends on the state of the filesystem when it is evaluated, so it is never cached
+/// by the arguments alone.
+///
+/// If the path does not exist or the user does not have permission to the path, returns `Unset`.
+async fn metadata(path: types::Path, (follow_symlinks): [_]) -> Value {
+    let path = path.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(&path))?;
+    let meta = if follow_symlinks.is_some() {
+        std::fs::metadata(path)
+    } else {
+        std::fs::symlink_metadata(path)
+    };
+    let meta = match meta {
+        Ok(m) => m,
+        Err(_) => return Ok(types::Unset.into()),
+    };
+
+    let mtime: Value = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::SystemTime::UNIX_EPOCH).ok())
+        .and_then(|d| types::Number::from_f64(d.as_secs_f64()))
+        .map(|n| n.into())
+        .unwrap_or(types::Unset.into());
+
+    #[allow(unused_mut)]
+    let mut entries: Vec<(&str, Value)> = vec![
+        (
+            "type",
+            types::String::from(file_type_name(meta.file_type())).into(),
+        ),
+        ("size", types::Number::from(meta.len()).into()),
+        ("mtime", mtime),
+        (
+            "readonly",
+            types::Bool(meta.permissions().readonly()).into(),
+        ),
+    ];
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        entries.push(("mode", types::Number::from(meta.mode() & 0o7777).into()));
+        entries.push(("uid", types::Number::from(meta.uid()).into()));
+        entries.push(("gid", types::Number::from(meta.gid()).into()));
+    }
+
+    let ret: Value = types::Map(
+        entries
+            .into_iter()
+            .map(|(k, v)| {
+                (
+                    crate::make_string_src(ARGS_SOURCE.clone().with(k)),
+                    Source::imbue(ARGS_SOURCE.clone().with(v)),
+                )
+            })
+            .collect(),
+    )
+    .into();
+    ret
+}
+
+#[types::ergo_fn]
+#[eval_for_id]
+/// Read the entries of a directory.
+///
+/// Arguments: `(Path :dir)`
+///
+/// Returns an `Iter` of the Paths of each entry in `dir` (not including `.` and `..`). The
+/// directory is read lazily as the Iter is consumed, so very large directories need not be held
+/// in memory. Entries are returned in an unspecified (platform-dependent) order.
+///
+/// The Iter identity incorporates the modification time of `dir`, so adding or removing entries
+/// will change the identity.
+async fn read_dir(dir: types::Path) -> Value {
+    let path = dir.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(&path))?;
+    let (entries, modified) = ergo_runtime::error_info!(
+        notes: [format_args!("path was {}", path.display())],
+        fields: ["path" = path.display().to_string()],
+        {
+            let modified = std::fs::metadata(path)?
+                .modified()?
+                .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default();
+            ergo_runtime::Result::Ok((std::fs::read_dir(path)?, modified))
+        }
+    )
+    .add_primary_label(Source::get(&dir).with("while reading this directory"))?;
+
+    let src = ARGS_SOURCE.clone();
+    let dir_path = path.to_owned();
+    types::Iter::new_iter(
+        entries.map(move |entry| {
+            Source::imbue(
+                src.clone().with(match entry {
+                    Ok(entry) => types::Path::from(entry.path()).into(),
+                    Err(e) => ergo_runtime::error! {
+                        labels: [ primary(src.clone().with("while reading this directory")) ],
+                        notes: [ format_args!("path was {}", dir_path.display()) ],
+                        error: e
+                    }
+                    .into(),
+                }),
+            )
+        }),
+        depends![dyn ^CALL_DEPENDS, modified],
+    )
+    .into()
+}
+
+fn parse_mode(v: Value) -> ergo_runtime::Result<u32> {
+    let source = Source::get(&v);
+    match_value! { v,
+        n@types::Number {..} => n.to_u32().ok_or_else(|| source.with("expected an unsigned integer").into_error()),
+        types::String(s) => u32::from_str_radix(s.as_str(), 8).map_err(|e| source.with(e).into_error()),
+        o => Err(traits::type_error(o, "Number or String").into())
+    }
+}
+
+#[types::ergo_fn]
+/// Set the permissions of a file or directory.
+///
+/// Arguments: `(Path :path)`
+///
+/// Keyed Arguments:
+/// * `:mode` - (unix only) the permission bits to set, as a Number or a String of octal digits
+///   (e.g. `755`).
+/// * `Into<Bool> :readonly` - whether the path should be read-only. This is applied after `mode`.
+///
+/// Returns a Unit value on success.
+async fn set_permissions_(path: types::Path, (mode): [_], (readonly): [_]) -> Value {
+    let mode = match mode {
+        None => None,
+        Some(mut v) => {
+            Context::eval(&mut v).await?;
+            Some(parse_mode(v)?)
+        }
+    };
+    let readonly = match readonly {
+        None => None,
+        Some(v) => Some(traits::into::<types::Bool>(v).await?.as_ref().0),
+    };
+
+    let p = path.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&p))?;
+    if crate::dry_run(format_args!("set permissions of {}", p.display())) {
+        return Ok(types::Unit.into());
+    }
+    ergo_runtime::error_info!(
+        notes: [format_args!("path was {}", p.display())],
+        fields: ["path" = p.display().to_string()],
+        {
+            let mut permissions = std::fs::metadata(p)?.permissions();
+            if let Some(mode) = mode {
+                set_permissions(&mut permissions, mode);
+            }
+            if let Some(readonly) = readonly {
+                permissions.set_readonly(readonly);
+            }
+            std::fs::set_permissions(p, permissions)
+        }
+    )?;
+    types::Unit.into()
+}
+
+#[types::ergo_fn]
+/// Change the permission bits of a file or directory.
+///
+/// Arguments: `(Path :path) :mode`
+///
+/// `mode` may be a Number or a String of octal digits (e.g. `755`). This is equivalent to
+/// `set-permissions :path ~mode=:mode`, and does nothing on platforms without unix permissions.
+///
+/// Returns a Unit value on success.
+async fn chmod(path: types::Path, mut mode: _) -> Value {
+    Context::eval(&mut mode).await?;
+    let mode = parse_mode(mode)?;
+
+    let p = path.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&p))?;
+    if crate::dry_run(format_args!("chmod {:o} {}", mode, p.display())) {
+        return Ok(types::Unit.into());
+    }
+    ergo_runtime::error_info!(
+        notes: [format_args!("path was {}", p.display())],
+        fields: ["path" = p.display().to_string()],
+        {
+            let mut permissions = std::fs::metadata(p)?.permissions();
+            set_permissions(&mut permissions, mode);
+            std::fs::set_permissions(p, permissions)
+        }
+    )?;
+    types::Unit.into()
+}
+
+#[cfg(unix)]
+fn set_modified_now(f: &std::fs::File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    // A null `times` argument sets both access and modification times to the current time.
+    if unsafe { libc::futimens(f.as_raw_fd(), std::ptr::null()) } == -1 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn set_modified_now(f: &std::fs::File) -> std::io::Result<()> {
+    // Resizing a file to its current length updates the last write time.
+    f.set_len(f.metadata()?.len())
+}
+
+#[types::ergo_fn]
+/// Create a file or update its modification time.
+///
+/// Arguments: `(Path :file)`
+///
+/// If `file` does not exist, it is created (empty). Otherwise the access and modification times
+/// are set to the current time. Parent directories are _not_ created.
+///
+/// Returns a Unit value on success.
+async fn touch(file: types::Path) -> Value {
+    let p = file.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&p))?;
+    if crate::dry_run(format_args!("touch {}", p.display())) {
+        return Ok(types::Unit.into());
+    }
+    ergo_runtime::error_info!(
+        notes: [format_args!("path was {}", p.display())],
+        fields: ["path" = p.display().to_string()],
+        {
+            let f = std::fs::OpenOptions::new().create(true).append(true).open(p)?;
+            set_modified_now(&f)
+        }
+    )?;
+    types::Unit.into()
+}
+
+#[types::ergo_fn]
+/// Create a symbolic link.
+///
+/// Arguments: `(Path :original) (Path :link)`
+///
+/// Creates a symbolic link at `link` which points to `original`. `original` may be relative, in
+/// which case it is interpreted relative to the directory containing `link` (as is the case with
+/// all symbolic links). If `link` already exists, an `Error` is returned.
+///
+/// Returns a Unit value on success.
+async fn symlink(original: types::Path, link: types::Path) -> Value {
+    let original = original.as_ref().as_ref();
+    let link = link.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&link))?;
+    if crate::dry_run(format_args!(
+        "create symbolic link pointing to {} at {}",
+        original.display(),
+        link.display()
+    )) {
+        return Ok(types::Unit.into());
+    }
+    let target = match link.parent() {
+        Some(parent) if original.is_relative() => parent.join(original),
+        _ => original.to_owned(),
+    };
+    if target.is_dir() {
+        symlink_dir(original, link)
+    } else {
+        symlink_file(original, link)
+    }
+    .add_note(format_args!(
+        "while creating symbolic link pointing to {} at {}",
+        original.display(),
+        link.display()
+    ))?;
+    types::Unit.into()
+}
+
+#[types::ergo_fn]
+/// Create a hard link.
+///
+/// Arguments: `(Path :original) (Path :link)`
+///
+/// Creates a hard link at `link` to the existing file `original`. If `link` already exists or
+/// the paths are on separate filesystems, an `Error` is returned.
+///
+/// Returns a Unit value on success.
+async fn hardlink(original: types::Path, link: types::Path) -> Value {
+    let original = original.as_ref().as_ref();
+    let link = link.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(&original))?;
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&link))?;
+    if crate::dry_run(format_args!(
+        "create hard link of {} at {}",
+        original.display(),
+        link.display()
+    )) {
+        return Ok(types::Unit.into());
+    }
+    std::fs::hard_link(original, link).add_note(format_args!(
+        "while creating a hard link of {} at {}",
+        original.display(),
+        link.display()
+    ))?;
+    types::Unit.into()
+}
+
 #[types::ergo_fn]
 /// Create a directory (and all ancestor directories).
 ///
@@ -379,6 +734,10 @@ async fn file_type(path: types::Path) -> Value {
 ///
 /// Returns a `Unit` value that creates the directory and ancestors if they do not exist.
 async fn create_dir(path: types::Path) -> Value {
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(path.as_ref().as_ref()))?;
+    if crate::dry_run(format_args!("create directory {}", path.as_ref().display())) {
+        return Ok(types::Unit.into());
+    }
     std::fs::create_dir_all(path.as_ref().as_ref())
         .add_note(format_args!("directory was {}", path.as_ref().display()))?;
     types::Unit.into()
@@ -400,8 +759,8 @@ fn set_permissions(_p: &mut std::fs::Permissions, _mode: u32) {}
 ///
 /// Keyed Arguments:
 /// * `String :format` - the type of archive to create. May be `dir`, `zip`, `tar`, `tar.gz`,
-/// `tar.bz2` or `tar.xz`. If unspecified, the extension of `archive` is used. If there is no
-/// extension, `dir` is assumed.
+/// `tar.bz2`, `tar.xz` or `tar.zst`. If unspecified, the extension of `archive` is used. If there
+/// is no extension, `dir` is assumed.
 ///
 /// Note that using a `dir` archive is the same as `std:fs:copy :source :archive`.
 ///
@@ -430,10 +789,12 @@ async fn archive(archive: types::Path, source: types::Path, (format): [types::St
                         archive_source.with(match s.rfind('.') {
                             None => "dir".into(),
                             Some(i) => {
-                                // Look for the name ending in one of the supported extensions; splitting
-                                // on `.` is not convenient if the filename contains other `.`s.
+                                // Look for the name ending in one of the supported extensions;
+                                // splitting on `.` is not convenient if the filename contains
+                                // other `.`s.
                                 let mut ret = None;
-                                let exts = &[".zip", ".tar", ".tar.gz", ".tar.bz2", ".tar.xz"];
+                                let exts =
+                                    &[".zip", ".tar", ".tar.gz", ".tar.bz2", ".tar.xz", ".tar.zst"];
                                 for ext in exts {
                                     if s.ends_with(ext) {
                                         ret = Some(ext[1..].into());
@@ -451,6 +812,18 @@ async fn archive(archive: types::Path, source: types::Path, (format): [types::St
 
     let (ext_source, ext) = ext.take();
 
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(source.as_ref().as_ref()))?;
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(archive.as_ref().as_ref()))?;
+
+    if crate::dry_run(format_args!(
+        "create {} archive {} of {}",
+        ext,
+        archive.as_ref().display(),
+        source.as_ref().display()
+    )) {
+        return Ok(types::Unit.into());
+    }
+
     fn tar_to<W: std::io::Write>(w: W, dir: &Path) -> std::io::Result<W> {
         let mut builder = tar::Builder::new(w);
         builder.mode(tar::HeaderMode::Deterministic);
@@ -554,6 +927,12 @@ async fn archive(archive: types::Path, source: types::Path, (format): [types::St
                 .map(|_| ())
                 .into_diagnostic()
         }
+        "tar.zst" => std::fs::File::create(archive.as_ref().as_ref())
+            .and_then(|p| zstd::stream::write::Encoder::new(p, 0))
+            .and_then(|e| tar_to(e, &source.as_ref().as_ref()))
+            .and_then(|e| e.finish())
+            .map(|_| ())
+            .into_diagnostic(),
         o => Err(Diagnostic::from(format!("unsupported format: {}", o))
             .add_primary_label(ext_source.with("")))?,
     };
@@ -578,14 +957,31 @@ async fn archive(archive: types::Path, source: types::Path, (format): [types::St
 /// Arguments: `(Path :destination) (PathOrByteStream :archive)`
 ///
 /// `archive` may be a Path to a directory, zip file or tar archive, or a ByteStream of a zip file
-/// or tar archive, where tar archives can optionally be compressed with gzip, bzip2, or lzma (xz).
-/// The archive contents are extracted into `destination` as a directory.
-async fn unarchive(destination: types::Path, mut archive: _) -> Value {
+/// or tar archive, where tar archives can optionally be compressed with gzip, bzip2, lzma (xz), or
+/// zstd. The format is detected from the leading bytes of the archive. The archive contents are
+/// extracted into `destination` as a directory.
+///
+/// If `archive` is compressed but does not contain a tar archive (for instance, a `.bz2` file), it
+/// is decompressed to `destination` as a file.
+///
+/// Keyed Arguments:
+/// * `String :lock` - if present, the content of `archive` (which must not be a directory) is
+///   verified against the lock file entry with the given key (recording the entry if it is
+///   missing).
+async fn unarchive(destination: types::Path, mut archive: _, (lock): [types::String]) -> Value {
     let to_path = destination.as_ref().as_ref();
 
     Context::eval(&mut archive).await?;
     let archive_source = Source::get(&archive);
 
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&to_path))?;
+    if let Some(p) = archive.as_ref::<types::Path>() {
+        crate::permitted(&ARGS_SOURCE, |c| c.check_read(p.as_ref()))?;
+    }
+    if crate::dry_run(format_args!("extract archive to {}", to_path.display())) {
+        return Ok(types::Unit.into());
+    }
+
     use std::io::{Read, Seek};
 
     fn extract_to<T: Read + Seek, P: AsRef<Path>>(
@@ -624,27 +1020,27 @@ async fn unarchive(destination: types::Path, mut archive: _) -> Value {
                 }
             }
         } else {
-            // TODO check whether file is a tar archive (need to buffer and seek into output
-            // stream)
-
             use tar::Archive;
-            // gzip
-            if bytes >= 2 && magic[0..2] == [0x1f, 0x8b] {
-                use flate2::read::GzDecoder;
-                Archive::new(GzDecoder::new(archive)).unpack(to_path)?;
-            }
-            // bzip2
-            else if bytes >= 3 && magic[0..3] == [b'B', b'Z', b'h'] {
-                use bzip2::read::BzDecoder;
-                Archive::new(BzDecoder::new(archive)).unpack(to_path)?;
+            let (format, mut reader) = crate::compress::sync_decoder(archive)?;
+            match format {
+                // Uncompressed data is assumed to be a tar archive.
+                None => Archive::new(reader).unpack(to_path)?,
+                Some(_) => {
+                    // Compressed data may be a tar archive or a single compressed file, which is
+                    // decompressed to `to_path`.
+                    let mut header = Vec::with_capacity(512);
+                    (&mut reader).take(512).read_to_end(&mut header)?;
+                    let is_tar = is_tar_header(&header);
+                    let mut reader = std::io::Cursor::new(header).chain(reader);
+                    if is_tar {
+                        Archive::new(reader).unpack(to_path)?;
+                    } else {
+                        let mut to_file = std::fs::File::create(to_path)
+                            .add_note(format_args!("path was {}", to_path.display()))?;
+                        std::io::copy(&mut reader, &mut to_file)?;
+                    }
+                }
             }
-            // lzma
-            else if bytes >= 6 && magic[0..6] == [0xfd, b'7', b'z', b'X', b'Z', 0] {
-                use xz::read::XzDecoder;
-                Archive::new(XzDecoder::new(archive)).unpack(to_path)?;
-            } else {
-                Archive::new(archive).unpack(to_path)?;
-            };
         }
 
         Ok(())
@@ -654,6 +1050,10 @@ async fn unarchive(destination: types::Path, mut archive: _) -> Value {
         p@types::Path { .. } => {
             let path = p.as_ref();
             if path.is_dir() {
+                if lock.is_some() {
+                    Err(Diagnostic::from("cannot lock the content of a directory")
+                        .add_primary_label(archive_source.with("")))?;
+                }
                 let path_c: std::path::PathBuf = path.clone().into();
                 let to_path_c: std::path::PathBuf = to_path.clone().into();
                 Context::global().task.spawn_blocking(move ||
@@ -663,9 +1063,13 @@ async fn unarchive(destination: types::Path, mut archive: _) -> Value {
                     .add_note(format_args!("source path was {}", path.display()))
                     .add_note(format_args!("target path was {}", to_path.display()))?;
             } else if path.is_file() {
-                let f = std::fs::File::open(&path)
+                let open = || std::fs::File::open(&path)
                             .add_note(format_args!("archive path was {}", path.display()))
-                            .add_primary_label(archive_source.with("while opening this archive"))?;
+                            .add_primary_label(archive_source.with("while opening this archive"));
+                if let Some(key) = &lock {
+                    crate::locked(&archive_source, key.as_ref().as_str(), open()?)?;
+                }
+                let f = open()?;
                 let to_path_c: std::path::PathBuf = to_path.clone().into();
                 Context::global().task.spawn_blocking(move || extract_to(f, to_path_c)).await?
                     .add_note(format_args!("archive path was {}", path.display()))
@@ -682,6 +1086,9 @@ async fn unarchive(destination: types::Path, mut archive: _) -> Value {
             use futures::io::AsyncReadExt;
             bs.read().read_to_end(&mut data).await
                 .add_primary_label(archive_source.with("while reading this byte stream"))?;
+            if let Some(key) = &lock {
+                crate::locked(&archive_source, key.as_ref().as_str(), data.as_slice())?;
+            }
             let to_path_c: std::path::PathBuf = to_path.clone().into();
             Context::global().task.spawn_blocking(move || extract_to(std::io::Cursor::new(data), to_path_c)).await?
                 .add_note(format_args!("target path was {}", to_path.display()))
@@ -693,6 +1100,38 @@ async fn unarchive(destination: types::Path, mut archive: _) -> Value {
     types::Unit.into()
 }
 
+/// Return whether the given bytes are a tar header block.
+///
+/// The header checksum is validated (rather than the `ustar` magic string, which v7 tar archives
+/// lack).
+fn is_tar_header(header: &[u8]) -> bool {
+    if header.len() != 512 {
+        return false;
+    }
+    // The checksum is an octal number terminated by a NUL or space.
+    let checksum = &header[148..156];
+    let digits = checksum
+        .iter()
+        .skip_while(|b| **b == b' ')
+        .take_while(|b| (b'0'..=b'7').contains(*b));
+    let mut expected: u32 = 0;
+    let mut count = 0;
+    for d in digits {
+        expected = expected * 8 + (d - b'0') as u32;
+        count += 1;
+    }
+    if count == 0 {
+        return false;
+    }
+    // The checksum is computed with the checksum field set to spaces.
+    let actual: u32 = header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (if (148..156).contains(&i) { b' ' } else { *b }) as u32)
+        .sum();
+    actual == expected
+}
+
 #[types::ergo_fn]
 /// Check the sha1sum of a file.
 ///
@@ -700,6 +1139,7 @@ async fn unarchive(destination: types::Path, mut archive: _) -> Value {
 ///
 /// Returns a boolean indicating whether `sum` is the sha1 sum of the contents of `file`.
 async fn sha1(file: types::Path, sum: types::String) -> Value {
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(file.as_ref().as_ref()))?;
     let mut f = std::fs::File::open(file.as_ref().as_ref())
         .add_note(format_args!("path was {}", file.as_ref().display()))?;
     let mut digest = Sha1::default();
@@ -728,6 +1168,9 @@ async fn track(file: _, (force_check): [_]) -> Value {
         .into_owned()
         .into_pathbuf();
     let force_check = force_check.is_some();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(&file))?;
+
+    Context::global().input_paths().add(&file);
 
     let loaded_info = Context::global().shared_state.get(|| {
         let std_dir = Context::global().env.project_directory().join("std");
@@ -746,8 +1189,10 @@ async fn track(file: _, (force_check): [_]) -> Value {
                 content_hash,
                 ..
             }) if !force_check => *content_hash,
-            other => {
-                ergo_runtime::error_info!(notes: [format_args!("path was {}", file.display())], {
+            other => ergo_runtime::error_info!(
+                notes: [format_args!("path was {}", file.display())],
+                fields: ["path" = file.display().to_string()],
+                {
                     let meta = std::fs::metadata(&file)?;
                     let modification_time = meta.modified()?;
 
@@ -770,8 +1215,8 @@ async fn track(file: _, (force_check): [_]) -> Value {
                         inner.computed_content_hash = true;
                         inner.content_hash
                     })
-                })?
-            }
+                }
+            )?,
         }
     };
 
@@ -937,14 +1382,22 @@ impl Drop for LoadedTrackInfo {
 /// If the path does not exist, nothing happens.
 async fn remove(path: types::Path) -> Value {
     let path = path.as_ref().as_ref();
-    ergo_runtime::error_info!(notes: [format_args!("path was {}", path.display())], {
-        if path.is_file() {
-            std::fs::remove_file(&path)?;
-        } else if path.is_dir() {
-            std::fs::remove_dir_all(&path)?;
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&path))?;
+    if crate::dry_run(format_args!("remove {}", path.display())) {
+        return Ok(types::Unit.into());
+    }
+    ergo_runtime::error_info!(
+        notes: [format_args!("path was {}", path.display())],
+        fields: ["path" = path.display().to_string()],
+        {
+            if path.is_file() {
+                std::fs::remove_file(&path)?;
+            } else if path.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            }
+            ergo_runtime::Result::Ok(())
         }
-        ergo_runtime::Result::Ok(())
-    })?;
+    )?;
     types::Unit.into()
 }
 
@@ -960,6 +1413,15 @@ async fn remove(path: types::Path) -> Value {
 async fn rename(from: types::Path, to: types::Path) -> Value {
     let from = from.as_ref().as_ref();
     let to = to.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&from))?;
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&to))?;
+    if crate::dry_run(format_args!(
+        "rename {} to {}",
+        from.display(),
+        to.display()
+    )) {
+        return Ok(types::Unit.into());
+    }
     ergo_runtime::error_info!(
         notes: [
             format_args!("`from` was {}", from.display()),
@@ -978,14 +1440,20 @@ async fn rename(from: types::Path, to: types::Path) -> Value {
 /// Returns a `ByteStream` of the file's contents.
 async fn read(file: types::Path) -> Value {
     let path = file.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_read(&path))?;
+    Context::global().input_paths().add(path);
     // TODO don't hash here, leave that to the script writer?
-    ergo_runtime::error_info!(notes: [format_args!("path was {}", path.display())], {
-        let hash = ergo_runtime::hash::hash_read(std::fs::File::open(&path)?)?;
-        ergo_runtime::Result::Ok(Value::with_id(
-            types::ByteStream::new(io::Blocking::new(std::fs::File::open(&path)?)),
-            depends![const hash],
-        ))
-    })?
+    ergo_runtime::error_info!(
+        notes: [format_args!("path was {}", path.display())],
+        fields: ["path" = path.display().to_string()],
+        {
+            let hash = ergo_runtime::hash::hash_read(std::fs::File::open(&path)?)?;
+            ergo_runtime::Result::Ok(Value::with_id(
+                types::ByteStream::new(io::Blocking::new(std::fs::File::open(&path)?)),
+                depends![const hash],
+            ))
+        }
+    )?
 }
 
 #[types::ergo_fn]
@@ -997,9 +1465,14 @@ async fn read(file: types::Path) -> Value {
 async fn write(file: types::Path, bytes: _) -> Value {
     let bytes = traits::into::<types::ByteStream>(bytes).await?;
     let path = file.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&path))?;
+    if crate::dry_run(format_args!("write {}", path.display())) {
+        return Ok(types::Unit.into());
+    }
 
     ergo_runtime::error_info!(
         notes: [format_args!("target path was {}", path.display())],
+        fields: ["path" = path.display().to_string()],
         async {
             let mut f = io::Blocking::new(std::fs::File::create(&path)?);
             ergo_runtime::io::copy(&mut bytes.as_ref().read(), &mut f).await
@@ -1016,9 +1489,14 @@ async fn write(file: types::Path, bytes: _) -> Value {
 async fn append(file: types::Path, bytes: _) -> Value {
     let bytes = traits::into::<types::ByteStream>(bytes).await?;
     let path = file.as_ref().as_ref();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(&path))?;
+    if crate::dry_run(format_args!("append to {}", path.display())) {
+        return Ok(types::Unit.into());
+    }
 
     ergo_runtime::error_info!(
         notes: [format_args!("target path was {}", path.display())],
+        fields: ["path" = path.display().to_string()],
         async {
             let mut f = io::Blocking::new(
                 std::fs::OpenOptions::new()
@@ -1056,6 +1534,7 @@ async fn lock(file: types::Path, mut value: _, (shared): [_]) -> Value {
     use std::fs::{File, OpenOptions};
 
     let shared = shared.is_some();
+    crate::permitted(&ARGS_SOURCE, |c| c.check_write(file.as_ref().as_ref()))?;
     let f = OpenOptions::new()
         .read(true)
         .write(true)
@@ -1093,3 +1572,170 @@ async fn lock(file: types::Path, mut value: _, (shared): [_]) -> Value {
     drop(guard);
     value
 }
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn tar_header() {
+        let mut header = tar::Header::new_old();
+        header.set_path("a").unwrap();
+        header.set_size(0);
+        header.set_cksum();
+        assert!(super::is_tar_header(header.as_bytes()));
+
+        let mut header = tar::Header::new_ustar();
+        header.set_path("a").unwrap();
+        header.set_cksum();
+        assert!(super::is_tar_header(header.as_bytes()));
+
+        assert!(!super::is_tar_header(&[0; 512]));
+        assert!(!super::is_tar_header(&[b'a'; 512]));
+    }
+
+    /// Create an empty temporary directory for a test.
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("ergo-fs-{}-{}", name, std::process::id()));
+        drop(std::fs::remove_dir_all(&dir));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    ergo_script::tests! {
+        fn glob_not_permitted(t) {
+            let t = t.capabilities(ergo_runtime::context::Capabilities::none());
+            t.assert_fail(&format!("self:fs:glob \"{}/*\"", std::env::temp_dir().display()));
+        }
+
+        fn metadata(t) {
+            let dir = temp_dir("metadata");
+            let file = dir.join("file");
+            std::fs::write(&file, "hello").unwrap();
+            let meta = |path: &std::path::Path, key: &str| {
+                format!("(self:fs:metadata \"{}\"):{}", path.display(), key)
+            };
+            t.assert_eq(&meta(&file, "type"), "file");
+            t.assert_eq(&meta(&dir, "type"), "directory");
+            t.assert_eq(&meta(&file, "size"), "self:Number:from 5");
+            t.assert_eq(&meta(&file, "readonly"), "self:Bool:false");
+            t.assert_eq(&format!("self:fs:metadata \"{}\"", dir.join("missing").display()), "$unset");
+            if cfg!(unix) {
+                let link = dir.join("link");
+                super::symlink_file("file", &link).unwrap();
+                t.assert_eq(&meta(&link, "type"), "symlink");
+                t.assert_eq(
+                    &format!("(self:fs:metadata ~follow-symlinks \"{}\"):type", link.display()),
+                    "file",
+                );
+            }
+            drop(std::fs::remove_dir_all(&dir));
+        }
+
+        fn read_dir(t) {
+            let dir = temp_dir("read-dir");
+            std::fs::write(dir.join("a"), "").unwrap();
+            std::fs::create_dir(dir.join("b")).unwrap();
+            t.assert_eq(
+                &format!("self:Iter:count (self:fs:read-dir \"{}\")", dir.display()),
+                "self:Number:from 2",
+            );
+            t.assert_fail(&format!("self:fs:read-dir \"{}\"", dir.join("missing").display()));
+            drop(std::fs::remove_dir_all(&dir));
+        }
+
+        fn set_permissions(t) {
+            let dir = temp_dir("set-permissions");
+            let file = dir.join("file");
+            std::fs::write(&file, "").unwrap();
+            t.assert_success(&format!("self:fs:set-permissions \"{}\" ~readonly=self:Bool:true", file.display()));
+            assert!(std::fs::metadata(&file).unwrap().permissions().readonly());
+            t.assert_success(&format!("self:fs:set-permissions \"{}\" ~readonly=self:Bool:false", file.display()));
+            assert!(!std::fs::metadata(&file).unwrap().permissions().readonly());
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                t.assert_success(&format!("self:fs:set-permissions \"{}\" ~mode=700", file.display()));
+                assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o7777, 0o700);
+                t.assert_fail(&format!("self:fs:set-permissions \"{}\" ~mode=9", file.display()));
+            }
+            drop(std::fs::remove_dir_all(&dir));
+        }
+
+        fn chmod(t) {
+            let dir = temp_dir("chmod");
+            let file = dir.join("file");
+            std::fs::write(&file, "").unwrap();
+            t.assert_success(&format!("self:fs:chmod \"{}\" 640", file.display()));
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o7777, 0o640);
+                t.assert_success(&format!("self:fs:chmod \"{}\" (self:Number:from 448)", file.display()));
+                assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o7777, 0o700);
+            }
+            t.assert_fail(&format!("self:fs:chmod \"{}\" 640", dir.join("missing").display()));
+            drop(std::fs::remove_dir_all(&dir));
+        }
+
+        fn touch(t) {
+            let dir = temp_dir("touch");
+            let file = dir.join("file");
+            t.assert_success(&format!("self:fs:touch \"{}\"", file.display()));
+            assert_eq!(std::fs::read_to_string(&file).unwrap(), "");
+            // Existing content is untouched.
+            std::fs::write(&file, "hello").unwrap();
+            t.assert_success(&format!("self:fs:touch \"{}\"", file.display()));
+            assert_eq!(std::fs::read_to_string(&file).unwrap(), "hello");
+            // Parent directories are not created.
+            t.assert_fail(&format!("self:fs:touch \"{}\"", dir.join("missing").join("file").display()));
+            drop(std::fs::remove_dir_all(&dir));
+        }
+
+        fn symlink(t) {
+            if cfg!(unix) {
+                let dir = temp_dir("symlink");
+                std::fs::write(dir.join("file"), "hello").unwrap();
+                let link = dir.join("link");
+                // Relative originals are relative to the directory containing the link.
+                t.assert_success(&format!("self:fs:symlink file \"{}\"", link.display()));
+                assert_eq!(std::fs::read_link(&link).unwrap(), std::path::Path::new("file"));
+                assert_eq!(std::fs::read_to_string(&link).unwrap(), "hello");
+                t.assert_fail(&format!("self:fs:symlink file \"{}\"", link.display()));
+                drop(std::fs::remove_dir_all(&dir));
+            }
+        }
+
+        fn hardlink(t) {
+            let dir = temp_dir("hardlink");
+            let file = dir.join("file");
+            std::fs::write(&file, "hello").unwrap();
+            let link = dir.join("link");
+            let script = format!("self:fs:hardlink \"{}\" \"{}\"", file.display(), link.display());
+            t.assert_success(&script);
+            assert_eq!(std::fs::read_to_string(&link).unwrap(), "hello");
+            t.assert_fail(&script);
+            drop(std::fs::remove_dir_all(&dir));
+        }
+    }
+
+    #[test]
+    fn write_dry_run() {
+        let t = ergo_script::testing::Test::dry_run(crate::_ergo_plugin);
+        let dir = temp_dir("write-dry-run");
+        let file = dir.join("file");
+        t.assert_eq(&format!("self:fs:write \"{}\" hello", file.display()), "()");
+        assert!(!file.exists());
+        drop(std::fs::remove_dir_all(&dir));
+    }
+
+    #[test]
+    fn remove_dry_run() {
+        let t = ergo_script::testing::Test::dry_run(crate::_ergo_plugin);
+        let dir = temp_dir("remove-dry-run");
+        let file = dir.join("file");
+        std::fs::write(&file, "hello").unwrap();
+        t.assert_eq(&format!("self:fs:remove \"{}\"", file.display()), "()");
+        t.assert_eq(&format!("self:fs:remove \"{}\"", dir.display()), "()");
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "hello");
+        drop(std::fs::remove_dir_all(&dir));
+    }
+}
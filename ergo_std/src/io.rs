@@ -7,7 +7,8 @@ pub fn module() -> Value {
         "stdin" = stdin(),
         "stdout" = stdout(),
         "stderr" = stderr(),
-        "is-terminal" = is_terminal()
+        "is-terminal" = is_terminal(),
+        "lines" = lines()
     }
 }
 
@@ -119,3 +120,55 @@ async fn is_terminal(_: types::Unit, (stream): [types::String]) -> Value {
 
     types::Bool(atty::is(stream)).into()
 }
+
+#[types::ergo_fn]
+/// Get the lines of a ByteStream.
+///
+/// Arguments: `(Into<ByteStream> :bytes)`
+///
+/// Returns an Iter of Strings, one for each line (separated by `\n`, with a trailing `\r` removed)
+/// in `bytes`. Invalid UTF-8 sequences are replaced with the unicode replacement character.
+///
+/// The Iter reads `bytes` lazily, so only a single line is held in memory at a time.
+async fn lines(bytes: _) -> Value {
+    let bytes = traits::into::<types::ByteStream>(bytes).await?;
+
+    #[derive(Clone)]
+    struct Lines(crate::byte_stream::Split);
+
+    ergo_runtime::ImplGenerator!(Lines => |self| {
+        Ok(self.0.next_segment().await?.map(|mut line| {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            types::String::from(String::from_utf8_lossy(&line).into_owned()).into()
+        }))
+    });
+
+    types::Iter::new(
+        Lines(crate::byte_stream::Split::new(
+            bytes.as_ref().read(),
+            b"\n".to_vec(),
+        )),
+        CALL_DEPENDS,
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod test {
+    ergo_script::tests! {
+        fn lines(t) {
+            t.assert_eq("self:Array:from <| self:io:lines \"a\\nb\\n\"", "[a,b]");
+            t.assert_eq("self:Array:from <| self:io:lines \"a\\n\\nb\"", "[a,\"\",b]");
+        }
+
+        fn lines_crlf(t) {
+            t.assert_eq("self:Array:from <| self:io:lines \"a\\u{d}\\nb\\u{d}\\n\"", "[a,b]");
+        }
+
+        fn lines_empty(t) {
+            t.assert_eq("self:Array:from <| self:io:lines \"\"", "[]");
+        }
+    }
+}
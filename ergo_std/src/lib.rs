@@ -3,7 +3,9 @@ use ergo_runtime::{plugin_entry, types, Context, EvaluatedValue, Value};
 
 mod array;
 mod bool;
+mod byte_stream;
 mod cache;
+mod compress;
 mod env;
 mod error;
 mod exec;
@@ -43,6 +45,64 @@ fn make_string(s: &str) -> EvaluatedValue {
     types::String::from(s).into()
 }
 
+/// Check that the capabilities of the current context permit an operation.
+fn permitted<F>(
+    src: &ergo_runtime::Source<()>,
+    check: F,
+) -> Result<(), ergo_runtime::error::Diagnostic>
+where
+    F: FnOnce(&ergo_runtime::context::Capabilities) -> Result<(), String>,
+{
+    use ergo_runtime::error::DiagnosticInfo;
+    Context::with(|ctx| check(&ctx.capabilities))
+        .add_primary_label(
+            src.clone()
+                .with("not permitted by the current capabilities"),
+        )
+        .set_kind("not-permitted")
+}
+
+/// Check the content of a retrieved resource against the lock file.
+///
+/// If the lock file has no entry for `key`, the content hash is recorded.
+fn locked<R: std::io::Read>(
+    src: &ergo_runtime::Source<()>,
+    key: &str,
+    mut content: R,
+) -> ergo_runtime::Result<()> {
+    use ergo_runtime::error::{Diagnostic, DiagnosticInfo};
+    use sha::utils::DigestExt;
+    let mut digest = sha::sha256::Sha256::default();
+    std::io::copy(&mut content, &mut digest).map_err(Diagnostic::from)?;
+    let hash = format!("sha256:{}", digest.to_hex());
+    Context::global()
+        .lock_file()?
+        .check(key, &hash)
+        .add_primary_label(
+            src.clone()
+                .with("while checking the content retrieved here"),
+        )
+        .add_note(format_args!(
+            "if the change is expected, run `ergo lock update --key {}`",
+            key
+        ))
+        .set_kind("lock:mismatch")
+        .add_field("key", key)
+        .map_err(|e| e.into())
+}
+
+/// Check whether side effects should be skipped (when in dry-run mode).
+///
+/// In dry-run mode, the side effect described by `action` is logged and true is returned.
+fn dry_run(action: std::fmt::Arguments) -> bool {
+    let ctx = Context::global();
+    let dry_run = ctx.env.dry_run();
+    if dry_run {
+        ctx.log.sublog("dry-run").info(action);
+    }
+    dry_run
+}
+
 #[macro_export]
 macro_rules! make_string_map {
     ( source $src:expr, $( $s:literal = $v:expr ),* ) => {
@@ -74,6 +134,7 @@ fn entry() -> ergo_runtime::Result<Value> {
     Ok(make_string_map! {
         "Array" = array::r#type(),
         "Bool" = bool::r#type(),
+        "ByteStream" = byte_stream::r#type(),
         "Cache" = cache::r#type(),
         "Error" = error::r#type(),
         "Function" = function::r#type(),
@@ -87,6 +148,7 @@ fn entry() -> ergo_runtime::Result<Value> {
         "Unit" = unit::r#type(),
         "Unset" = unset::r#type(),
         "Type" = r#type::r#type(),
+        "compress" = compress::module(),
         "env" = env::module(),
         "exec" = exec::function(),
         "fs" = fs::module(),
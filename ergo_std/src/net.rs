@@ -38,6 +38,9 @@ pub fn module() -> Value {
 /// * `Into<Number> :timeout`: A timeout to use, in seconds.
 /// * `(Map:Of :String :String) :headers`: Key-value pairs are header names and values to set for the request.
 /// * `Into<ByteStream> :body`: The body to send with the request, if any.
+/// * `:lock`: If present, the content of a successful response body is verified against the lock
+/// file entry for the url (recording the entry if it is missing). The body is read entirely before
+/// the response is returned.
 ///
 /// Returns a Map with the following indices:
 /// * `:body`: The response body, as a `ByteStream`.
@@ -57,6 +60,7 @@ async fn http(
     (timeout): [_],
     (headers): [types::Map],
     (body): [_],
+    (lock): [_],
 ) -> Value {
     let method = match method {
         None => Method::GET,
@@ -77,7 +81,37 @@ async fn http(
 
     let log = Context::global().log.sublog("net:http");
 
-    let client = Client::builder().build()?;
+    let description = format!("{} {}", method, url.as_ref().as_str());
+
+    let capabilities = Context::with(|ctx| ctx.capabilities.clone());
+    let allowed = move |url: &reqwest::Url| {
+        capabilities.check_net(
+            url.host_str().unwrap_or_default(),
+            url.port_or_known_default(),
+        )
+    };
+    match reqwest::Url::parse(url.as_ref().as_str()) {
+        Ok(u) => allowed(&u),
+        Err(_) => Err(format!("invalid url: {}", url.as_ref().as_str())),
+    }
+    .add_primary_label(
+        ARGS_SOURCE
+            .clone()
+            .with("not permitted by the current capabilities"),
+    )?;
+
+    let client = Client::builder()
+        .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+            // Redirects are also subject to the capabilities (with the default redirect limit).
+            if attempt.previous().len() > 10 {
+                attempt.error("too many redirects")
+            } else if let Err(e) = allowed(attempt.url()) {
+                attempt.error(e)
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()?;
     let mut request = client.request(method, url.as_ref().as_str());
 
     if let Some(auth) = basic_auth {
@@ -135,18 +169,35 @@ async fn http(
         request = request.body(body_vec);
     }
 
+    if crate::dry_run(format_args!("send http request: {}", description)) {
+        // Return a placeholder successful response with no headers and an empty body.
+        let body = Value::with_id(
+            types::ByteStream::from(Vec::new()),
+            depends![dyn ^CALL_DEPENDS.clone(), nsid!(net::http::dry_run)],
+        );
+        return Ok(crate::make_string_map! { source ARGS_SOURCE,
+            "status-code" = HttpStatus::from(StatusCode::OK).into(),
+            "headers" = types::Map(Default::default()).into(),
+            "body" = body,
+            "complete" = types::Unit.into()
+        });
+    }
+
     log.debug(format!("sending http request: {:?}", request));
 
     // Wrap the request in a mutex because RequestBuilder is not Sync and cannot be captured in
     // `spawn_blocking`, but `Mutex` will be Sync.
     let req = std::sync::Mutex::new(request);
+    let request_url = url.as_ref().as_str().to_owned();
     let response = Context::global()
         .task
         .spawn_blocking(move || {
-            req.into_inner()
-                .unwrap()
-                .send()
-                .map_err(|e| ergo_runtime::error! { error: e })
+            req.into_inner().unwrap().send().map_err(|e| {
+                Diagnostic::from(e)
+                    .set_kind("net:request")
+                    .add_field("url", request_url)
+                    .into_error()
+            })
         })
         .await
         .and_then(|v| v)?;
@@ -167,8 +218,23 @@ async fn http(
     }
     let headers = types::Map(headers);
 
+    let body = if lock.is_some() && err_status.is_success() {
+        let data = Context::global()
+            .task
+            .spawn_blocking(move || {
+                response
+                    .bytes()
+                    .map_err(|e| ergo_runtime::error! { error: e })
+            })
+            .await
+            .and_then(|v| v)?;
+        crate::locked(&ARGS_SOURCE, url.as_ref().as_str(), data.as_ref())?;
+        types::ByteStream::from(data.to_vec())
+    } else {
+        types::ByteStream::new(io::Blocking::new(response))
+    };
     let body = Value::with_id(
-        types::ByteStream::new(io::Blocking::new(response)),
+        body,
         depends![dyn ^CALL_DEPENDS.clone(), nsid!(net::http::body)],
     );
 
@@ -177,15 +243,21 @@ async fn http(
     } else {
         let src = ARGS_SOURCE.clone();
         let body = body.clone();
+        let url = url.as_ref().as_str().to_owned();
         // TODO should this just immediately be an Error type? It's made lazy here to delay the
         // reading of the (possibly large) body.
         ergo_runtime::lazy_value! {
             #![depends(dyn ^CALL_DEPENDS.clone(), nsid!(net::http::complete))]
-            src.with(format!(
-                "{}: {}",
-                err_status,
-                traits::to_string(body).await?
-            ))
+            src.with(
+                Diagnostic::from(format!(
+                    "{}: {}",
+                    err_status,
+                    traits::to_string(body).await?
+                ))
+                .set_kind("net:status")
+                .add_field("url", url)
+                .add_field("status-code", err_status.as_u16()),
+            )
             .into_error()
             .into()
         }
@@ -375,4 +447,54 @@ mod test {
             t.assert_fail(&format!(r#"self:net:http (timeout=1/10) "{}" |>:complete"#, server.url("/onesecond")));
         }
     }
+
+    #[test]
+    fn http_dry_run() {
+        let t = ergo_script::testing::Test::dry_run(crate::_ergo_plugin);
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.path("/hi");
+            then.status(200).body("hello world");
+        });
+        let url = server.url("/hi");
+        t.assert_eq(&format!(r#"self:net:http "{}" |>:complete"#, url), "()");
+        t.assert_eq(
+            &format!(
+                r#"self:net:http (method=put) (body=data) "{}" |>:body | self:String:from"#,
+                url
+            ),
+            "\"\"",
+        );
+        t.assert_eq(
+            &format!(
+                r#"self:net:http "{}" |>:status-code | self:String:from"#,
+                url
+            ),
+            "200",
+        );
+        assert_eq!(mock.hits(), 0);
+    }
+
+    #[test]
+    fn load_remote_dry_run() {
+        // Like `std:load-remote`, which cannot load the (unretrieved) archive in a dry run.
+        let t = ergo_script::testing::Test::dry_run(crate::_ergo_plugin);
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.path("/archive.tar");
+            then.status(200);
+        });
+        let dir =
+            std::env::temp_dir().join(format!("ergo-load-remote-dry-run-{}", std::process::id()));
+        t.assert_eq(
+            &format!(
+                r#"result = self:net:http ~lock "{}"; result:complete; self:fs:unarchive "{dir}" result:body; self:Error:kind <| load "{dir}""#,
+                server.url("/archive.tar"),
+                dir = dir.display()
+            ),
+            "dry-run",
+        );
+        assert_eq!(mock.hits(), 0);
+        assert!(!dir.exists());
+    }
 }
@@ -5,20 +5,29 @@ use ergo_runtime::{
     metadata::Source,
     traits,
     type_system::ErgoType,
-    types, Value,
+    types, Context, Value,
 };
 
 pub fn r#type() -> Value {
     types::Type {
         tp: types::Path::ergo_type(),
         index: crate::make_string_map! {
+            "absolute" = absolute(),
+            "artifact" = artifact(),
+            "canonicalize" = canonicalize(),
+            "exists" = exists(),
+            "extension" = extension(),
             "from" = from(),
+            "is-absolute" = is_absolute(),
             "owned" = owned(),
             "join" = join(),
             "name" = name(),
+            "normalize" = normalize(),
             "parent" = parent(),
             "relative" = relative(),
-            "split" = split()
+            "split" = split(),
+            "stem" = stem(),
+            "with-extension" = with_extension()
         },
     }
     .into()
@@ -47,6 +56,27 @@ async fn owned(path: _) -> Value {
     path.into()
 }
 
+#[types::ergo_fn]
+/// Create an artifact Path.
+///
+/// Arguments: `Into:into $Path |> :path`
+///
+/// An artifact Path is identical to a normal Path, however when it is stored in a cache (for
+/// instance as part of a `Cache:entry` value), the content of the file or directory is stored in
+/// the artifact store of the project storage directory. When the cached value is later retrieved,
+/// the content is restored at the path, so cached build outputs may safely be removed or
+/// overwritten. When a directory is restored, any entries of the directory which are not part of
+/// the stored content are removed.
+///
+/// The path itself is marked as an artifact (for the remainder of the evaluation), rather than the
+/// returned value: any Path value with the same path is stored as an artifact, and the returned
+/// value has the same identity as a normal Path.
+async fn artifact(path: _) -> Value {
+    let path = traits::into::<types::Path>(path).await?;
+    Context::global().artifacts().add(&path.as_ref().path);
+    path.into()
+}
+
 #[types::ergo_fn]
 /// Join components into a Path.
 ///
@@ -141,6 +171,163 @@ async fn relative(base: types::Path, child: types::Path) -> Value {
     .into()
 }
 
+#[types::ergo_fn]
+/// Returns the extension of the given path.
+///
+/// Arguments: `(Path :path)`
+///
+/// Returns the extension (without the leading `.`) of the final component as a String, or `Unset`
+/// if there is no final component or the final component has no extension.
+async fn extension(path: types::Path) -> Value {
+    match path.as_ref().as_ref().extension() {
+        None => types::Unset.into(),
+        Some(ext) => types::String::from(ext.to_string_lossy().to_owned()).into(),
+    }
+}
+
+#[types::ergo_fn]
+/// Returns the given path with a different extension.
+///
+/// Arguments: `(Path :path) (Into<String> :extension)`
+///
+/// If `extension` is an empty String, the existing extension is removed.
+///
+/// Fails if the given path does not have a final component.
+async fn with_extension(path: types::Path, extension: _) -> Value {
+    let extension = traits::into::<types::String>(extension).await?;
+    let p = path.as_ref().as_ref();
+    if p.file_name().is_none() {
+        Err(Source::get(&path)
+            .with("path does not have a final component")
+            .into_error())?;
+    }
+    types::Path::from(p.with_extension(extension.as_ref().as_str())).into()
+}
+
+#[types::ergo_fn]
+/// Returns the stem of the given path.
+///
+/// Arguments: `(Path :path)`
+///
+/// The stem is the final component of the path without the extension, returned as a String.
+///
+/// Fails if the given path does not have a final component.
+async fn stem(path: types::Path) -> Value {
+    match path.as_ref().as_ref().file_stem() {
+        None => Source::get(&path)
+            .with("path does not have a final component")
+            .into_error()
+            .into(),
+        Some(stem) => types::String::from(stem.to_string_lossy().to_owned()).into(),
+    }
+}
+
+#[types::ergo_fn]
+/// Returns whether the given path is absolute.
+///
+/// Arguments: `(Path :path)`
+///
+/// Returns a Bool.
+async fn is_absolute(path: types::Path) -> Value {
+    types::Bool(path.as_ref().as_ref().is_absolute()).into()
+}
+
+#[types::ergo_fn]
+#[eval_for_id]
+/// Make a path absolute.
+///
+/// Arguments: `(Path :path)`
+///
+/// If `path` is relative, it is made relative to the calling script directory (or the current
+/// working directory if there is no calling script). Otherwise `path` is returned unchanged.
+///
+/// The path is not normalized; see `normalize` or `canonicalize`.
+async fn absolute(path: types::Path) -> Value {
+    let p = path.as_ref().as_ref();
+    if p.is_absolute() {
+        path.into()
+    } else {
+        let mut base = Context::source_path(&ARGS_SOURCE)
+            .and_then(|p| p.parent().map(|p| p.to_owned()))
+            .map(Ok)
+            .unwrap_or_else(std::env::current_dir)
+            .add_note("while getting the current working directory")?;
+        base.push(p);
+        types::Path::from(base).into()
+    }
+}
+
+#[types::ergo_fn]
+#[eval_for_id]
+/// Get the canonical form of a path.
+///
+/// Arguments: `(Path :path)`
+///
+/// Returns the absolute path with all intermediate components normalized and all symbolic links
+/// resolved. Relative paths are resolved relative to the current working directory.
+///
+/// The result depends on the state of the filesystem when it is evaluated, so it is never cached
+/// by the arguments alone.
+///
+/// Fails if the path does not exist.
+async fn canonicalize(path: types::Path) -> Value {
+    let p = path.as_ref().as_ref();
+    types::Path::from(
+        std::fs::canonicalize(p)
+            .add_note(format_args!("path was {}", p.display()))
+            .add_primary_label(Source::get(&path).with("while canonicalizing this path"))?,
+    )
+    .into()
+}
+
+#[types::ergo_fn]
+/// Lexically normalize a path.
+///
+/// Arguments: `(Path :path)`
+///
+/// Removes `.` components and collapses `..` components with their preceding component, without
+/// accessing the filesystem. Leading `..` components of relative paths are preserved, and `..`
+/// components directly following a root are removed.
+///
+/// Note that this may change the meaning of a path which contains symbolic links.
+async fn normalize(path: types::Path) -> Value {
+    use std::path::Component;
+
+    let mut ret = std::path::PathBuf::new();
+    for c in path.as_ref().as_ref().components() {
+        match c {
+            Component::CurDir => (),
+            Component::ParentDir => match ret.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    ret.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => (),
+                _ => ret.push(c),
+            },
+            c => ret.push(c),
+        }
+    }
+    if ret.as_os_str().is_empty() {
+        ret.push(".");
+    }
+    types::Path::from(ret).into()
+}
+
+#[types::ergo_fn]
+#[eval_for_id]
+/// Return whether a path exists.
+///
+/// Arguments: `(Path :path)`
+///
+/// Returns a Bool indicating whether the path exists. Symbolic links are followed, so a broken
+/// symbolic link does not exist.
+///
+/// The result depends on the state of the filesystem when it is evaluated, so it is never cached
+/// by the arguments alone.
+async fn exists(path: types::Path) -> Value {
+    types::Bool(path.as_ref().as_ref().exists()).into()
+}
+
 #[cfg(test)]
 mod test {
     ergo_script::tests! {
@@ -149,6 +336,76 @@ mod test {
             t.assert_value_eq("self:Path:join a b c", &super::types::Path::from(p.join("b").join("c")));
         }
 
+        fn artifact(t) {
+            t.assert_eq("self:Path:artifact (self:Path:join a b)", "self:Path:join a b");
+        }
+
+        fn artifact_restored(t) {
+            let dir = std::env::temp_dir().join(format!("ergo-artifact-restored-{}", std::process::id()));
+            drop(std::fs::remove_dir_all(&dir));
+            std::fs::create_dir_all(&dir).unwrap();
+            let file = dir.join("out");
+            std::fs::write(&file, "content").unwrap();
+            let script = format!(
+                "self:Cache:entry (self:Cache:open \"{}\") (self:Path:artifact \"{}\")",
+                dir.join("cache.db").display(),
+                file.display()
+            );
+            t.assert_success(&script);
+            // Flush the cache so that the entry is stored.
+            t.shutdown();
+
+            std::fs::remove_file(&file).unwrap();
+            t.assert_success(&script);
+            assert_eq!(std::fs::read_to_string(&file).unwrap(), "content");
+            drop(std::fs::remove_dir_all(&dir));
+        }
+
+        fn artifact_marks_path(t) {
+            let dir = std::env::temp_dir().join(format!("ergo-artifact-marks-path-{}", std::process::id()));
+            drop(std::fs::remove_dir_all(&dir));
+            std::fs::create_dir_all(&dir).unwrap();
+            let file = dir.join("out");
+            std::fs::write(&file, "content").unwrap();
+            // The path is marked, so a plain Path value with the same path is stored as an artifact.
+            t.assert_success(&format!("self:Path:artifact \"{}\"", file.display()));
+            let script = format!(
+                "self:Cache:entry (self:Cache:open \"{}\") (self:Path:from \"{}\")",
+                dir.join("cache.db").display(),
+                file.display()
+            );
+            t.assert_success(&script);
+            t.shutdown();
+
+            std::fs::remove_file(&file).unwrap();
+            t.assert_success(&script);
+            assert_eq!(std::fs::read_to_string(&file).unwrap(), "content");
+            drop(std::fs::remove_dir_all(&dir));
+        }
+
+        fn artifact_directory_restored(t) {
+            let dir = std::env::temp_dir().join(format!("ergo-artifact-directory-{}", std::process::id()));
+            drop(std::fs::remove_dir_all(&dir));
+            let out = dir.join("out");
+            std::fs::create_dir_all(&out).unwrap();
+            std::fs::write(out.join("a"), "a").unwrap();
+            let script = format!(
+                "self:Cache:entry (self:Cache:open \"{}\") (self:Path:artifact \"{}\")",
+                dir.join("cache.db").display(),
+                out.display()
+            );
+            t.assert_success(&script);
+            t.shutdown();
+
+            // Entries which are not part of the artifact are removed when restored.
+            std::fs::remove_file(out.join("a")).unwrap();
+            std::fs::write(out.join("b"), "b").unwrap();
+            t.assert_success(&script);
+            assert_eq!(std::fs::read_to_string(out.join("a")).unwrap(), "a");
+            assert!(!out.join("b").exists());
+            drop(std::fs::remove_dir_all(&dir));
+        }
+
         fn from(t) {
             t.assert_value_eq("self:Path:from a/b/c", &super::types::Path::from(std::path::PathBuf::from("a").join("b").join("c")));
         }
@@ -164,5 +421,54 @@ mod test {
         fn split(t) {
             t.assert_eq("self:Path:split (self:Path:join a b c)", "[a,b,c]");
         }
+
+        fn extension(t) {
+            t.assert_eq("self:Path:extension (self:Path:join a b.tar.gz)", "gz");
+            t.assert_eq("self:Path:extension (self:Path:join a b)", "$unset");
+        }
+
+        fn with_extension(t) {
+            t.assert_eq("self:Path:with-extension (self:Path:join a b.txt) md", "self:Path:join a b.md");
+            t.assert_eq("self:Path:with-extension (self:Path:join a b.txt) \"\"", "self:Path:join a b");
+        }
+
+        fn stem(t) {
+            t.assert_eq("self:Path:stem (self:Path:join a b.tar.gz)", "b.tar");
+        }
+
+        fn is_absolute(t) {
+            t.assert_eq("self:Path:is-absolute (self:Path:join a b)", "self:Bool:false");
+        }
+
+        fn exists(t) {
+            let dir = std::env::temp_dir().join(format!("ergo-path-exists-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("file"), "").unwrap();
+            t.assert_eq(&format!("self:Path:exists (self:Path:join \"{}\" file)", dir.display()), "self:Bool:true");
+            t.assert_eq(&format!("self:Path:exists (self:Path:join \"{}\" missing)", dir.display()), "self:Bool:false");
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fn canonicalize(t) {
+            let dir = std::env::temp_dir().join(format!("ergo-path-canonicalize-{}", std::process::id()));
+            std::fs::create_dir_all(dir.join("sub")).unwrap();
+            std::fs::write(dir.join("sub").join("file"), "").unwrap();
+            let expected = std::fs::canonicalize(dir.join("sub").join("file")).unwrap();
+            t.assert_value_eq(&format!("self:Path:canonicalize (self:Path:join \"{}\" sub .. sub file)", dir.display()), &super::types::Path::from(expected));
+            t.assert_fail(&format!("self:Path:canonicalize (self:Path:join \"{}\" missing)", dir.display()));
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fn absolute(t) {
+            let cwd = std::env::current_dir().unwrap();
+            t.assert_value_eq("self:Path:absolute (self:Path:join a b)", &super::types::Path::from(cwd.join("a").join("b")));
+            let abs = std::env::temp_dir().join("a");
+            t.assert_value_eq(&format!("self:Path:absolute (self:Path:from \"{}\")", abs.display()), &super::types::Path::from(abs));
+        }
+
+        fn normalize(t) {
+            t.assert_eq("self:Path:normalize (self:Path:join a . b .. c)", "self:Path:join a c");
+            t.assert_eq("self:Path:normalize (self:Path:join .. a .. ..)", "self:Path:join .. ..");
+        }
     }
 }
@@ -5,7 +5,9 @@ use ergo_runtime::abi_stable::{
     std_types::{RArc, ROption, RString},
     StableAbi,
 };
-use ergo_runtime::context::{DynamicScopeKey, Log, LogTask, RecordingWork, TaskPermit, Work};
+use ergo_runtime::context::{
+    DynamicScopeKey, Log, LogTask, RecordingWork, TaskLog, TaskLogKey, TaskPermit, Work,
+};
 use ergo_runtime::{error::DiagnosticInfo, metadata::Source, nsid, traits, types, Context, Value};
 
 pub const SCRIPT_TASK_PRIORITY_OFFSET: u32 = 1000;
@@ -32,6 +34,9 @@ pub const SCRIPT_TASK_PRIORITY_OFFSET: u32 = 1000;
 /// work in the runtime progress tracking. An active task takes up `task-count` slots out of the total permitted number of
 /// concurrent tasks at a single time (as configured in the runtime). If there are not enough slots, the task will wait
 /// until more become available. If the requested number is greater than the maximum, it will be limited to the maximum.
+///
+/// If the runtime is configured with a task log directory, the log entries written while the task runs and the
+/// stdout/stderr of child processes spawned by the task are saved there (see `ergolang logs`).
 pub async fn function(
     description: types::String,
     value: _,
@@ -88,12 +93,16 @@ pub async fn function(
         #![contains(value)]
         let parent_task =
             ParentTask::new(description.clone(), count, log.clone(), work.clone()).await;
+        let task_log = task_log(&description);
         let mut value = value;
         Context::spawn(
             priority,
             |ctx| {
                 ctx.dynamic_scope
-                    .set(&ARGS_SOURCE.with(ParentTaskKey), parent_task)
+                    .set(&ARGS_SOURCE.with(ParentTaskKey), parent_task);
+                if let Some(task_log) = task_log {
+                    ctx.dynamic_scope.set(&ARGS_SOURCE.with(TaskLogKey), task_log);
+                }
             },
             async move {
                 log.info(format!("starting: {}", &description));
@@ -157,6 +166,21 @@ pub async fn function(
     value
 }
 
+/// Create the task log for a task, if task logs are enabled.
+fn task_log(description: &str) -> Option<TaskLog> {
+    let dir = Context::global().env.task_log_directory()?;
+    match TaskLog::new(&dir, description) {
+        Ok(l) => Some(l),
+        Err(e) => {
+            Context::global().log.sublog("task").warn(format_args!(
+                "failed to create task log for {}: {}",
+                description, e
+            ));
+            None
+        }
+    }
+}
+
 pub struct ParentTaskKey;
 
 impl DynamicScopeKey for ParentTaskKey {
@@ -1,6 +1,8 @@
 //! Value-related intrinsics.
 
 use ergo_runtime::{
+    dependency::Dependency,
+    error::DiagnosticInfo,
     metadata::{Runtime, Source},
     nsid, traits, types, Context, Value,
 };
@@ -10,6 +12,7 @@ pub fn module() -> Value {
     crate::make_string_map! {
         "backtrace" = backtrace(),
         "debug" = debug(),
+        "dependencies" = dependencies(),
         "dynamic" = crate::make_string_map! {
             "get" = dynamic_binding_get(),
             "eval" = dynamic_binding_set()
@@ -107,6 +110,108 @@ async fn identity(value: _) -> Value {
     types::String::from(format!("{:032x}", value.id().await)).into()
 }
 
+#[types::ergo_fn]
+/// Get the dependency tree of the identity of a value.
+///
+/// Arguments: `:value`
+///
+/// Keyed Arguments:
+/// * `Into<Number> :depth`: The maximum depth of the tree to retrieve. If unspecified, the entire
+/// tree is retrieved.
+///
+/// Returns a Map with the following keys:
+/// * `id` - the identity of the value, as a 32-character hex string
+/// * `source` - the source location of the value as a `name:line:column` String, if available
+/// * `type` - the type name of the value, if the value was evaluated to determine its identity
+/// * `dependencies` - an Array of Maps with the same structure, one for each dependency from which
+/// the identity is derived
+///
+/// Constant dependencies (which are not values) only have an `id` key. Values which occur more than
+/// once in the tree only have `dependencies` in their first occurrence; later occurrences have a
+/// `repeated` key set to `Bool:true`.
+///
+/// Like `identity`, values are evaluated as necessary to determine their identities.
+async fn dependencies(value: _, (depth): [_]) -> Value {
+    let depth = match depth {
+        None => None,
+        Some(v) => {
+            let n = traits::into::<types::Number>(v).await?;
+            let depth = n
+                .as_ref()
+                .to_usize()
+                .add_primary_label(Source::get(&n).with("expected this to be unsigned integer"))?;
+            Some(depth)
+        }
+    };
+
+    struct TreeBuilder {
+        seen: std::collections::HashSet<u128>,
+        source: ergo_runtime::Source<()>,
+    }
+
+    impl TreeBuilder {
+        fn map(&self, entries: Vec<(&str, Value)>) -> Value {
+            types::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| {
+                        (
+                            crate::make_string_src(self.source.clone().with(k)),
+                            Source::imbue(self.source.clone().with(v)),
+                        )
+                    })
+                    .collect(),
+            )
+            .into()
+        }
+
+        fn node<'a>(&'a mut self, mut value: Value, depth: Option<usize>) -> BoxFuture<'a, Value> {
+            async move {
+                let id = value.eval_id().await.id;
+                let mut entries: Vec<(&str, Value)> =
+                    vec![("id", types::String::from(format!("{:032x}", id)).into())];
+                if let Some(s) = Source::get_option(&value)
+                    .and_then(|s| Context::global().diagnostic_sources().location_string(&s))
+                {
+                    entries.push(("source", types::String::from(s).into()));
+                }
+                if value.is_evaluated() {
+                    entries.push((
+                        "type",
+                        types::String::from(traits::type_name(&value)).into(),
+                    ));
+                }
+                if !self.seen.insert(id) {
+                    entries.push(("repeated", types::Bool(true).into()));
+                } else if depth != Some(0) {
+                    let mut deps = Vec::new();
+                    value.dependencies().map(|d| deps.push(d.clone()));
+                    let mut children = Vec::new();
+                    for d in deps {
+                        children.push(match d {
+                            Dependency::Value(v) => self.node(v, depth.map(|d| d - 1)).await,
+                            Dependency::Constant(_) => {
+                                let id = format!("{:032x}", d.id().await.id);
+                                self.map(vec![("id", types::String::from(id).into())])
+                            }
+                        });
+                    }
+                    entries.push(("dependencies", types::Array(children.into()).into()));
+                }
+                self.map(entries)
+            }
+            .boxed()
+        }
+    }
+
+    TreeBuilder {
+        seen: Default::default(),
+        source: ARGS_SOURCE,
+    }
+    .node(value, depth)
+    .await
+}
+
 #[types::ergo_fn]
 /// Evaluate a value until certain metadata is available.
 ///
@@ -415,6 +520,10 @@ mod test {
             t.assert_eq("v = self:value:meta:set mkey mvalue value; { result = :v2, ^_ } = self:value:meta:eval mkey $v; self:value:meta:get mkey $v2", "mvalue");
         }
 
+        fn dependencies(t) {
+            t.assert_eq("{ id = :i, ^_ } = self:value:dependencies [a,b]; $i", "self:value:identity [a,b]");
+        }
+
         fn merge(t) {
             t.assert_eq("self:value:merge [1,2] [3,4]", "[1,2,3,4]");
             t.assert_eq("self:value:merge hi ()", "()");
@@ -0,0 +1,225 @@
+//! Static script checking.
+
+use super::evaluate::{
+    load_path,
+    output::{error as error_output, json, TermToTermcolor},
+    OutputFormat,
+};
+use crate::AppErr;
+use ergo_runtime::error::{Diagnostic, DiagnosticInfo, Severity};
+use ergo_script::{LintLevel, Runtime, Source};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, clap::Args)]
+/// Check scripts without evaluating them.
+///
+/// Scripts are parsed and compiled, reporting syntax errors, lint messages (such as unused or
+/// shadowed bindings and block items which have no effect), and names which are not bound in any
+/// enclosing scope nor in the top-level environment.
+///
+/// Exits with a non-zero status if any messages are reported.
+pub struct Check {
+    #[clap(long, default_value = "on")]
+    /// The lint level.
+    ///
+    /// May be "off", "on", or "aggressive" (which additionally reports shadowed bindings and
+    /// strings which match bindings in scope).
+    pub lint: LintLevel,
+
+    #[clap(long)]
+    /// Apply lint fixes to the files in place.
+    ///
+    /// Only fixes which preserve the meaning of the script (such as replacing unused bindings
+    /// with `_`) are applied; other fixes are reported as notes. Lints which are fixed are not
+    /// reported, though fixes which overlap other fixes are not applied and are reported.
+    pub fix: bool,
+
+    #[clap(long, default_value = "auto", arg_enum)]
+    /// The output format.
+    ///
+    /// The `json` format writes JSON Lines `diagnostic` events to stdout.
+    pub format: OutputFormat,
+
+    #[clap(required = true)]
+    /// Scripts to check.
+    ///
+    /// Directories are resolved to scripts as they are when loaded.
+    pub files: Vec<PathBuf>,
+}
+
+impl super::Command for Check {
+    fn run(self) -> Result<(), String> {
+        let runtime = Runtime::new(ergo_runtime::Context::builder(), load_path())
+            .expect("failed to create script context");
+        runtime.lint_level(self.lint);
+        let sources = runtime.ctx.global.diagnostic_sources();
+
+        let mut diagnostics = Vec::new();
+        for path in self.files {
+            let path = if path.is_dir() {
+                runtime
+                    .resolve_script_path(None, &path)
+                    .app_err(&format!("no script found in {}", path.display()))?
+            } else {
+                path
+            };
+            let source_id = sources
+                .add_file(path.clone())
+                .app_err(&format!("failed to read {}", path.display()))?;
+            match runtime.load(Source::new(source_id)) {
+                Err(e) => e.visit_diagnostics(|d| diagnostics.push(d.clone())),
+                Ok(script) => {
+                    let mut fixes = Vec::new();
+                    for m in script.lint_messages() {
+                        let (src, m) = m.clone().take();
+                        match m.fix {
+                            Some(fix) if self.fix && m.fix_is_safe => {
+                                fixes.push((src.with(fix), m.message))
+                            }
+                            fix => {
+                                let mut d = Diagnostic::from(m.message)
+                                    .set_severity(Severity::Warning)
+                                    .add_primary_label(src.with(""));
+                                if let Some(fix) = fix {
+                                    d = d.add_note(format_args!("fix: replace with `{}`", fix));
+                                }
+                                diagnostics.push(d);
+                            }
+                        }
+                    }
+                    if !fixes.is_empty() {
+                        let skipped =
+                            apply_fixes(&path, sources.content(source_id).unwrap(), fixes)
+                                .app_err(&format!("failed to write {}", path.display()))?;
+                        for (fix, message) in skipped {
+                            let (src, fix) = fix.take();
+                            diagnostics.push(
+                                Diagnostic::from(message)
+                                    .set_severity(Severity::Warning)
+                                    .add_primary_label(src.with(""))
+                                    .add_note(format_args!("fix: replace with `{}`", fix))
+                                    .add_note("the fix was not applied as it overlaps another fix"),
+                            );
+                        }
+                    }
+                    for name in script.unbound_names() {
+                        let (src, name) = name.take();
+                        diagnostics.push(
+                            Diagnostic::from(format!("unbound value `{}`", name))
+                                .add_primary_label(src.with("")),
+                        );
+                    }
+                }
+            }
+        }
+
+        if self.format == OutputFormat::Json {
+            let stdout = std::io::stdout();
+            let mut stdout = stdout.lock();
+            for d in &diagnostics {
+                json::write_event(
+                    &mut stdout,
+                    serde_json::json!({
+                        "type": "diagnostic",
+                        "diagnostic": json::diagnostic(d, sources.as_ref()),
+                    }),
+                )
+                .app_err("failed to write to output")?;
+            }
+        } else if !diagnostics.is_empty() {
+            use ergo_runtime::error::emit_diagnostics;
+            let err = error_output(self.format)
+                .app_err("could not create error output from requested format")?;
+            emit_diagnostics(&diagnostics, sources.as_ref(), &mut TermToTermcolor(err))
+                .map_err(|e| e.to_string())?;
+        }
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{} problem(s) found", diagnostics.len()))
+        }
+    }
+}
+
+/// A fix (the replacement of part of the content) and the message of the lint it fixes.
+type Fix = (Source<String>, String);
+
+/// Apply fixes to a file.
+///
+/// Fixes which overlap earlier fixes are skipped, and are returned.
+fn apply_fixes(path: &Path, content: &str, fixes: Vec<Fix>) -> std::io::Result<Vec<Fix>> {
+    let (result, skipped) = fixed_content(content, fixes);
+    std::fs::write(path, result)?;
+    Ok(skipped)
+}
+
+/// Get the content with fixes applied, and the fixes which were skipped.
+///
+/// Fix locations are byte offsets into the content. Fixes are applied in order of their start
+/// offset, and those which overlap an earlier fix are skipped.
+fn fixed_content(content: &str, mut fixes: Vec<Fix>) -> (String, Vec<Fix>) {
+    fixes.sort_by_key(|(f, _)| f.location.start);
+    let mut result = String::with_capacity(content.len());
+    let mut skipped = Vec::new();
+    let mut offset = 0;
+    for (fix, message) in fixes {
+        if fix.location.start < offset {
+            skipped.push((fix, message));
+            continue;
+        }
+        result.push_str(&content[offset..fix.location.start]);
+        offset = fix.location.end();
+        result.push_str(fix.value());
+    }
+    result.push_str(&content[offset..]);
+    (result, skipped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ergo_runtime::source::Location;
+
+    fn fix(start: usize, length: usize, replacement: &str) -> Fix {
+        let mut src = Source::missing(replacement.to_owned());
+        src.location = Location::new(start, length);
+        (src, format!("fix at {}", start))
+    }
+
+    #[test]
+    fn ordering() {
+        let (result, skipped) = fixed_content(
+            "a b c",
+            vec![fix(4, 1, "z"), fix(0, 1, "x"), fix(2, 1, "y")],
+        );
+        assert_eq!(result, "x y z");
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn overlapping() {
+        let (result, skipped) = fixed_content(
+            "abc def",
+            vec![
+                fix(4, 3, "_"),
+                fix(0, 3, "_"),
+                fix(1, 1, "x"),
+                fix(5, 2, "y"),
+            ],
+        );
+        assert_eq!(result, "_ _");
+        let skipped: Vec<_> = skipped.into_iter().map(|(_, m)| m).collect();
+        assert_eq!(skipped, vec!["fix at 1", "fix at 5"]);
+    }
+
+    #[test]
+    fn unicode_offsets() {
+        // Locations are byte offsets.
+        let content = "é = 1\nünused = 2\n";
+        let start = content.find("ünused").unwrap();
+        let (result, skipped) = fixed_content(content, vec![fix(start, "ünused".len(), "_")]);
+        assert_eq!(result, "é = 1\n_ = 2\n");
+        assert!(skipped.is_empty());
+    }
+}
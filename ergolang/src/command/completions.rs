@@ -0,0 +1,394 @@
+//! Shell completion.
+
+use super::evaluate::{command_script, load_path};
+use ergo_runtime::{context::Capabilities, metadata::Doc, types, Context};
+use ergo_script::constants::{DIR_NAME, EXTENSION, WORKSPACE_NAME};
+use ergo_script::Runtime;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The maximum time spent completing a word.
+///
+/// If exceeded, no candidates are printed.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, clap::ArgEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Debug, clap::Args)]
+/// Generate a shell completion script for `ergo`.
+///
+/// The script completes the first argument of `ergo` by calling back into `ergo`, listing scripts
+/// in the current directory and load path, the indices of the value being indexed (e.g. `ergo
+/// std:fs:<TAB>`), and workspace commands. Workspace command descriptions are taken from the
+/// documentation of `workspace:command`, from list items of the form `* <name> - <summary>`.
+///
+/// Values are evaluated without permission to execute programs, write files, or access the
+/// network, so only indices which can be determined without doing so are completed. Completion
+/// gives up (printing no candidates) after two seconds.
+///
+/// For example, add `source <(ergolang completions bash)` to `~/.bashrc`, or write the output of
+/// `ergolang completions fish` to `~/.config/fish/completions/ergo.fish`.
+pub struct Completions {
+    #[clap(arg_enum)]
+    /// The shell for which to generate the script.
+    pub shell: Shell,
+}
+
+const BASH: &str = r#"_ergo() {
+    local line="${COMP_LINE:0:COMP_POINT}"
+    local cur="${line##*[[:space:]]}"
+    local before=(${line%"$cur"})
+    local word
+    for word in "${before[@]:1}"; do
+        [[ "$word" == -* ]] || return
+    done
+    # Bash treats `:` as a word break, so only complete the part after the last `:`.
+    local prefix="${cur%"${cur##*:}"}"
+    local IFS=$'\n'
+    local candidate
+    COMPREPLY=()
+    for candidate in $(ergo --complete "$cur" 2>/dev/null); do
+        candidate="${candidate%%$'\t'*}"
+        COMPREPLY+=("${candidate#"$prefix"}")
+    done
+}
+complete -o nospace -F _ergo ergo
+"#;
+
+const ZSH: &str = r#"#compdef ergo
+_ergo() {
+    local word
+    for word in "${(@)words[2,CURRENT-1]}"; do
+        [[ "$word" == -* ]] || return 1
+    done
+    local -a candidates
+    candidates=("${(@f)$(ergo --complete "$PREFIX" 2>/dev/null | sed -e 's/:/\\:/g' -e 's/\t/:/')}")
+    _describe -t commands 'ergo' candidates -S ''
+}
+compdef _ergo ergo
+"#;
+
+const FISH: &str = r#"function __ergo_complete
+    for word in (commandline -opc)[2..-1]
+        string match -q -- '-*' $word; or return
+    end
+    ergo --complete (commandline -ct) 2>/dev/null
+end
+complete -c ergo -f -a '(__ergo_complete)'
+"#;
+
+impl super::Command for Completions {
+    fn run(self) -> Result<(), String> {
+        print!(
+            "{}",
+            match self.shell {
+                Shell::Bash => BASH,
+                Shell::Zsh => ZSH,
+                Shell::Fish => FISH,
+            }
+        );
+        Ok(())
+    }
+}
+
+/// A completion candidate.
+#[derive(Debug, PartialEq, Eq)]
+struct Candidate {
+    value: String,
+    description: Option<String>,
+}
+
+/// Print the completion candidates of the first argument of `ergo`, one per line.
+///
+/// Each line contains the candidate and, if available, a tab and its description.
+pub(crate) fn complete(word: &str) -> Result<(), String> {
+    // Give up rather than leaving the shell waiting on a slow evaluation.
+    std::thread::spawn(|| {
+        std::thread::sleep(TIMEOUT);
+        std::process::exit(0);
+    });
+
+    let runtime = runtime().expect("failed to create script context");
+    let roots: Vec<_> = std::env::current_dir()
+        .into_iter()
+        .chain(load_path())
+        .collect();
+
+    for c in candidates(&runtime, &roots, word) {
+        match c.description {
+            Some(d) => println!("{}\t{}", c.value, d),
+            None => println!("{}", c.value),
+        }
+    }
+    Ok(())
+}
+
+/// Get the completion candidates of a word, sorted and without duplicates.
+///
+/// Scripts are found relative to the given root directories (the current directory and load path).
+fn candidates(runtime: &Runtime, roots: &[PathBuf], word: &str) -> Vec<Candidate> {
+    let mut candidates = match word.rsplit_once(':') {
+        Some((value, index)) => indices(runtime, value)
+            .into_iter()
+            .filter(|k| k.starts_with(index))
+            .map(|k| Candidate {
+                value: format!("{}:{}", value, k),
+                description: None,
+            })
+            .collect(),
+        None => {
+            let mut candidates = scripts(roots, word);
+            candidates.extend(workspace_commands(runtime));
+            candidates.retain(|c| c.value.starts_with(word));
+            candidates
+        }
+    };
+    candidates.sort_by(|a, b| a.value.cmp(&b.value));
+    candidates.dedup_by(|a, b| a.value == b.value);
+    candidates
+}
+
+/// Get the scripts (in the given root directories) which may complete the given word.
+///
+/// Directories which are not scripts are also included (with a trailing `/`), so that nested
+/// scripts may be completed.
+fn scripts(roots: &[PathBuf], word: &str) -> Vec<Candidate> {
+    let dir = match word.rfind('/') {
+        Some(i) => &word[..i + 1],
+        None => "",
+    };
+    let mut ret = Vec::new();
+    for root in roots {
+        let entries = match std::fs::read_dir(root.join(dir)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let name = match entry.file_name().into_string() {
+                Ok(name) if !name.starts_with('.') => name,
+                _ => continue,
+            };
+            let name = if path.is_dir() {
+                if is_script_dir(&path) {
+                    name
+                } else {
+                    name + "/"
+                }
+            } else {
+                if name == DIR_NAME {
+                    continue;
+                }
+                match name.strip_suffix(&format!(".{}", EXTENSION)) {
+                    Some(name) => name.to_owned(),
+                    None => continue,
+                }
+            };
+            ret.push(Candidate {
+                value: format!("{}{}", dir, name),
+                description: None,
+            });
+        }
+    }
+    ret
+}
+
+/// Return whether a directory resolves to a script.
+fn is_script_dir(path: &Path) -> bool {
+    path.join(DIR_NAME).exists() || path.join(WORKSPACE_NAME).exists()
+}
+
+/// Create a runtime with which to evaluate values for completion.
+///
+/// Evaluation is a dry run with restricted capabilities so that it has no side effects: only
+/// scripts and plugins may be loaded.
+pub(crate) fn runtime() -> Result<Runtime, ergo_runtime::context::BuilderError> {
+    let root = std::env::current_dir()
+        .ok()
+        .and_then(|dir| dir.ancestors().last().map(|root| root.to_owned()));
+    let capabilities = Capabilities::none()
+        .with_read(root)
+        .with_plugin(load_path());
+    Runtime::new(
+        Context::builder().dry_run(true).capabilities(capabilities),
+        load_path(),
+    )
+}
+
+/// Evaluate the value that `ergo <value>` would load, and get its (String) indices.
+fn indices(runtime: &Runtime, value: &str) -> Vec<String> {
+    script_indices(
+        runtime,
+        &command_script(runtime, false, &[value.to_owned()]),
+    )
+}
+
+/// Evaluate a script, and get the (String) indices of the resulting value.
+pub(crate) fn script_indices(runtime: &Runtime, script: &str) -> Vec<String> {
+    let value = match runtime.evaluate_string("<completion>", script) {
+        Ok(v) => v,
+        Err(_) => return Vec::new(),
+    };
+    runtime.block_on(async move {
+        let mut value = value;
+        if Context::eval(&mut value).await.is_err() {
+            return Vec::new();
+        }
+        if let Some(t) = value.as_ref::<types::Type>() {
+            value = t.index.clone();
+            if Context::eval(&mut value).await.is_err() {
+                return Vec::new();
+            }
+        }
+        match value.as_ref::<types::Map>() {
+            Some(types::Map(entries)) => entries
+                .iter()
+                .filter_map(|(k, _)| k.as_ref::<types::String>().map(|s| s.to_string()))
+                .collect(),
+            None => Vec::new(),
+        }
+    })
+}
+
+/// Get the workspace commands listed in the documentation of `workspace:command`.
+fn workspace_commands(runtime: &Runtime) -> Vec<Candidate> {
+    let doc = runtime
+        .evaluate_string("<completion>", "workspace:command")
+        .and_then(|v| runtime.block_on(Doc::get(&v)));
+    match doc {
+        Ok(doc) => doc.lines().filter_map(command_item).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Parse a documentation list item of the form `* <name> [args] - <summary>`.
+fn command_item(line: &str) -> Option<Candidate> {
+    let item = line
+        .trim_start()
+        .strip_prefix("* ")
+        .or_else(|| line.trim_start().strip_prefix("- "))?;
+    let (usage, summary) = match item.split_once(" - ") {
+        Some((usage, summary)) => (usage, Some(summary.trim().to_owned())),
+        None => (item, None),
+    };
+    let name = usage.split_whitespace().next()?;
+    if name.starts_with(|c: char| !c.is_alphanumeric()) {
+        return None;
+    }
+    Some(Candidate {
+        value: name.to_owned(),
+        description: summary.filter(|s| !s.is_empty()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(value: &str, description: Option<&str>) -> Candidate {
+        Candidate {
+            value: value.into(),
+            description: description.map(|s| s.into()),
+        }
+    }
+
+    /// Create an empty temporary directory for a test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ergo-completions-{}-{}", name, std::process::id()));
+        drop(std::fs::remove_dir_all(&dir));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn workspace_command_items() {
+        let items: Vec<_> = include_str!("../../../workspace.ergo")
+            .lines()
+            .filter_map(|l| l.strip_prefix("## "))
+            .filter_map(command_item)
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                candidate("build", Some("build all crates")),
+                candidate("test", Some("build and run tests")),
+                candidate("package", Some("create installable package")),
+                candidate("doc", Some("generate documentation")),
+            ]
+        );
+    }
+
+    #[test]
+    fn command_items() {
+        assert_eq!(
+            command_item("  - run <args> - run  it "),
+            Some(candidate("run", Some("run  it")))
+        );
+        assert_eq!(command_item("* run"), Some(candidate("run", None)));
+        assert_eq!(command_item("* run - "), Some(candidate("run", None)));
+        assert_eq!(
+            command_item("* my-cmd.v2 - x - y"),
+            Some(candidate("my-cmd.v2", Some("x - y")))
+        );
+        assert_eq!(command_item("* `run` - quoted"), None);
+        assert_eq!(command_item("*run - no space"), None);
+        assert_eq!(command_item("Commands:"), None);
+        assert_eq!(command_item("* "), None);
+    }
+
+    #[test]
+    fn script_candidates() {
+        let root = temp_dir("scripts");
+        std::fs::write(root.join("a.ergo"), "").unwrap();
+        std::fs::write(root.join("b.txt"), "").unwrap();
+        std::fs::write(root.join(".hidden.ergo"), "").unwrap();
+        std::fs::create_dir_all(root.join("lib/nested")).unwrap();
+        std::fs::write(root.join("lib/nested.ergo"), "").unwrap();
+        std::fs::write(root.join(format!("lib/{}", DIR_NAME)), "").unwrap();
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        std::fs::write(root.join("pkg").join(WORKSPACE_NAME), "").unwrap();
+
+        let values = |word| {
+            let mut v: Vec<_> = scripts(&[root.clone()], word)
+                .into_iter()
+                .map(|c| c.value)
+                .collect();
+            v.sort();
+            v
+        };
+        assert_eq!(values(""), vec!["a", "lib", "pkg"]);
+        assert_eq!(values("lib/"), vec!["lib/nested", "lib/nested/"]);
+        assert!(values("missing/").is_empty());
+        drop(std::fs::remove_dir_all(&root));
+    }
+
+    #[test]
+    fn complete_candidates() {
+        let root = temp_dir("complete");
+        std::fs::write(root.join("a.ergo"), "{alpha = 1, beta = 2, also = 3}").unwrap();
+        std::fs::write(root.join("ab.ergo"), "").unwrap();
+        let runtime = runtime().unwrap();
+        let roots = [root.clone()];
+
+        let values = |word: &str| -> Vec<_> {
+            candidates(&runtime, &roots, word)
+                .into_iter()
+                .map(|c| c.value)
+                .collect()
+        };
+        assert_eq!(values("a"), vec!["a", "ab"]);
+        let script = root.join("a").display().to_string();
+        assert_eq!(
+            values(&format!("{}:al", script)),
+            vec![format!("{}:alpha", script), format!("{}:also", script)]
+        );
+        drop(runtime);
+        drop(std::fs::remove_dir_all(&root));
+    }
+}
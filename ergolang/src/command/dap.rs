@@ -0,0 +1,705 @@
+//! Debug adapter.
+//!
+//! The Debug Adapter Protocol is served over stdio. A single evaluation (configured by the launch
+//! request) is debugged, which is represented as a single thread to the client.
+
+use super::evaluate::{command_script, load_path, lock_file, storage_directory, sync};
+use ergo_runtime::abi_stable::std_types::RArc;
+use ergo_runtime::context::{GlobalContext, LogEntry, LogLevel, LogTarget};
+use ergo_runtime::{error::Diagnostics, try_value, Error};
+use ergo_script::debug::{DebugClient, Debugger, StopReason, Stopped};
+use ergo_script::{Runtime, Source};
+use serde_json::{json, Value as JsonValue};
+use std::io::{BufRead, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, clap::Args)]
+/// Run a debug adapter.
+///
+/// The Debug Adapter Protocol is used over stdin/stdout. The launch request supports the following
+/// arguments:
+/// * `args`: the arguments to evaluate, as with the `evaluate` command
+/// * `expression`: whether to evaluate `args` as an expression (like `evaluate -e`)
+/// * `cwd`: the directory in which to evaluate
+pub struct Dap {
+    #[clap(long = "log", default_value = "warn")]
+    /// The runtime log level.
+    ///
+    /// May be "debug", "info", "warn", or "error".
+    pub log_level: LogLevel,
+
+    #[clap(short, long)]
+    /// The maximum number of jobs to run concurrently.
+    ///
+    /// If unspecified, the number of cpus is used.
+    pub jobs: Option<usize>,
+
+    #[clap(long, default_value = concat!(".ergo_work"))]
+    /// The storage directory for the runtime.
+    ///
+    /// If a relative path, it will be made relative to the furthest ancestor directory that is a
+    /// workspace. If none are found, the current directory is used.
+    pub storage: std::path::PathBuf,
+}
+
+/// The thread id of the evaluation.
+const THREAD_ID: u64 = 1;
+
+/// The variables reference of the captures scope.
+const CAPTURES_REFERENCE: u64 = 1;
+/// The variables reference of the dynamic scope.
+const DYNAMIC_SCOPE_REFERENCE: u64 = 2;
+
+/// How often to check for stopped or completed evaluation while waiting for requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Read a message (with its Content-Length header).
+///
+/// Returns None if the input has ended.
+fn read_message<R: BufRead>(r: &mut R) -> std::io::Result<Option<JsonValue>> {
+    let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            length = Some(
+                v.trim()
+                    .parse::<usize>()
+                    .map_err(|e| invalid(e.to_string()))?,
+            );
+        }
+    }
+    let length = length.ok_or_else(|| invalid("missing Content-Length header".into()))?;
+    let mut buf = vec![0; length];
+    r.read_exact(&mut buf)?;
+    serde_json::from_slice(&buf)
+        .map(Some)
+        .map_err(|e| invalid(e.to_string()))
+}
+
+/// The writer of outgoing messages, which may be shared between threads.
+#[derive(Clone)]
+struct Writer {
+    inner: Arc<Mutex<WriterInner>>,
+}
+
+struct WriterInner {
+    seq: u64,
+    output: Box<dyn Write + Send>,
+}
+
+impl Writer {
+    fn new<W: Write + Send + 'static>(output: W) -> Self {
+        Writer {
+            inner: Arc::new(Mutex::new(WriterInner {
+                seq: 0,
+                output: Box::new(output),
+            })),
+        }
+    }
+
+    fn send(&self, mut message: JsonValue) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.seq += 1;
+        message["seq"] = inner.seq.into();
+        let body = message.to_string();
+        // If the output is closed, the client is gone and there is nothing to do.
+        drop(
+            write!(
+                inner.output,
+                "Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .and_then(|()| inner.output.flush()),
+        );
+    }
+
+    fn event(&self, event: &str, body: JsonValue) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn respond(&self, request: &JsonValue, result: Result<JsonValue, String>) {
+        let mut message = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => message["body"] = body,
+            Err(e) => message["message"] = e.into(),
+        }
+        self.send(message);
+    }
+
+    fn output(&self, category: &str, output: String) {
+        self.event("output", json!({ "category": category, "output": output }));
+    }
+}
+
+/// A LogTarget which sends log entries as output events.
+struct OutputLog {
+    writer: Writer,
+    level: LogLevel,
+}
+
+impl LogTarget for OutputLog {
+    fn log(&self, entry: LogEntry) {
+        if entry.level >= self.level {
+            self.writer.output("console", format!("{}\n", entry));
+        }
+    }
+}
+
+/// A running evaluation.
+struct Evaluation {
+    global: RArc<GlobalContext>,
+    task: sync::ScopedRef<ergo_runtime::context::TaskManager>,
+    /// Receives the displayed result or the rendered errors when evaluation completes.
+    complete: mpsc::Receiver<Result<String, String>>,
+}
+
+struct Server {
+    options: Dap,
+    writer: Writer,
+    debugger: Debugger,
+    client: DebugClient,
+    launch: Option<JsonValue>,
+    evaluation: Option<Evaluation>,
+    stopped: Option<Stopped>,
+}
+
+impl Server {
+    /// Start evaluation with the launch arguments.
+    fn start(&mut self) -> Result<(), String> {
+        let launch = self.launch.take().unwrap_or_default();
+        if let Some(cwd) = launch["cwd"].as_str() {
+            std::env::set_current_dir(cwd)
+                .map_err(|e| format!("failed to change directory to {}: {}", cwd, e))?;
+        }
+        let args: Vec<String> = match &launch["args"] {
+            JsonValue::Null => Default::default(),
+            v => serde_json::from_value(v.clone())
+                .map_err(|_| "`args` must be an array of strings".to_owned())?,
+        };
+        let expression = launch["expression"].as_bool().unwrap_or(false);
+
+        let errors = Arc::new(Mutex::new(Diagnostics::new()));
+        let runtime = {
+            let errors = errors.clone();
+            Runtime::new(
+                ergo_runtime::Context::builder()
+                    .logger(OutputLog {
+                        writer: self.writer.clone(),
+                        level: self.options.log_level,
+                    })
+                    .storage_directory(storage_directory(&self.options.storage))
+                    .lock_file(lock_file())
+                    .threads(self.options.jobs)
+                    .keep_going(true)
+                    .error_handler(move |e: Error| {
+                        errors.lock().unwrap().insert(&e);
+                    }),
+                load_path(),
+            )
+            .map_err(|e| format!("failed to create script context: {}", e))?
+        };
+        runtime.debugger(Some(self.debugger.clone()));
+
+        let to_eval = command_script(&runtime, expression, &args);
+        let global = runtime.ctx.global.clone();
+        let (task_scope, task) = sync::Scoped::new_pair(runtime.ctx.task.clone());
+        let (complete_send, complete) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let result = runtime
+                .evaluate_string("<command line>", &to_eval)
+                .and_then(|v| {
+                    let v = runtime.block_on(Runtime::apply_unbound(v));
+                    Ok(try_value!(v))
+                })
+                .and_then(|value| {
+                    runtime.block_on(async {
+                        use ergo_runtime::traits::{display, eval_nested, Formatter};
+                        eval_nested(value.clone()).await?;
+                        let mut s = String::new();
+                        {
+                            let mut formatter = Formatter::new(&mut s);
+                            display(value, &mut formatter).await?;
+                        }
+                        Ok(s)
+                    })
+                });
+
+            let mut errors = std::mem::take(&mut *errors.lock().unwrap());
+            let result = match result {
+                Ok(s) if errors.len() == 0 => Ok(s),
+                o => {
+                    if let Err(e) = o {
+                        errors.insert(&e);
+                    }
+                    let sources = runtime.ctx.global.diagnostic_sources();
+                    Err(ergo_runtime::error::diagnostics_to_string(
+                        &errors,
+                        sources.as_ref(),
+                        false,
+                    ))
+                }
+            };
+            drop(errors);
+
+            // Before the context is destroyed (unloading plugins), clear the thread-local storage in case
+            // there are values which were allocated in the plugins.
+            ergo_runtime::plugin::Context::reset();
+
+            drop(task_scope);
+            runtime.ctx.global.hooks().shutdown();
+            drop(runtime);
+
+            drop(complete_send.send(result));
+        });
+
+        self.evaluation = Some(Evaluation {
+            global,
+            task,
+            complete,
+        });
+        Ok(())
+    }
+
+    /// Get the protocol source, line, and column of a source location.
+    fn location<T>(&self, src: &Source<T>) -> (JsonValue, u64, u64) {
+        let evaluation = match &self.evaluation {
+            Some(e) => e,
+            None => return (JsonValue::Null, 0, 0),
+        };
+        let sources = evaluation.global.diagnostic_sources();
+        let source = match (sources.path(src.source_id), sources.name(src.source_id)) {
+            (Some(path), _) => json!({
+                "name": path.file_name().map(|s| s.to_string_lossy().into_owned()),
+                "path": path,
+            }),
+            (None, Some(name)) => json!({ "name": name.to_string() }),
+            (None, None) => JsonValue::Null,
+        };
+        let (line, column) = sources.line_column(src).unwrap_or_default();
+        (source, line as u64, column as u64)
+    }
+
+    fn stopped(&self) -> Result<&Stopped, String> {
+        self.stopped
+            .as_ref()
+            .ok_or_else(|| "evaluation is not paused".to_owned())
+    }
+
+    /// Handle a request, returning the response body.
+    fn handle(&mut self, command: &str, args: &JsonValue) -> Result<JsonValue, String> {
+        match command {
+            "initialize" => Ok(json!({ "supportsConfigurationDoneRequest": true })),
+            "launch" => {
+                self.launch = Some(args.clone());
+                Ok(JsonValue::Null)
+            }
+            "setBreakpoints" => {
+                let path = args["source"]["path"]
+                    .as_str()
+                    .ok_or("breakpoints must be set on a source path")?;
+                let lines: Vec<u64> = args["breakpoints"]
+                    .as_array()
+                    .map(|bps| bps.iter().filter_map(|b| b["line"].as_u64()).collect())
+                    .unwrap_or_default();
+                self.client
+                    .set_breakpoints(path.as_ref(), lines.iter().map(|l| *l as usize));
+                Ok(json!({
+                    "breakpoints": lines
+                        .iter()
+                        .map(|l| json!({ "verified": true, "line": l }))
+                        .collect::<Vec<_>>()
+                }))
+            }
+            "configurationDone" => self.start().map(|()| JsonValue::Null),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "evaluation" }] })),
+            "stackTrace" => {
+                let stopped = self.stopped()?;
+                let mut frames = Vec::new();
+                let (source, line, column) = self.location(&stopped.location);
+                frames.push(json!({
+                    "id": 0,
+                    "name": "paused expression",
+                    "source": source,
+                    "line": line,
+                    "column": column,
+                }));
+                for (i, frame) in stopped.backtrace.iter().enumerate() {
+                    let (source, line, column) = self.location(frame);
+                    frames.push(json!({
+                        "id": i + 1,
+                        "name": frame.value(),
+                        "source": source,
+                        "line": line,
+                        "column": column,
+                    }));
+                }
+                Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+            }
+            "scopes" => Ok(json!({
+                "scopes": [
+                    {
+                        "name": "Captures",
+                        "variablesReference": CAPTURES_REFERENCE,
+                        "expensive": false,
+                    },
+                    {
+                        "name": "Dynamic Scope",
+                        "variablesReference": DYNAMIC_SCOPE_REFERENCE,
+                        "expensive": false,
+                    },
+                ]
+            })),
+            "variables" => {
+                let stopped = self.stopped()?;
+                let variables: Vec<_> = match args["variablesReference"].as_u64() {
+                    Some(CAPTURES_REFERENCE) => stopped
+                        .captures
+                        .iter()
+                        .map(|v| {
+                            json!({
+                                "name": v.name,
+                                "value": v
+                                    .value
+                                    .as_deref()
+                                    .or(v.type_name.as_deref())
+                                    .unwrap_or("<unevaluated>"),
+                                "type": v.type_name,
+                                "variablesReference": 0,
+                            })
+                        })
+                        .collect(),
+                    Some(DYNAMIC_SCOPE_REFERENCE) => stopped
+                        .dynamic_scope
+                        .iter()
+                        .map(|b| {
+                            let (source, line, column) = self.location(&b.location);
+                            let name = source["name"].as_str().unwrap_or("<unknown>");
+                            json!({
+                                "name": format!("set at {}:{}:{}", name, line, column),
+                                "value": b
+                                    .value
+                                    .as_deref()
+                                    .or(b.type_name.as_deref())
+                                    .unwrap_or("<unavailable>"),
+                                "type": b.type_name,
+                                "variablesReference": 0,
+                            })
+                        })
+                        .collect(),
+                    _ => return Err("unknown variables reference".into()),
+                };
+                Ok(json!({ "variables": variables }))
+            }
+            "continue" => {
+                if self.stopped.take().is_none() || !self.client.resume() {
+                    return Err("evaluation is not paused".into());
+                }
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                if self.stopped.take().is_none() || !self.client.step() {
+                    return Err("evaluation is not paused".into());
+                }
+                Ok(JsonValue::Null)
+            }
+            "pause" => {
+                self.client.pause();
+                Ok(JsonValue::Null)
+            }
+            "evaluate" => {
+                let expression = args["expression"]
+                    .as_str()
+                    .ok_or("missing expression to evaluate")?;
+                match self.client.evaluate(expression) {
+                    None => Err("evaluation is not paused".into()),
+                    Some(Ok(result)) => Ok(json!({ "result": result, "variablesReference": 0 })),
+                    Some(Err(e)) => Err(e.to_string()),
+                }
+            }
+            "disconnect" | "terminate" => {
+                if let Some(evaluation) = &self.evaluation {
+                    evaluation.task.with(|t| t.abort());
+                }
+                if self.stopped.take().is_some() {
+                    self.client.resume();
+                }
+                Ok(JsonValue::Null)
+            }
+            other => Err(format!("unsupported request: {}", other)),
+        }
+    }
+
+    /// Send events for stopped or completed evaluation.
+    fn poll(&mut self) {
+        while let Some(stopped) = self.client.next_stop(Duration::ZERO) {
+            let reason = match stopped.reason {
+                StopReason::Breakpoint => "breakpoint",
+                StopReason::Step => "step",
+                StopReason::Pause => "pause",
+            };
+            self.stopped = Some(stopped);
+            self.writer.event(
+                "stopped",
+                json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+            );
+        }
+
+        let result = match &self.evaluation {
+            None => return,
+            Some(evaluation) => match evaluation.complete.try_recv() {
+                Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => Err("evaluation failed".into()),
+            },
+        };
+        self.evaluation = None;
+        self.stopped = None;
+        let exit_code = match result {
+            Ok(s) => {
+                self.writer.output("stdout", format!("{}\n", s));
+                0
+            }
+            Err(e) => {
+                self.writer.output("stderr", e);
+                1
+            }
+        };
+        self.writer
+            .event("exited", json!({ "exitCode": exit_code }));
+        self.writer.event("terminated", json!({}));
+    }
+}
+
+impl super::Command for Dap {
+    fn run(self) -> Result<(), String> {
+        let (message_send, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            let stdin = std::io::stdin();
+            let mut stdin = stdin.lock();
+            loop {
+                let message = read_message(&mut stdin);
+                let done = !matches!(message, Ok(Some(_)));
+                if message_send.send(message).is_err() || done {
+                    break;
+                }
+            }
+        });
+
+        let (debugger, client) = Debugger::new();
+        let mut server = Server {
+            options: self,
+            writer: Writer::new(std::io::stdout()),
+            debugger,
+            client,
+            launch: None,
+            evaluation: None,
+            stopped: None,
+        };
+
+        loop {
+            server.poll();
+            let message = match messages.recv_timeout(POLL_INTERVAL) {
+                Ok(m) => m,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            let request = match message {
+                Ok(Some(m)) => m,
+                Ok(None) => break,
+                Err(e) => return Err(format!("failed to read message: {}", e)),
+            };
+            if request["type"] != "request" {
+                continue;
+            }
+            let command = request["command"].as_str().unwrap_or_default().to_owned();
+            let result = server.handle(&command, &request["arguments"]);
+            server.writer.respond(&request, result);
+            match command.as_str() {
+                "initialize" => server.writer.event("initialized", json!({})),
+                "disconnect" => break,
+                _ => (),
+            }
+        }
+
+        // Allow any paused evaluation to complete (it has been aborted).
+        let Server {
+            client, evaluation, ..
+        } = server;
+        drop(client);
+        if let Some(evaluation) = evaluation {
+            drop(evaluation.complete.recv());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// An output buffer which may be shared with a `Writer`.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn encode(messages: &[JsonValue]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for m in messages {
+            let body = m.to_string();
+            write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        }
+        out
+    }
+
+    fn decode(data: Vec<u8>) -> Vec<JsonValue> {
+        let mut data = std::io::Cursor::new(data);
+        let mut messages = Vec::new();
+        while let Some(m) = read_message(&mut data).expect("invalid message") {
+            messages.push(m);
+        }
+        messages
+    }
+
+    /// Handle the requests with a new server, returning the messages it sends.
+    fn round_trip(requests: &[JsonValue]) -> Vec<JsonValue> {
+        let output = Buffer::default();
+        let (debugger, client) = Debugger::new();
+        let mut server = Server {
+            options: Dap {
+                log_level: LogLevel::Warn,
+                jobs: None,
+                storage: ".ergo_work".into(),
+            },
+            writer: Writer::new(output.clone()),
+            debugger,
+            client,
+            launch: None,
+            evaluation: None,
+            stopped: None,
+        };
+        for request in decode(encode(requests)) {
+            let command = request["command"].as_str().unwrap_or_default().to_owned();
+            let result = server.handle(&command, &request["arguments"]);
+            server.writer.respond(&request, result);
+        }
+        let data = output.0.lock().unwrap().clone();
+        decode(data)
+    }
+
+    fn request(seq: u64, command: &str, arguments: JsonValue) -> JsonValue {
+        json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })
+    }
+
+    #[test]
+    fn requests() {
+        let responses = round_trip(&[
+            request(1, "initialize", json!({ "adapterID": "ergo" })),
+            request(2, "threads", JsonValue::Null),
+            request(3, "continue", json!({ "threadId": THREAD_ID })),
+            request(
+                4,
+                "variables",
+                json!({ "variablesReference": CAPTURES_REFERENCE }),
+            ),
+            request(5, "evaluate", json!({ "expression": "1" })),
+            request(6, "restart", JsonValue::Null),
+        ]);
+        assert_eq!(
+            responses,
+            vec![
+                json!({
+                    "seq": 1,
+                    "type": "response",
+                    "request_seq": 1,
+                    "command": "initialize",
+                    "success": true,
+                    "body": { "supportsConfigurationDoneRequest": true },
+                }),
+                json!({
+                    "seq": 2,
+                    "type": "response",
+                    "request_seq": 2,
+                    "command": "threads",
+                    "success": true,
+                    "body": { "threads": [{ "id": THREAD_ID, "name": "evaluation" }] },
+                }),
+                json!({
+                    "seq": 3,
+                    "type": "response",
+                    "request_seq": 3,
+                    "command": "continue",
+                    "success": false,
+                    "message": "evaluation is not paused",
+                }),
+                json!({
+                    "seq": 4,
+                    "type": "response",
+                    "request_seq": 4,
+                    "command": "variables",
+                    "success": false,
+                    "message": "evaluation is not paused",
+                }),
+                json!({
+                    "seq": 5,
+                    "type": "response",
+                    "request_seq": 5,
+                    "command": "evaluate",
+                    "success": false,
+                    "message": "evaluation is not paused",
+                }),
+                json!({
+                    "seq": 6,
+                    "type": "response",
+                    "request_seq": 6,
+                    "command": "restart",
+                    "success": false,
+                    "message": "unsupported request: restart",
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_messages() {
+        let mut empty = std::io::Cursor::new(Vec::new());
+        assert!(read_message(&mut empty).unwrap().is_none());
+
+        let mut missing_length = std::io::Cursor::new(b"Content-Type: json\r\n\r\n{}".to_vec());
+        assert_eq!(
+            read_message(&mut missing_length).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        let mut invalid_json = std::io::Cursor::new(b"Content-Length: 2\r\n\r\n{]".to_vec());
+        assert_eq!(
+            read_message(&mut invalid_json).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+}
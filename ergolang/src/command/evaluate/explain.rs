@@ -0,0 +1,395 @@
+//! Value identity explanation.
+//!
+//! The dependency tree of the final value's identity is retrieved with `std:value:dependencies`,
+//! printed, and saved in the storage directory so that a later run of the same command can print
+//! the differences between the two trees.
+
+use ergo_runtime::{types, Value};
+use std::fmt::Write;
+
+/// The dependency tree of a value identity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdTree {
+    id: String,
+    type_name: Option<String>,
+    source: Option<String>,
+    kind: Kind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Kind {
+    /// A constant dependency.
+    Constant,
+    /// A value which occurs earlier in the tree.
+    Repeated,
+    /// A value with the given dependencies.
+    Value(Vec<IdTree>),
+}
+
+/// The directory (within the storage directory) in which trees are saved.
+const SAVE_DIRECTORY: &str = "explain-id";
+
+/// The path of the saved tree for the given command.
+pub fn save_path(storage_directory: &std::path::Path, to_eval: &str) -> std::path::PathBuf {
+    use std::hash::Hash;
+    let mut h = ergo_runtime::hash::HashFn::default();
+    to_eval.hash(&mut h);
+    storage_directory
+        .join(SAVE_DIRECTORY)
+        .join(format!("{:032x}", h.finish_ext()))
+}
+
+impl IdTree {
+    /// Create a tree from the (evaluated) Map returned by `std:value:dependencies`.
+    pub fn from_value(v: &Value) -> Option<Self> {
+        let map = v.as_ref::<types::Map>()?;
+        let get = |key: &str| {
+            map.0
+                .iter()
+                .find_map(|(k, v)| match k.as_ref::<types::String>() {
+                    Some(s) if s.as_str() == key => Some(v),
+                    _ => None,
+                })
+        };
+        let get_string =
+            |key: &str| get(key).and_then(|v| v.as_ref::<types::String>().map(|s| s.to_string()));
+
+        let kind = if let Some(deps) = get("dependencies") {
+            let deps = deps.as_ref::<types::Array>()?;
+            Kind::Value(
+                deps.0
+                    .iter()
+                    .map(IdTree::from_value)
+                    .collect::<Option<Vec<_>>>()?,
+            )
+        } else if get("repeated").is_some() {
+            Kind::Repeated
+        } else {
+            Kind::Constant
+        };
+
+        Some(IdTree {
+            id: get_string("id")?,
+            type_name: get_string("type"),
+            source: get_string("source"),
+            kind,
+        })
+    }
+
+    /// Serialize the tree to a line-based format.
+    ///
+    /// Each node is a line with tab-separated depth, kind, id, type name, and source fields.
+    pub fn serialize(&self) -> String {
+        let mut s = String::new();
+        self.serialize_into(0, &mut s);
+        s
+    }
+
+    fn serialize_into(&self, depth: usize, out: &mut String) {
+        let field = |s: &Option<String>| {
+            s.as_deref()
+                .unwrap_or("")
+                .replace(|c: char| c == '\t' || c == '\n', " ")
+        };
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}",
+            depth,
+            match self.kind {
+                Kind::Constant => "c",
+                Kind::Repeated => "r",
+                Kind::Value(_) => "v",
+            },
+            self.id,
+            field(&self.type_name),
+            field(&self.source)
+        )
+        .unwrap();
+        if let Kind::Value(children) = &self.kind {
+            for c in children {
+                c.serialize_into(depth + 1, out);
+            }
+        }
+    }
+
+    /// Deserialize a tree from the format produced by `serialize`.
+    pub fn deserialize(s: &str) -> Option<Self> {
+        let mut lines = s.lines().peekable();
+        let tree = Self::deserialize_from(0, &mut lines)?;
+        if lines.next().is_some() {
+            None
+        } else {
+            Some(tree)
+        }
+    }
+
+    fn deserialize_from(
+        depth: usize,
+        lines: &mut std::iter::Peekable<std::str::Lines>,
+    ) -> Option<Self> {
+        let mut fields = lines.next()?.splitn(5, '\t');
+        let mut next = || fields.next();
+        if next()?.parse::<usize>().ok()? != depth {
+            return None;
+        }
+        let kind = next()?;
+        let id = next()?.to_owned();
+        let optional = |s: &str| (!s.is_empty()).then(|| s.to_owned());
+        let type_name = optional(next()?);
+        let source = optional(next()?);
+
+        let kind = match kind {
+            "c" => Kind::Constant,
+            "r" => Kind::Repeated,
+            "v" => {
+                let mut children = Vec::new();
+                while let Some(line) = lines.peek() {
+                    let child_depth = line.split('\t').next()?.parse::<usize>().ok()?;
+                    if child_depth <= depth {
+                        break;
+                    }
+                    children.push(Self::deserialize_from(depth + 1, lines)?);
+                }
+                Kind::Value(children)
+            }
+            _ => return None,
+        };
+
+        Some(IdTree {
+            id,
+            type_name,
+            source,
+            kind,
+        })
+    }
+
+    /// A single-line description of the node.
+    fn label(&self) -> String {
+        let mut s = self.id.clone();
+        if let Some(t) = &self.type_name {
+            write!(s, " {}", t).unwrap();
+        }
+        if let Some(src) = &self.source {
+            write!(s, " at {}", src).unwrap();
+        }
+        match self.kind {
+            Kind::Constant => s.push_str(" (constant)"),
+            Kind::Repeated => s.push_str(" (repeated)"),
+            Kind::Value(_) => (),
+        }
+        s
+    }
+
+    /// Render the tree for display.
+    pub fn render(&self) -> String {
+        let mut s = String::new();
+        self.render_into(0, &mut s);
+        s
+    }
+
+    fn render_into(&self, depth: usize, out: &mut String) {
+        writeln!(out, "{:indent$}{}", "", self.label(), indent = depth * 2).unwrap();
+        if let Kind::Value(children) = &self.kind {
+            for c in children {
+                c.render_into(depth + 1, out);
+            }
+        }
+    }
+
+    /// Render the differences between a prior tree and this tree.
+    pub fn render_diff(&self, prior: &IdTree) -> String {
+        if self.id == prior.id {
+            return format!("identity unchanged: {}", self.id);
+        }
+        let mut s = String::from("identity changed:\n");
+        diff(prior, self, 0, &mut s);
+        s
+    }
+}
+
+/// Write the differences between two nodes with differing ids.
+///
+/// Children of the nodes are paired by equal ids first (which are unchanged), then by equal
+/// sources, and finally by order.
+fn diff(old: &IdTree, new: &IdTree, depth: usize, out: &mut String) {
+    let indent = depth * 2;
+    writeln!(
+        out,
+        "{:indent$}~ {} -> {}",
+        "",
+        old.id,
+        new.label(),
+        indent = indent
+    )
+    .unwrap();
+
+    let (old_children, new_children) = match (&old.kind, &new.kind) {
+        (Kind::Value(o), Kind::Value(n)) => (o, n),
+        _ => return,
+    };
+
+    let mut old_used = vec![false; old_children.len()];
+    let mut pairs: Vec<Option<usize>> = vec![None; new_children.len()];
+
+    let mut pair_by = |matches: &dyn Fn(&IdTree, &IdTree) -> bool| {
+        for (ni, n) in new_children.iter().enumerate() {
+            if pairs[ni].is_some() {
+                continue;
+            }
+            if let Some(oi) =
+                (0..old_children.len()).find(|&oi| !old_used[oi] && matches(&old_children[oi], n))
+            {
+                old_used[oi] = true;
+                pairs[ni] = Some(oi);
+            }
+        }
+    };
+    pair_by(&|o, n| o.id == n.id);
+    pair_by(&|o, n| o.source.is_some() && o.source == n.source);
+    pair_by(&|_, _| true);
+
+    for (n, pair) in new_children.iter().zip(pairs) {
+        match pair {
+            Some(oi) => {
+                let o = &old_children[oi];
+                if o.id != n.id {
+                    diff(o, n, depth + 1, out);
+                }
+            }
+            None => writeln!(out, "{:indent$}+ {}", "", n.label(), indent = indent + 2).unwrap(),
+        }
+    }
+    for (o, used) in old_children.iter().zip(old_used) {
+        if !used {
+            writeln!(out, "{:indent$}- {}", "", o.label(), indent = indent + 2).unwrap();
+        }
+    }
+}
+
+/// Produce the output for an explained identity tree (as returned by `IdTree::serialize`).
+///
+/// The tree is saved to `path`. If `diff` is set, the differences from the previously saved tree
+/// are returned rather than the tree itself.
+pub fn output(tree: &str, path: &std::path::Path, diff: bool) -> Result<String, String> {
+    let tree = IdTree::deserialize(tree).ok_or("invalid identity dependency tree")?;
+
+    let prior = if diff {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|s| IdTree::deserialize(&s))
+    } else {
+        None
+    };
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
+    }
+    std::fs::write(path, tree.serialize())
+        .map_err(|e| format!("failed to save identity tree to {}: {}", path.display(), e))?;
+
+    Ok(match prior {
+        Some(prior) => tree.render_diff(&prior),
+        None if diff => format!(
+            "no prior identity tree for this command, the current tree is:\n{}",
+            tree.render()
+        ),
+        None => tree.render(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IdTree, Kind};
+
+    fn node(id: &str, source: Option<&str>, kind: Kind) -> IdTree {
+        IdTree {
+            id: id.to_owned(),
+            type_name: Some("String".to_owned()),
+            source: source.map(|s| s.to_owned()),
+            kind,
+        }
+    }
+
+    fn tree(leaf_id: &str) -> IdTree {
+        node(
+            "root",
+            Some("a.ergo:1:1"),
+            Kind::Value(vec![
+                node("const", None, Kind::Constant),
+                node(
+                    "leaf",
+                    Some("a.ergo:2:1"),
+                    Kind::Value(vec![node(leaf_id, None, Kind::Constant)]),
+                ),
+                node("const", None, Kind::Repeated),
+            ]),
+        )
+    }
+
+    #[test]
+    fn serialize_roundtrip() {
+        let t = tree("x");
+        assert_eq!(IdTree::deserialize(&t.serialize()), Some(t));
+    }
+
+    #[test]
+    fn serialize_replaces_separators() {
+        let t = node("id", Some("a\tb\nc"), Kind::Constant);
+        let s = t.serialize();
+        assert_eq!(s, "0\tc\tid\tString\ta b c\n");
+        assert_eq!(
+            IdTree::deserialize(&s).and_then(|t| t.source),
+            Some("a b c".to_owned())
+        );
+    }
+
+    #[test]
+    fn deserialize_invalid() {
+        assert_eq!(IdTree::deserialize(""), None);
+        assert_eq!(IdTree::deserialize("0\tx\tid\t\t\n"), None);
+        assert_eq!(IdTree::deserialize("1\tc\tid\t\t\n"), None);
+        assert_eq!(IdTree::deserialize("0\tc\ta\t\t\n0\tc\tb\t\t\n"), None);
+    }
+
+    #[test]
+    fn render_diff_unchanged() {
+        assert_eq!(
+            tree("x").render_diff(&tree("x")),
+            "identity unchanged: root"
+        );
+    }
+
+    #[test]
+    fn render_diff_changed() {
+        let mut new = tree("y");
+        new.id = "root2".to_owned();
+        if let Kind::Value(children) = &mut new.kind {
+            children[1].id = "leaf2".to_owned();
+            children.push(node("added", None, Kind::Constant));
+        }
+        assert_eq!(
+            new.render_diff(&tree("x")),
+            "identity changed:\n\
+             ~ root -> root2 String at a.ergo:1:1\n\
+             \x20 ~ leaf -> leaf2 String at a.ergo:2:1\n\
+             \x20   ~ x -> y String (constant)\n\
+             \x20 + added String (constant)\n"
+        );
+    }
+
+    #[test]
+    fn render_diff_removed() {
+        let mut new = tree("x");
+        new.id = "root2".to_owned();
+        if let Kind::Value(children) = &mut new.kind {
+            children.remove(2);
+        }
+        assert_eq!(
+            new.render_diff(&tree("x")),
+            "identity changed:\n\
+             ~ root -> root2 String at a.ergo:1:1\n\
+             \x20 - const String (repeated)\n"
+        );
+    }
+}
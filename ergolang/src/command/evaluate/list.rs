@@ -0,0 +1,207 @@
+//! Listing of the indices of a value.
+//!
+//! Each index of the loaded Map (or Type index) is listed with the summary of its documentation,
+//! grouped by doc category in the same way as `std:doc:module`.
+
+use ergo_runtime::{
+    error::{Diagnostic, DiagnosticInfo},
+    metadata, types, Context, Value,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The metadata key of doc categories, as set by the `std:doc` category attributes.
+const CATEGORY_KEY: &str = "std:doc:category";
+
+/// The category of values which should not be listed.
+const IGNORED: &str = "Ignored";
+
+/// Categories which are listed first, in order.
+const ORDERED_CATEGORIES: &[&str] = &["Types", "Modules", "Functions", "Values"];
+
+/// An index to list.
+struct Entry {
+    name: String,
+    summary: String,
+}
+
+/// Get the doc category of a value.
+///
+/// If the value has no explicit category, it is evaluated to determine the category from its type.
+async fn category(value: &mut Value, key: u128) -> ergo_runtime::Result<String> {
+    let key = metadata::Runtime { key };
+    while value.get_metadata(&key).is_none() && value.eval_once().await {}
+    match value.get_metadata(&key) {
+        Some(c) => {
+            let mut c = c.owned();
+            Context::eval(&mut c).await?;
+            Ok(match c.as_ref::<types::String>() {
+                Some(s) => s.to_string(),
+                None => "Values".into(),
+            })
+        }
+        None => Ok(if value.is_type::<types::Unbound>() {
+            "Functions"
+        } else if value.is_type::<types::Type>() {
+            "Types"
+        } else {
+            "Values"
+        }
+        .into()),
+    }
+}
+
+/// List the indices of a Map or Type, without applying any functions.
+pub async fn list(mut value: Value) -> ergo_runtime::Result<String> {
+    Context::eval(&mut value).await?;
+    if let Some(t) = value.as_ref::<types::Type>() {
+        value = t.index.clone();
+        Context::eval(&mut value).await?;
+    }
+    let entries = match value.as_ref::<types::Map>() {
+        Some(types::Map(entries)) => entries
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>(),
+        None => {
+            return Err(Diagnostic::from(format!(
+                "cannot list value with type '{}'",
+                ergo_runtime::traits::type_name(&value)
+            ))
+            .add_note("only Maps and Types may be listed")
+            .into())
+        }
+    };
+
+    let key: Value = types::String::from(CATEGORY_KEY).into();
+    let key = key.id().await;
+    let mut categories: BTreeMap<String, Vec<Entry>> = BTreeMap::new();
+    for (k, v) in entries {
+        let name = match k.as_ref::<types::String>() {
+            Some(s) => s.to_string(),
+            None => continue,
+        };
+        let category = category(&mut v.clone(), key).await?;
+        if category == IGNORED {
+            continue;
+        }
+        let summary = ergo_script::doc_summary(&metadata::Doc::get(&v).await?);
+        categories
+            .entry(category)
+            .or_default()
+            .push(Entry { name, summary });
+    }
+
+    let mut sections: Vec<_> = ORDERED_CATEGORIES
+        .iter()
+        .filter_map(|c| categories.remove_entry(*c))
+        .collect();
+    sections.extend(categories);
+
+    let width = sections
+        .iter()
+        .flat_map(|(_, entries)| entries.iter().map(|e| e.name.len()))
+        .max()
+        .unwrap_or_default();
+
+    let mut s = String::new();
+    for (category, mut entries) in sections {
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        if !s.is_empty() {
+            s.push('\n');
+        }
+        writeln!(s, "{}:", category).unwrap();
+        for e in entries {
+            writeln!(s, "  {:width$}  {}", e.name, e.summary, width = width).unwrap();
+        }
+    }
+    // Remove the trailing newline, as the result is printed with one.
+    s.pop();
+    Ok(s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ergo_runtime::{abi_stable::bst::BstMap, type_system::ErgoType};
+    use ergo_script::Runtime;
+
+    fn string(s: &str) -> Value {
+        types::String::from(s).into()
+    }
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        let mut m = BstMap::default();
+        for (k, v) in entries {
+            m.insert(types::String::from(k).into(), v);
+        }
+        // Entries with keys which are not Strings are not listed.
+        m.insert(types::Unit.into(), string("unit"));
+        types::Map(m).into()
+    }
+
+    #[test]
+    fn grouped() {
+        let runtime = Runtime::new(Context::builder(), vec![]).unwrap();
+        let (listed, type_listed, not_listed) = runtime.block_on(async {
+            let key = metadata::Runtime {
+                key: string(CATEGORY_KEY).id().await,
+            };
+            let entry = |doc: &str, category: Option<&str>, mut value: Value| {
+                metadata::Doc::set_string(&mut value, doc);
+                if let Some(c) = category {
+                    value.set_metadata(&key, string(c));
+                }
+                value
+            };
+            let a_type: Value = types::Type {
+                tp: types::String::ergo_type(),
+                index: map(vec![("new", entry("Create a value.", None, string("")))]),
+            }
+            .into();
+
+            let value = map(vec![
+                ("zeta", entry("A value.\n\nMore docs.", None, string("z"))),
+                (
+                    "extra",
+                    entry("Custom category.", Some("Custom"), string("e")),
+                ),
+                ("hidden", entry("Ignored.", Some(IGNORED), string("h"))),
+                (
+                    "alpha",
+                    entry("A\nfunction.", Some("Functions"), string("a")),
+                ),
+                ("mod", entry("A module.", Some("Modules"), string("m"))),
+                ("Kind", entry("A type.", None, a_type.clone())),
+                ("beta", entry("Another value.", None, string("b"))),
+            ]);
+            (
+                list(value).await,
+                list(a_type).await,
+                list(string("not a map")).await,
+            )
+        });
+
+        assert_eq!(
+            listed.unwrap(),
+            "Types:
+  Kind   A type.
+
+Modules:
+  mod    A module.
+
+Functions:
+  alpha  A function.
+
+Values:
+  beta   Another value.
+  zeta   A value.
+
+Custom:
+  extra  Custom category."
+        );
+        // Types list their index.
+        assert_eq!(type_listed.unwrap(), "Values:\n  new  Create a value.");
+        assert!(not_listed.is_err());
+    }
+}
@@ -1,16 +1,24 @@
 //! The evaluation command.
 
 use crate::AppErr;
-use ergo_runtime::{context::LogLevel, try_value, Error};
-use ergo_script::constants::{PROGRAM_NAME, WORKSPACE_NAME};
+use ergo_runtime::{
+    context::{Capabilities, ErrorScope, JobServerMode, LogLevel},
+    metadata::Source,
+    try_value, types, Error,
+};
+use ergo_script::constants::{LOCK_NAME, PROGRAM_NAME, WORKSPACE_NAME};
 use ergo_script::Runtime;
 
-mod output;
-mod render_markdown;
-mod sync;
+mod explain;
+mod list;
+pub(crate) mod output;
+mod profile;
+pub(crate) mod render_markdown;
+pub(crate) mod sync;
+mod watch;
 
 /// Constant values shared throughout the program.
-mod constants {
+pub(crate) mod constants {
     use directories;
     pub fn app_dirs() -> Option<directories::ProjectDirs> {
         directories::ProjectDirs::from("", "", super::PROGRAM_NAME)
@@ -40,6 +48,7 @@ pub enum OutputFormat {
     Basic,
     Pretty,
     Auto,
+    Json,
 }
 
 #[derive(Debug, clap::Args)]
@@ -56,6 +65,9 @@ pub struct Evaluate {
 
     #[clap(long, default_value = "auto", arg_enum)]
     /// The output format.
+    ///
+    /// The `json` format writes JSON Lines events (log entries, task activity, progress, errors,
+    /// and the final result) to stdout, each with a `type` field.
     pub format: OutputFormat,
 
     #[clap(short, long)]
@@ -71,10 +83,60 @@ pub struct Evaluate {
     /// workspace. If none are found, the current directory is used.
     pub storage: std::path::PathBuf,
 
+    #[clap(long, default_value = "off")]
+    /// Whether to share task slots with child processes through a GNU make jobserver.
+    ///
+    /// May be "off", "on" (create a jobserver), or "join" (join the jobserver from `MAKEFLAGS` if
+    /// present, otherwise create one). A jobserver sets `MAKEFLAGS`, `MFLAGS`, and
+    /// `CARGO_MAKEFLAGS` for child processes, unless they are set with `~env`.
+    pub jobserver: JobServerMode,
+
     #[clap(short, long)]
     /// Clear the storage directory prior to executing.
     pub clean: bool,
 
+    #[clap(long, use_delimiter = true, min_values = 0, require_equals = true)]
+    /// Only allow executing the given (comma-separated) programs.
+    ///
+    /// Programs given by name may only be executed by name (with path lookup); programs given by
+    /// path may only be executed by path.
+    ///
+    /// If any of the `--allow-*` options are specified, capabilities which are not specified are
+    /// denied. Otherwise all capabilities are allowed.
+    pub allow_exec: Option<Vec<String>>,
+
+    #[clap(long, use_delimiter = true, min_values = 0, require_equals = true)]
+    /// Only allow reading within the given (comma-separated) paths.
+    pub allow_read: Option<Vec<std::path::PathBuf>>,
+
+    #[clap(long, use_delimiter = true, min_values = 0, require_equals = true)]
+    /// Only allow writing within the given (comma-separated) paths.
+    pub allow_write: Option<Vec<std::path::PathBuf>>,
+
+    #[clap(long, use_delimiter = true, min_values = 0, require_equals = true)]
+    /// Only allow network access to the given (comma-separated) hosts.
+    ///
+    /// Hosts may include a port (`host:port`), otherwise any port is allowed.
+    pub allow_net: Option<Vec<String>>,
+
+    #[clap(long, use_delimiter = true, min_values = 0, require_equals = true)]
+    /// Only allow loading plugins within the given (comma-separated) paths.
+    ///
+    /// Plugins are native code, so they are not restricted by the other capabilities (though the
+    /// standard library checks them). If unspecified when other capabilities are restricted, only
+    /// the plugins installed with ergo (such as the standard library) may be loaded.
+    pub allow_plugin: Option<Vec<std::path::PathBuf>>,
+
+    #[clap(long)]
+    /// Log side effects rather than performing them.
+    ///
+    /// Functions with side effects (`std:exec`, `std:fs` functions which modify the filesystem,
+    /// and `std:net:http`) log what they would do and return placeholder values. Other evaluation
+    /// (including `Cache:entry` lookups) proceeds normally, though new values are not persisted
+    /// to caches. Messages are logged at the "info" level, so the log level is lowered to at
+    /// least "info".
+    pub dry_run: bool,
+
     #[clap(short, long, require_equals = true)]
     /// Check for common syntax mistakes while executing the script.
     ///
@@ -86,6 +148,12 @@ pub struct Evaluate {
     /// Enable evaluation backtraces on errors.
     pub backtrace: bool,
 
+    #[clap(skip)]
+    /// Lock file entries to update rather than verify.
+    ///
+    /// If empty, all entries are updated.
+    pub lock_update: Option<Vec<String>>,
+
     #[clap(short, long)]
     /// Display documentation for the final value rather than executing it.
     ///
@@ -97,9 +165,26 @@ pub struct Evaluate {
     /// filesystem.
     ///
     /// This option behaves similarly to `--doc`, but rather than displaying the documentation, it
-    /// is written to the given path.
+    /// is written within the given directory.
     pub doc_path: Option<std::path::PathBuf>,
 
+    #[clap(long, default_value = "html")]
+    /// The format of documentation written with `--doc-write`.
+    ///
+    /// May be "html" (a static site with a sidebar, search, syntax highlighting, and cross-links),
+    /// "markdown", or "mdbook" (markdown pages which are added to the summary of the book
+    /// containing the path).
+    pub doc_format: ergo_script::DocFormat,
+
+    #[clap(long)]
+    /// List the indices of the final value (a Map or Type) with the summaries of their
+    /// documentation rather than executing it.
+    ///
+    /// Indices are grouped by their `std:doc` category. Functions are not applied, however values
+    /// which have no explicit category are evaluated to determine their category. If no arguments
+    /// are provided, the value of the workspace is listed.
+    pub list: bool,
+
     #[clap(short, long)]
     /// Page the evaluation output.
     pub page: bool,
@@ -108,6 +193,55 @@ pub struct Evaluate {
     /// Whether to stop immediately when an error occurs.
     pub stop: bool,
 
+    #[clap(short, long)]
+    /// Watch the inputs of the evaluation, and re-evaluate when they change.
+    ///
+    /// All scripts that are loaded and files that are read or tracked (with `std:fs:track`) during
+    /// evaluation are watched. When any of them change, the output is cleared and the evaluation
+    /// is run again. The runtime is reused across evaluations: loaded scripts are only reloaded if
+    /// they (or scripts they load) changed, though any change to other files reloads all scripts.
+    pub watch: bool,
+
+    #[clap(long)]
+    /// Profile the evaluation, writing the results to the given file.
+    ///
+    /// The start and end times, threads, and active and waiting time of tasks and units of work,
+    /// as well as cache hits and misses, are recorded. The profile is written as Chrome
+    /// trace-event JSON (viewable with `chrome://tracing` or Perfetto) to the given file, and as
+    /// folded stacks (for use with flamegraph tools) to the same path with a `.folded` suffix
+    /// appended.
+    pub profile: Option<std::path::PathBuf>,
+
+    #[clap(long)]
+    /// Print a summary of the resource usage of child processes per task after evaluating.
+    ///
+    /// For each `std:task` which spawned child processes, the number of children and their total
+    /// wall time, total CPU time, and peak resident set size are printed to stderr (sorted by peak
+    /// resident set size).
+    pub usage: bool,
+
+    #[clap(long)]
+    /// Display the dependency tree of the identity of the final value rather than executing it.
+    ///
+    /// Each value in the tree is displayed with its identity, type (if it was evaluated), and
+    /// source location. The tree is saved in the storage directory so that `--explain-id-diff`
+    /// may later compare against it.
+    pub explain_id: bool,
+
+    #[clap(long)]
+    /// Display the differences between the dependency tree of the identity of the final value and
+    /// the tree of the prior `--explain-id` or `--explain-id-diff` run of the same command.
+    ///
+    /// This is useful to determine why an identity changed (for instance, to explain why a cache
+    /// entry was missed). The current tree is saved for the next run.
+    pub explain_id_diff: bool,
+
+    #[clap(long, hide = true)]
+    /// Print completion candidates of the given (partial) first argument rather than executing.
+    ///
+    /// This is used by the scripts generated by the `completions` command.
+    pub complete: Option<String>,
+
     #[clap(short, long)]
     /// Evaluate the arguments as an expression.
     ///
@@ -129,6 +263,193 @@ pub struct Evaluate {
     pub args: Vec<String>,
 }
 
+/// Interrupt signal handling, shared by all evaluations of a single command.
+pub(crate) struct Interrupt {
+    task: std::sync::Mutex<Option<sync::ScopedRef<ergo_runtime::context::TaskManager>>>,
+    interrupted: std::sync::atomic::AtomicBool,
+}
+
+impl Interrupt {
+    /// Install the interrupt signal handler.
+    ///
+    /// This may only be called once per process.
+    pub fn install() -> Result<std::sync::Arc<Self>, String> {
+        let ret = std::sync::Arc::new(Interrupt {
+            task: Default::default(),
+            interrupted: Default::default(),
+        });
+        let interrupt = ret.clone();
+        ctrlc::set_handler(move || {
+            interrupt
+                .interrupted
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+            if let Some(task) = &*interrupt.task.lock().unwrap() {
+                task.with(|t| t.abort());
+            }
+        })
+        .app_err("failed to set signal handler")?;
+        Ok(ret)
+    }
+
+    /// Set the task manager to abort when an interrupt occurs.
+    pub fn set_task(&self, task: sync::ScopedRef<ergo_runtime::context::TaskManager>) {
+        *self.task.lock().unwrap() = Some(task);
+    }
+
+    /// Return whether an interrupt has occurred.
+    pub fn interrupted(&self) -> bool {
+        self.interrupted.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// Build the script to evaluate for the given command arguments.
+///
+/// If `expression` is set, the arguments are joined as a script expression. Otherwise the first
+/// argument is the script to load (or workspace command to run), and remaining arguments are
+/// passed to it as strings.
+pub(crate) fn command_script(runtime: &Runtime, expression: bool, args: &[String]) -> String {
+    if expression {
+        if args.is_empty() {
+            "()".into()
+        } else {
+            let mut eval_args = String::new();
+            for arg in args {
+                if !eval_args.is_empty() {
+                    eval_args.push(' ');
+                }
+                eval_args.push_str(&arg);
+            }
+            eval_args
+        }
+    } else {
+        let mut args = args.iter();
+        match args.next() {
+            None => {
+                // When there are no arguments, always call workspace:command function.
+                // This returns _just_ the function, so that documentation will work as expected, but it
+                // will then be called (with Runtime::apply_unbound).
+                "workspace:command".into()
+            }
+            Some(a) => {
+                let mut s = String::new();
+                // Split the first argument on `:` to support indexing.
+                let mut indices = a.split(":");
+                let to_load = indices.next().unwrap();
+                let use_workspace_command = runtime
+                    .resolve_script_path(None, to_load.as_ref())
+                    .is_none();
+                // Inspect the first effective argument to determine whether to invoke a normal load
+                // or the workspace:command function.
+                s += if use_workspace_command {
+                    "workspace:command"
+                } else {
+                    "load"
+                };
+                s.push(' ');
+                s += &string_quote(to_load);
+
+                let mut needs_call = !use_workspace_command;
+
+                // Apply indices
+                let mut first = true;
+                for index in indices {
+                    if first {
+                        s += " |>";
+                        needs_call = false;
+                        first = false;
+                    }
+                    s += ":";
+                    s += &string_quote(index);
+                }
+
+                // Pass all remaining arguments as strings.
+                for a in args {
+                    if needs_call {
+                        s += " |>";
+                        needs_call = false;
+                    }
+                    s.push(' ');
+                    s += &string_quote(a);
+                }
+                s
+            }
+        }
+    }
+}
+
+/// Get the project root directory.
+///
+/// This is the furthest ancestor directory that is a workspace, or the current directory if there
+/// are none.
+pub(crate) fn project_root() -> std::path::PathBuf {
+    let working_dir = std::env::current_dir().expect("could not get current directory");
+
+    // Search for furthest workspace ancestor
+    if let Some(p) = working_dir
+        .ancestors()
+        .filter(|p| p.join(WORKSPACE_NAME).exists())
+        .last()
+    {
+        p.to_owned()
+    } else {
+        working_dir
+    }
+}
+
+/// Get the storage directory for the given (possibly relative) path.
+///
+/// Relative paths are made relative to the project root.
+pub(crate) fn storage_directory(storage: &std::path::Path) -> std::path::PathBuf {
+    project_root().join(storage)
+}
+
+/// Get the lock file path, in the project root.
+pub(crate) fn lock_file() -> std::path::PathBuf {
+    project_root().join(LOCK_NAME)
+}
+
+/// Get the neighboring lib directory when running in a [prefix]/bin directory.
+fn installed_lib_directory() -> Option<std::path::PathBuf> {
+    std::env::current_exe().ok().and_then(|path| {
+        path.parent().and_then(|parent| {
+            if parent.file_name() == Some("bin".as_ref()) {
+                let path = parent
+                    .parent()
+                    .expect("must have parent directory")
+                    .join("lib")
+                    .join(PROGRAM_NAME);
+                if path.exists() {
+                    Some(path)
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        })
+    })
+}
+
+/// Get the load path from exe location and user directories.
+pub(crate) fn load_path() -> Vec<std::path::PathBuf> {
+    let mut load_paths = Vec::new();
+
+    // Add the data directory prior to any user lib dir.
+    if let Some(dir) = installed_lib_directory() {
+        load_paths.push(dir);
+    }
+
+    // Add user lib dir.
+    if let Some(proj_dirs) = constants::app_dirs() {
+        let path = proj_dirs.preference_dir().join("lib");
+        if path.exists() {
+            load_paths.push(path);
+        }
+    }
+
+    load_paths
+}
+
 #[cfg(unix)]
 fn setup_pager() {
     pager::Pager::with_default_pager(if cfg!(target_os = "macos") {
@@ -146,6 +467,10 @@ fn setup_pager() {
 
 impl super::Command for Evaluate {
     fn run(mut self) -> Result<(), String> {
+        if let Some(word) = &self.complete {
+            return super::completions::complete(word);
+        }
+
         // Additional options logic.
         if self.doc {
             self.page ^= true;
@@ -153,10 +478,16 @@ impl super::Command for Evaluate {
         let doc = self.doc;
         let paging_enabled = self.page;
 
+        let interrupt = Interrupt::install()?;
+
+        if self.watch {
+            return self.eval_watching(&interrupt);
+        }
+
         let (output, is_terminal) = output(self.format, !self.stop)
             .app_err("could not create output with requested format")?;
 
-        let result = self.eval(output);
+        let result = self.eval(output, &interrupt, &mut None, &mut Vec::new());
 
         // Restore terminal state prior to the pager being used.
         unsafe { crate::terminal_state::restore() };
@@ -172,30 +503,141 @@ impl super::Command for Evaluate {
         if doc && is_terminal {
             render_markdown::render_markdown(color_support, &s);
         } else {
-            println!("{}", s);
+            use std::io::Write;
+            match writeln!(std::io::stdout(), "{}", s) {
+                // The reader of the output going away (e.g. a closed pipe) is not an error.
+                Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                    return Err(format!("failed to write to output:\n{}", e))
+                }
+                _ => (),
+            }
         }
         Ok(())
     }
 }
 
+/// The runtime and associated state used for evaluation.
+struct Session {
+    runtime: Runtime,
+    log: output::ForwardLog,
+    profile: Option<profile::Profile>,
+    task_log_directory: Option<std::path::PathBuf>,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Before the context is destroyed (unloading plugins), clear the thread-local storage in case
+        // there are values which were allocated in the plugins.
+        ergo_runtime::plugin::Context::reset();
+
+        self.runtime.ctx.global.hooks().shutdown();
+    }
+}
+
 impl Evaluate {
-    fn eval(self, mut output: OutputInstance) -> Result<String, String> {
-        output.set_log_level(self.log_level);
+    /// Repeatedly evaluate, waiting for changes to the evaluation inputs between each evaluation.
+    ///
+    /// The runtime is retained between evaluations, so that loaded scripts which are unaffected by
+    /// the changes are not loaded again.
+    fn eval_watching(&mut self, interrupt: &Interrupt) -> Result<(), String> {
+        let mut session = None;
+        loop {
+            let (output, is_terminal) = output(self.format, !self.stop)
+                .app_err("could not create output with requested format")?;
+
+            let mut input_paths = Vec::new();
+            let result = self.eval(output, interrupt, &mut session, &mut input_paths);
 
-        let working_dir = std::env::current_dir().expect("could not get current directory");
+            // Only clean the storage directory prior to the first evaluation.
+            self.clean = false;
+
+            unsafe { crate::terminal_state::restore() };
+
+            match result {
+                Ok(s) => println!("{}", s),
+                Err(e) => eprintln!("{}", e),
+            }
+
+            if interrupt.interrupted() {
+                return Ok(());
+            }
+
+            if input_paths.is_empty() {
+                return Err("no inputs were used in the evaluation, nothing to watch".into());
+            }
+
+            eprintln!(
+                "watching {} input path{} for changes...",
+                input_paths.len(),
+                if input_paths.len() == 1 { "" } else { "s" }
+            );
 
-        // Search for furthest workspace ancestor, and set as storage directory root
-        let storage_dir_root = if let Some(p) = working_dir
-            .ancestors()
-            .filter(|p| p.join(WORKSPACE_NAME).exists())
-            .last()
+            let changed = match watch::wait_for_change(&input_paths, interrupt)? {
+                Some(changed) => changed,
+                None => return Ok(()),
+            };
+
+            if let Some(s) = &session {
+                if !s.runtime.invalidate(&changed) {
+                    // A new runtime is necessary.
+                    session = None;
+                }
+            }
+
+            if is_terminal {
+                // Clear the screen and move the cursor to the top left.
+                print!("\x1b[2J\x1b[H");
+                drop(std::io::Write::flush(&mut std::io::stdout()));
+            }
+        }
+    }
+
+    /// Get the capabilities granted by the `--allow-*` options.
+    fn capabilities(&self) -> Capabilities {
+        if self.allow_exec.is_none()
+            && self.allow_read.is_none()
+            && self.allow_write.is_none()
+            && self.allow_net.is_none()
+            && self.allow_plugin.is_none()
         {
-            p.to_owned()
-        } else {
-            working_dir.clone()
-        };
+            return Capabilities::default();
+        }
+        Capabilities::none()
+            .with_exec(self.allow_exec.clone().unwrap_or_default())
+            .with_read(self.allow_read.clone().unwrap_or_default())
+            .with_write(self.allow_write.clone().unwrap_or_default())
+            .with_net(self.allow_net.clone().unwrap_or_default())
+            .with_plugin(
+                self.allow_plugin
+                    .clone()
+                    .unwrap_or_else(|| installed_lib_directory().into_iter().collect()),
+            )
+    }
 
-        let storage_directory = storage_dir_root.join(self.storage);
+    /// Create the task log directory for a new run.
+    ///
+    /// Task logs are only recorded for runs which may execute tasks.
+    fn new_run(
+        &self,
+        storage_directory: &std::path::Path,
+    ) -> Result<Option<std::path::PathBuf>, String> {
+        if self.dry_run
+            || self.doc
+            || self.doc_path.is_some()
+            || self.list
+            || self.explain_id
+            || self.explain_id_diff
+        {
+            return Ok(None);
+        }
+        super::logs::new_run(storage_directory, &self.args.join(" "))
+            .map(Some)
+            .app_err("failed to create task log directory")
+    }
+
+    /// Create a new session, cleaning the storage directory if requested.
+    fn session(&self) -> Result<Session, String> {
+        let storage_directory = storage_directory(&self.storage);
 
         // Clean storage directory if requested.
         if self.clean && storage_directory.exists() {
@@ -203,57 +645,36 @@ impl Evaluate {
                 .app_err("failed to clean storage directory")?;
         }
 
-        // Get the load path from exe location and user directories.
-        let load_path = {
-            let mut load_paths = Vec::new();
-
-            // Add neighboring lib directories when running in a [prefix]/bin directory.
-            let neighbor_data_dir = std::env::current_exe().ok().and_then(|path| {
-                path.parent().and_then(|parent| {
-                    if parent.file_name() == Some("bin".as_ref()) {
-                        let path = parent
-                            .parent()
-                            .expect("must have parent directory")
-                            .join("lib")
-                            .join(PROGRAM_NAME);
-                        if path.exists() {
-                            Some(path)
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                })
-            });
-
-            // Add the data directory prior to any user lib dir.
-            if let Some(dir) = neighbor_data_dir {
-                load_paths.push(dir);
-            }
+        let task_log_directory = self.new_run(&storage_directory)?;
 
-            // Add user lib dir.
-            if let Some(proj_dirs) = constants::app_dirs() {
-                let path = proj_dirs.preference_dir().join("lib");
-                if path.exists() {
-                    load_paths.push(path);
-                }
-            }
+        let log = output::ForwardLog::default();
 
-            load_paths
+        let (context_builder, profile) = if self.profile.is_some() {
+            let (profiler, profile) = profile::Profiler::new(log.clone());
+            (
+                ergo_runtime::Context::builder().logger(profiler),
+                Some(profile),
+            )
+        } else {
+            (ergo_runtime::Context::builder().logger(log.clone()), None)
         };
 
-        let error_logger = output.error_log();
+        let context_builder = match &task_log_directory {
+            Some(dir) => context_builder.task_log_directory(dir.clone()),
+            None => context_builder,
+        };
 
         // Create script runtime.
         let runtime = Runtime::new(
-            ergo_runtime::Context::builder()
-                .logger(output.log())
+            context_builder
                 .storage_directory(storage_directory)
                 .threads(self.jobs)
-                .keep_going(!self.stop)
-                .error_handler(move |e: Error| error_logger.new_error(e)),
-            load_path,
+                .jobserver(self.jobserver)
+                .dry_run(self.dry_run)
+                .lock_file(lock_file())
+                .capabilities(self.capabilities())
+                .keep_going(!self.stop),
+            load_path(),
         )
         .expect("failed to create script context");
 
@@ -263,114 +684,158 @@ impl Evaluate {
 
         runtime.backtrace(self.backtrace);
 
+        if let Some(keys) = &self.lock_update {
+            runtime
+                .ctx
+                .global
+                .lock_file()
+                .app_err("failed to load lock file")?
+                .set_update((!keys.is_empty()).then(|| keys.clone()));
+        }
+
+        Ok(Session {
+            runtime,
+            log,
+            profile,
+            task_log_directory,
+        })
+    }
+
+    /// Evaluate the command.
+    ///
+    /// If `retained` is set, its session is used for the evaluation. Otherwise a new session is
+    /// created. When watching, the session is retained in `retained` after evaluation.
+    fn eval(
+        &self,
+        mut output: OutputInstance,
+        interrupt: &Interrupt,
+        retained: &mut Option<Session>,
+        input_paths: &mut Vec<std::path::PathBuf>,
+    ) -> Result<String, String> {
+        output.set_log_level(if self.dry_run && self.log_level > LogLevel::Info {
+            LogLevel::Info
+        } else {
+            self.log_level
+        });
+
+        let storage_directory = storage_directory(&self.storage);
+        let explain = self.explain_id || self.explain_id_diff;
+
+        let mut session = match retained.take() {
+            Some(mut session) => {
+                // Each evaluation is a separate run with its own task logs.
+                session.task_log_directory = self.new_run(&storage_directory)?;
+                session
+                    .runtime
+                    .ctx
+                    .global
+                    .env
+                    .set_task_log_directory(session.task_log_directory.clone());
+                session
+            }
+            None => self.session()?,
+        };
+        let keep_session = self.watch;
+
+        session.log.set(Some(output.log()));
+        if let Some(profile) = &session.profile {
+            profile.clear();
+        }
+        let error_logger = output.error_log();
+        session.runtime.ctx.error_scope =
+            ErrorScope::new(move |e: Error| error_logger.new_error(e));
+        let profile = session.profile.clone();
+        let task_log_directory = session.task_log_directory.clone();
+        let runtime = &session.runtime;
+
         // Set interrupt signal handler to abort tasks.
         //
         // Keep signal_handler_task in scope until the handler is no longer needed.
         let (signal_handler_task, task_ref) = sync::Scoped::new_pair(runtime.ctx.task.clone());
-        {
-            ctrlc::set_handler(move || task_ref.with(|t| t.abort()))
-                .app_err("failed to set signal handler")?;
-        }
+        interrupt.set_task(task_ref);
 
         // Build script string to evaluate
-        let mut to_eval = if self.expression {
-            if self.args.is_empty() {
-                "()".into()
-            } else {
-                let mut eval_args = String::new();
-                for arg in self.args {
-                    if !eval_args.is_empty() {
-                        eval_args.push(' ');
-                    }
-                    eval_args.push_str(&arg);
-                }
-                eval_args
-            }
+        let mut to_eval = if self.list && !self.expression && self.args.is_empty() {
+            "workspace".into()
         } else {
-            let mut args = self.args.into_iter();
-            match args.next() {
-                None => {
-                    // When there are no arguments, always call workspace:command function.
-                    // This returns _just_ the function, so that documentation will work as expected, but it
-                    // will then be called (with Runtime::apply_unbound).
-                    "workspace:command".into()
-                }
-                Some(a) => {
-                    let mut s = String::new();
-                    // Split the first argument on `:` to support indexing.
-                    let mut indices = a.split(":");
-                    let to_load = indices.next().unwrap();
-                    let use_workspace_command = runtime
-                        .resolve_script_path(None, to_load.as_ref())
-                        .is_none();
-                    // Inspect the first effective argument to determine whether to invoke a normal load
-                    // or the workspace:command function.
-                    s += if use_workspace_command {
-                        "workspace:command"
-                    } else {
-                        "load"
-                    };
-                    s.push(' ');
-                    s += &string_quote(to_load);
-
-                    let mut needs_call = !use_workspace_command;
-
-                    // Apply indices
-                    let mut first = true;
-                    for index in indices {
-                        if first {
-                            s += " |>";
-                            needs_call = false;
-                            first = false;
-                        }
-                        s += ":";
-                        s += &string_quote(index);
-                    }
-
-                    // Pass all remaining arguments as strings.
-                    for a in args {
-                        if needs_call {
-                            s += " |>";
-                            needs_call = false;
-                        }
-                        s.push(' ');
-                        s += &string_quote(a);
-                    }
-                    s
-                }
-            }
+            command_script(runtime, self.expression, &self.args)
         };
 
         if self.doc {
             to_eval = format!("doc ({})", to_eval);
-        } else if let Some(path) = self.doc_path {
+        } else if let Some(path) = &self.doc_path {
             to_eval = format!(
-                "doc:write {} ({})",
+                "doc:write ~format={} {} ({})",
+                self.doc_format,
                 string_quote(path.display().to_string()),
                 to_eval
             );
         }
 
+        let explain_path = explain.then(|| explain::save_path(&storage_directory, &to_eval));
+
         let loaded = runtime.evaluate_string("<command line>", &to_eval);
         let progress = runtime.ctx.global.progress.clone();
 
         let (complete_send, complete) = std::sync::mpsc::channel();
 
+        let list = self.list;
         let exec_thread = std::thread::spawn(move || {
+            let runtime = &session.runtime;
             let value_to_execute = loaded.and_then(|script_output| {
+                // The listed value is not applied.
+                if list {
+                    return Ok(script_output);
+                }
                 let v = runtime.block_on(Runtime::apply_unbound(script_output));
                 Ok(try_value!(v))
             });
 
+            // Replace the value with its identity dependency tree if explaining.
+            let value_to_execute = value_to_execute.and_then(|value| {
+                if !explain {
+                    return Ok(value);
+                }
+                let src = Source::get(&value);
+                let f = runtime.evaluate_string("<command line>", "std:value:dependencies")?;
+                let v = runtime.block_on(ergo_runtime::traits::bind(
+                    f,
+                    Source::imbue(
+                        src.with(
+                            types::Args {
+                                args: types::args::Arguments::positional(vec![value]).unchecked(),
+                            }
+                            .into(),
+                        ),
+                    ),
+                ));
+                Ok(try_value!(v))
+            });
+
             // Clear load cache, so that lifetimes are optimistically dropped. It's not very likely that
             // stuff will be loaded while executing the final value, but if so it'll just take the hit of
-            // reloading the scripts.
-            runtime.clear_load_cache();
+            // reloading the scripts. When watching, the loaded scripts are retained for the next
+            // evaluation.
+            if !keep_session {
+                runtime.clear_load_cache();
+            }
 
             let result = value_to_execute.and_then(|value| {
                 runtime.block_on(async {
                     use ergo_runtime::traits::{display, eval_nested, Formatter};
+                    if list {
+                        return list::list(value).await;
+                    }
                     eval_nested(value.clone()).await?;
+                    if explain {
+                        return Ok(explain::IdTree::from_value(&value)
+                            .ok_or_else(|| {
+                                ergo_runtime::error! {
+                                    error: "unexpected std:value:dependencies result"
+                                }
+                            })?
+                            .serialize());
+                    }
                     let mut s = String::new();
                     {
                         let mut formatter = Formatter::new(&mut s);
@@ -380,19 +845,21 @@ impl Evaluate {
                 })
             });
 
-            // Before the context is destroyed (unloading plugins), clear the thread-local storage in case
-            // there are values which were allocated in the plugins.
-            ergo_runtime::plugin::Context::reset();
-
             drop(signal_handler_task);
 
             let sources = runtime.ctx.global.diagnostic_sources();
             let progress = runtime.ctx.global.progress.clone();
-            runtime.ctx.global.hooks().shutdown();
-            drop(runtime);
+            let input_paths = runtime.ctx.global.input_paths().paths();
+            // A deadlocked runtime cannot be reused.
+            let session = if keep_session && !progress.is_deadlocked() {
+                Some(session)
+            } else {
+                drop(session);
+                None
+            };
 
             drop(complete_send.send(()));
-            (result, sources, progress)
+            (result, sources, progress, input_paths, session)
         });
 
         // Use this thread for UI updates.
@@ -407,7 +874,14 @@ impl Evaluate {
             }
         }
 
-        let (result, sources, progress) = exec_thread.join().unwrap();
+        let (result, sources, progress, paths, kept_session) = exec_thread.join().unwrap();
+        *input_paths = paths;
+
+        if let (Some(profile), Some(path)) = (&profile, &self.profile) {
+            if let Err(e) = profile.write(path) {
+                eprintln!("failed to write profile to {}: {}", path.display(), e);
+            }
+        }
 
         let errors = {
             let deadlock_errors = progress.deadlock_errors();
@@ -420,10 +894,54 @@ impl Evaluate {
 
         // Drop the output prior to the context dropping, so that any stored state (like errors) can
         // drop with the plugins still loaded.
+        if let Some(s) = &kept_session {
+            s.log.set(None);
+        }
         drop(output);
+        *retained = kept_session;
+
+        if let (true, Some(dir)) = (self.usage, &task_log_directory) {
+            match super::logs::usage_summary(dir) {
+                Ok(s) => eprint!("{}", s),
+                Err(e) => eprintln!("failed to read child process usage: {}", e),
+            }
+        }
 
         match (result, errors.len()) {
-            (Ok(v), 0) => Ok(v),
+            (Ok(v), 0) => {
+                let v = match explain_path {
+                    Some(path) => explain::output(&v, &path, self.explain_id_diff)?,
+                    None => v,
+                };
+                if self.format == OutputFormat::Json {
+                    Ok(serde_json::json!({ "type": "result", "value": v }).to_string())
+                } else {
+                    Ok(v)
+                }
+            }
+            (o, _) if self.format == OutputFormat::Json => {
+                let mut errors = errors;
+                if let Err(e) = o {
+                    errors.insert(&e);
+                }
+                let stdout = std::io::stdout();
+                let mut stdout = stdout.lock();
+                for d in &errors {
+                    output::json::write_event(
+                        &mut stdout,
+                        serde_json::json!({
+                            "type": "error",
+                            "diagnostic": output::json::diagnostic(d, sources.as_ref()),
+                        }),
+                    )
+                    .app_err("failed to write to output")?;
+                }
+                Err(if errors.len() == 0 {
+                    "interrupted".into()
+                } else {
+                    "one or more errors occurred".into()
+                })
+            }
             (o, _) => {
                 // Write error output to stderr.
                 use ergo_runtime::error::emit_diagnostics;
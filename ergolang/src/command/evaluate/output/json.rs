@@ -0,0 +1,256 @@
+//! JSON Lines output.
+//!
+//! Each event is written to the output as a single line containing a JSON object with a `type`
+//! field.
+
+use ergo_runtime::abi_stable::std_types::{RDuration, ROption, RSlice, RString};
+use ergo_runtime::context::{LogEntry, LogLevel, LogTaskKey, Sources};
+use ergo_runtime::error::{Diagnostic, FieldValue, Severity};
+use ergo_runtime::source::{Location, Source};
+use ergo_runtime::Error;
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashSet;
+use std::io::Write;
+
+pub struct Output {
+    log_level: LogLevel,
+    out: Box<dyn Write + Send>,
+    errors: ergo_runtime::error::Diagnostics,
+    running_tasks: HashSet<LogTaskKey>,
+    /// Whether writing to the output failed, in which case no more events are written.
+    closed: bool,
+}
+
+/// Write a single event.
+pub fn write_event<W: Write + ?Sized>(out: &mut W, event: JsonValue) -> std::io::Result<()> {
+    writeln!(out, "{}", event)
+}
+
+/// Get the JSON representation of a diagnostic.
+///
+/// Label locations include the source name (a path for file sources) and the (1-based) line and
+/// column of the start and end of the label.
+pub fn diagnostic(d: &Diagnostic, sources: &Sources) -> JsonValue {
+    let labels: Vec<_> = d
+        .labels
+        .iter()
+        .map(|l| {
+            let src = &l.label;
+            let start = src.clone().with(());
+            let mut end = start.clone();
+            end.location = Location::new(src.location.start + src.location.length, 0);
+            let position = |s: &Source<()>| match sources.line_column(s) {
+                Some((line, column)) => json!({ "line": line, "column": column }),
+                None => JsonValue::Null,
+            };
+            json!({
+                "file": sources.name(src.source_id).map(|n| n.to_string()),
+                "start": position(&start),
+                "end": position(&end),
+                "message": src.value().as_str(),
+                "primary": !l.secondary,
+            })
+        })
+        .collect();
+    json!({
+        "severity": match d.severity {
+            Severity::Bug => "bug",
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+            Severity::Help => "help",
+        },
+        "message": d.message.as_str(),
+        "labels": labels,
+        "notes": d.notes.iter().map(|n| n.as_str()).collect::<Vec<_>>(),
+        "kind": d.kind.as_ref().map(|k| k.as_str()).into_option(),
+        "fields": d
+            .fields
+            .iter()
+            .map(|f| {
+                let value = match &f.value {
+                    FieldValue::String(s) => json!(s.as_str()),
+                    FieldValue::Integer(i) => json!(i),
+                    FieldValue::Bool(b) => json!(b),
+                };
+                (f.name.to_string(), value)
+            })
+            .collect::<serde_json::Map<_, _>>(),
+    })
+}
+
+impl Output {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Output {
+            log_level: LogLevel::Info,
+            out,
+            errors: Default::default(),
+            running_tasks: Default::default(),
+            closed: false,
+        }
+    }
+
+    fn event(&mut self, event: JsonValue) {
+        if self.closed {
+            return;
+        }
+        if let Err(e) = write_event(&mut self.out, event) {
+            // The reader of the output going away (e.g. a closed pipe) is not an error.
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                log::warn!("failed to write to output: {}", e);
+            }
+            self.closed = true;
+        }
+    }
+}
+
+fn ids(id: RSlice<RString>) -> Vec<&str> {
+    id.iter().map(|s| s.as_str()).collect()
+}
+
+impl super::Output for Output {
+    fn set_log_level(&mut self, log_level: LogLevel) {
+        self.log_level = log_level;
+    }
+
+    fn new_error(&mut self, err: Error) {
+        self.errors.insert(&err);
+    }
+
+    fn interrupt(&mut self) {
+        self.event(json!({ "type": "interrupt" }));
+    }
+
+    fn indicate_progress(&mut self) {
+        let running = self.running_tasks.len();
+        self.event(json!({ "type": "progress", "running_tasks": running }));
+    }
+
+    fn update(&mut self) {
+        drop(self.out.flush());
+    }
+
+    fn take_errors(&mut self) -> ergo_runtime::error::Diagnostics {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn log(&mut self, entry: LogEntry) {
+        if entry.level >= self.log_level {
+            self.event(json!({
+                "type": "log",
+                "level": entry.level.to_string(),
+                "context": entry.context.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+                "message": entry.args.as_str(),
+            }));
+        }
+    }
+
+    fn task_running(&mut self, key: LogTaskKey, description: RString) {
+        self.running_tasks.insert(key);
+        self.event(json!({
+            "type": "task-start",
+            "task": key,
+            "description": description.as_str(),
+        }));
+    }
+
+    fn task_suspend(&mut self, key: LogTaskKey) {
+        self.running_tasks.remove(&key);
+        self.event(json!({ "type": "task-stop", "task": key }));
+    }
+
+    fn timer_pending(&mut self, id: RSlice<RString>) {
+        self.event(json!({ "type": "work-start", "id": ids(id) }));
+    }
+
+    fn timer_complete(&mut self, id: RSlice<RString>, duration: ROption<RDuration>) {
+        let duration: Option<std::time::Duration> = duration.into_option().map(|d| d.into());
+        self.event(json!({
+            "type": "work-complete",
+            "id": ids(id),
+            "duration_ms": duration.map(|d| d.as_secs_f64() * 1000.0),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ergo_runtime::error::{DiagnosticInfo, Label};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn diagnostic_shape() {
+        let sources = Sources::new();
+        let id = sources.add_string("test.ergo".into(), "let x =\n  bad value".into());
+        let mut src = Source::new(id);
+        src.location = Location::new(10, 3);
+
+        let d = Diagnostic::from("invalid value")
+            .add_label(Label::primary(src.with("here")))
+            .add_label(Label::secondary(Source::missing("elsewhere")))
+            .add_note("a note")
+            .set_kind("invalid")
+            .add_field("count", 2)
+            .add_field("name", "x")
+            .add_field("fatal", true);
+
+        assert_eq!(
+            diagnostic(&d, &sources),
+            json!({
+                "severity": "error",
+                "message": "invalid value",
+                "labels": [
+                    {
+                        "file": "test.ergo",
+                        "start": { "line": 2, "column": 3 },
+                        "end": { "line": 2, "column": 6 },
+                        "message": "here",
+                        "primary": true,
+                    },
+                    {
+                        "file": "<missing>",
+                        "start": null,
+                        "end": null,
+                        "message": "elsewhere",
+                        "primary": false,
+                    },
+                ],
+                "notes": ["a note"],
+                "kind": "invalid",
+                "fields": { "count": 2, "name": "x", "fatal": true },
+            })
+        );
+    }
+
+    /// A writer which counts write attempts, always failing with a closed pipe.
+    struct ClosedPipe(Arc<AtomicUsize>);
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn closed_output() {
+        use super::super::Output as _;
+
+        let writes = Arc::new(AtomicUsize::new(0));
+        let mut out = Output::new(Box::new(ClosedPipe(writes.clone())));
+        out.interrupt();
+        assert_eq!(writes.load(Ordering::Relaxed), 1);
+
+        // No more events are written once the output is closed.
+        out.interrupt();
+        out.indicate_progress();
+        out.update();
+        assert_eq!(writes.load(Ordering::Relaxed), 1);
+    }
+}
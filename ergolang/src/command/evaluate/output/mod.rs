@@ -4,6 +4,7 @@ use ergo_runtime::context::{LogEntry, LogLevel, LogTarget, LogTaskKey};
 use ergo_runtime::Error;
 
 mod interface;
+pub mod json;
 mod plain;
 mod terminal;
 
@@ -29,6 +30,9 @@ pub trait Output {
 /// Returns the output instance, the associated log instance, and whether a terminal was detected.
 pub fn output(format: OutputFormat, keep_going: bool) -> Option<(OutputInstance, bool)> {
     use interface::OutputType::*;
+    if format == OutputFormat::Json {
+        return Some((json::Output::new(Box::new(std::io::stdout())).into(), false));
+    }
     interface::stdout(format).map(|v| match v {
         Term(term_output) => (terminal::Output::new(term_output, keep_going).into(), true),
         Dumb(w) => (plain::Output::new(w).into(), false),
@@ -57,6 +61,7 @@ macro_rules! impl_from {
 
 impl_from!(terminal::Output);
 impl_from!(plain::Output);
+impl_from!(json::Output);
 
 impl OutputInstance {
     pub fn log(&self) -> OutputLog {
@@ -182,6 +187,61 @@ impl LogTarget for OutputLog {
     }
 }
 
+/// A LogTarget which forwards to a replaceable OutputLog.
+///
+/// This allows a runtime (which has a fixed logger) to be used with a new Output for each
+/// evaluation. Nothing is logged while no OutputLog is set.
+#[derive(Clone, Default)]
+pub struct ForwardLog {
+    inner: std::sync::Arc<std::sync::RwLock<Option<OutputLog>>>,
+}
+
+impl ForwardLog {
+    /// Set the OutputLog to forward to.
+    pub fn set(&self, log: Option<OutputLog>) {
+        *self.inner.write().unwrap() = log;
+    }
+
+    fn with<R: Default, F: FnOnce(&OutputLog) -> R>(&self, f: F) -> R {
+        self.inner
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(f)
+            .unwrap_or_default()
+    }
+}
+
+impl LogTarget for ForwardLog {
+    fn log(&self, entry: LogEntry) {
+        self.with(move |l| l.log(entry))
+    }
+
+    fn task_running(&self, description: RString) -> LogTaskKey {
+        self.with(move |l| l.task_running(description))
+    }
+
+    fn task_suspend(&self, key: LogTaskKey) {
+        self.with(move |l| l.task_suspend(key))
+    }
+
+    fn timer_pending(&self, id: RSlice<RString>) {
+        self.with(move |l| l.timer_pending(id))
+    }
+
+    fn timer_complete(&self, id: RSlice<RString>, duration: ROption<RDuration>) {
+        self.with(move |l| l.timer_complete(id, duration))
+    }
+
+    fn pause_logging(&self) {
+        self.with(|l| l.pause_logging())
+    }
+
+    fn resume_logging(&self) {
+        self.with(|l| l.resume_logging())
+    }
+}
+
 pub struct OutputErrorLog {
     inner: super::sync::Mutator<Box<dyn Output>>,
 }
@@ -0,0 +1,514 @@
+//! Evaluation profiling.
+//!
+//! A `Profiler` wraps a `LogTarget`, recording task activity, units of work, and cache accesses
+//! (as reported through the runtime log) before forwarding them to the wrapped target.
+
+use ergo_runtime::abi_stable::std_types::{RDuration, ROption, RSlice, RString};
+use ergo_runtime::context::{LogEntry, LogTarget, LogTaskKey};
+use serde_json::{json, Map as JsonMap, Value as JsonValue};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A LogTarget which records profiling data.
+pub struct Profiler<T> {
+    inner: T,
+    data: Arc<Mutex<ProfileData>>,
+}
+
+/// A handle to the profiling data of a Profiler.
+#[derive(Clone)]
+pub struct Profile(Arc<Mutex<ProfileData>>);
+
+struct ActiveTask {
+    inner_key: LogTaskKey,
+    description: String,
+    thread: usize,
+    start: Duration,
+}
+
+struct PendingWork {
+    thread: usize,
+    start: Duration,
+}
+
+enum Event {
+    /// A task was active for the given span.
+    Task {
+        description: String,
+        thread: usize,
+        start: Duration,
+        end: Duration,
+    },
+    /// A unit of work was pending for the given span, and active for `active`.
+    Work {
+        id: Vec<String>,
+        thread: usize,
+        start: Duration,
+        end: Duration,
+        active: Option<Duration>,
+    },
+    /// A cache was accessed.
+    Cache {
+        id: Vec<String>,
+        thread: usize,
+        at: Duration,
+        hit: bool,
+    },
+}
+
+#[derive(Default)]
+struct CacheCounts {
+    hits: usize,
+    misses: usize,
+}
+
+struct ProfileData {
+    start: Instant,
+    threads: HashMap<std::thread::ThreadId, usize>,
+    next_task_key: LogTaskKey,
+    active_tasks: HashMap<LogTaskKey, ActiveTask>,
+    pending_work: HashMap<Vec<String>, Vec<PendingWork>>,
+    events: Vec<Event>,
+    caches: BTreeMap<String, CacheCounts>,
+}
+
+impl ProfileData {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Get a small integer identifying the current thread.
+    fn thread(&mut self) -> usize {
+        let next = self.threads.len() + 1;
+        *self
+            .threads
+            .entry(std::thread::current().id())
+            .or_insert(next)
+    }
+}
+
+fn to_ids(id: RSlice<RString>) -> Vec<String> {
+    id.iter().map(|s| s.to_string()).collect()
+}
+
+impl<T: LogTarget> Profiler<T> {
+    /// Create a new profiler wrapping the given target.
+    pub fn new(inner: T) -> (Self, Profile) {
+        let data = Arc::new(Mutex::new(ProfileData {
+            start: Instant::now(),
+            threads: Default::default(),
+            next_task_key: Default::default(),
+            active_tasks: Default::default(),
+            pending_work: Default::default(),
+            events: Default::default(),
+            caches: Default::default(),
+        }));
+        (
+            Profiler {
+                inner,
+                data: data.clone(),
+            },
+            Profile(data),
+        )
+    }
+}
+
+impl<T: LogTarget> LogTarget for Profiler<T> {
+    fn log(&self, entry: LogEntry) {
+        self.inner.log(entry)
+    }
+
+    fn task_running(&self, description: RString) -> LogTaskKey {
+        let inner_key = self.inner.task_running(description.clone());
+        let mut data = self.data.lock().unwrap();
+        let key = data.next_task_key;
+        data.next_task_key += 1;
+        let task = ActiveTask {
+            inner_key,
+            description: description.into(),
+            thread: data.thread(),
+            start: data.now(),
+        };
+        data.active_tasks.insert(key, task);
+        key
+    }
+
+    fn task_suspend(&self, key: LogTaskKey) {
+        let inner_key = {
+            let mut data = self.data.lock().unwrap();
+            let end = data.now();
+            match data.active_tasks.remove(&key) {
+                None => return,
+                Some(task) => {
+                    data.events.push(Event::Task {
+                        description: task.description,
+                        thread: task.thread,
+                        start: task.start,
+                        end,
+                    });
+                    task.inner_key
+                }
+            }
+        };
+        self.inner.task_suspend(inner_key)
+    }
+
+    fn timer_pending(&self, id: RSlice<RString>) {
+        {
+            let mut data = self.data.lock().unwrap();
+            let work = PendingWork {
+                thread: data.thread(),
+                start: data.now(),
+            };
+            data.pending_work.entry(to_ids(id)).or_default().push(work);
+        }
+        self.inner.timer_pending(id)
+    }
+
+    fn timer_complete(&self, id: RSlice<RString>, duration: ROption<RDuration>) {
+        {
+            let mut data = self.data.lock().unwrap();
+            let end = data.now();
+            let ids = to_ids(id);
+            if let Some(work) = data.pending_work.get_mut(&ids).and_then(|v| v.pop()) {
+                data.events.push(Event::Work {
+                    id: ids,
+                    thread: work.thread,
+                    start: work.start,
+                    end,
+                    active: duration.into_option().map(|d| d.into()),
+                });
+            }
+        }
+        self.inner.timer_complete(id, duration)
+    }
+
+    fn pause_logging(&self) {
+        self.inner.pause_logging()
+    }
+
+    fn resume_logging(&self) {
+        self.inner.resume_logging()
+    }
+
+    fn cache_access(&self, id: RSlice<RString>, hit: bool) {
+        {
+            let mut data = self.data.lock().unwrap();
+            let ids = to_ids(id);
+            let counts = data.caches.entry(ids.join("/")).or_default();
+            if hit {
+                counts.hits += 1;
+            } else {
+                counts.misses += 1;
+            }
+            let event = Event::Cache {
+                id: ids,
+                thread: data.thread(),
+                at: data.now(),
+                hit,
+            };
+            data.events.push(event);
+        }
+        self.inner.cache_access(id, hit)
+    }
+}
+
+/// Get a duration in (whole) microseconds.
+fn micros(d: Duration) -> u64 {
+    d.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+/// Make a string suitable for use as a frame in a folded stack.
+fn folded_frame(s: &str) -> String {
+    s.replace(|c: char| c == ';' || c == '\n' || c == '\r', " ")
+}
+
+/// Aggregated task statistics.
+#[derive(Default)]
+struct TaskSummary {
+    activations: usize,
+    active: Duration,
+    first_start: Option<Duration>,
+    last_end: Duration,
+}
+
+impl Profile {
+    /// Clear the recorded events, so that the profile only contains subsequent activity.
+    pub fn clear(&self) {
+        let mut data = self.0.lock().unwrap();
+        data.events.clear();
+        data.caches.clear();
+    }
+
+    /// Write the profile as Chrome trace-event JSON to `path`, and as folded stacks (for
+    /// flamegraphs) to `path` with a `.folded` suffix appended (e.g. `profile.json.folded`).
+    ///
+    /// Any tasks or work which are still outstanding are ignored.
+    pub fn write(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let data = self.0.lock().unwrap();
+
+        let mut tasks: BTreeMap<&str, TaskSummary> = BTreeMap::new();
+        for e in &data.events {
+            if let Event::Task {
+                description,
+                start,
+                end,
+                ..
+            } = e
+            {
+                let summary = tasks.entry(description.as_str()).or_default();
+                summary.activations += 1;
+                summary.active += *end - *start;
+                summary.first_start = Some(summary.first_start.map_or(*start, |s| s.min(*start)));
+                summary.last_end = summary.last_end.max(*end);
+            }
+        }
+
+        // Chrome trace events
+        {
+            let mut events = Vec::new();
+            for thread in data.threads.values() {
+                events.push(json!({
+                    "name": "thread_name",
+                    "ph": "M",
+                    "pid": 1,
+                    "tid": thread,
+                    "args": { "name": format!("thread {}", thread) },
+                }));
+            }
+            // Spans are written as async events, as they may overlap arbitrarily on a single
+            // thread (tasks suspend while waiting on other tasks).
+            for (event_id, e) in data.events.iter().enumerate() {
+                match e {
+                    Event::Task {
+                        description,
+                        thread,
+                        start,
+                        end,
+                    } => {
+                        for (ph, ts) in [("b", start), ("e", end)] {
+                            events.push(json!({
+                                "name": description,
+                                "cat": "task",
+                                "ph": ph,
+                                "id": event_id,
+                                "ts": micros(*ts),
+                                "pid": 1,
+                                "tid": thread,
+                            }));
+                        }
+                    }
+                    Event::Work {
+                        id,
+                        thread,
+                        start,
+                        end,
+                        active,
+                    } => {
+                        let active = active.unwrap_or_default();
+                        let waiting = (*end - *start).saturating_sub(active);
+                        let name = id.join("/");
+                        events.push(json!({
+                            "name": name,
+                            "cat": "work",
+                            "ph": "b",
+                            "id": event_id,
+                            "ts": micros(*start),
+                            "pid": 1,
+                            "tid": thread,
+                        }));
+                        events.push(json!({
+                            "name": name,
+                            "cat": "work",
+                            "ph": "e",
+                            "id": event_id,
+                            "ts": micros(*end),
+                            "pid": 1,
+                            "tid": thread,
+                            "args": {
+                                "active_us": micros(active),
+                                "waiting_us": micros(waiting),
+                            },
+                        }));
+                    }
+                    Event::Cache {
+                        id,
+                        thread,
+                        at,
+                        hit,
+                    } => {
+                        events.push(json!({
+                            "name": if *hit { "cache hit" } else { "cache miss" },
+                            "cat": "cache",
+                            "ph": "i",
+                            "s": "t",
+                            "ts": micros(*at),
+                            "pid": 1,
+                            "tid": thread,
+                            "args": { "cache": id.join("/") },
+                        }));
+                    }
+                }
+            }
+
+            let caches: JsonMap<String, JsonValue> = data
+                .caches
+                .iter()
+                .map(|(name, counts)| {
+                    (
+                        name.clone(),
+                        json!({ "hits": counts.hits, "misses": counts.misses }),
+                    )
+                })
+                .collect();
+            let task_summaries: JsonMap<String, JsonValue> = tasks
+                .iter()
+                .map(|(description, summary)| {
+                    let span = summary.last_end - summary.first_start.unwrap_or_default();
+                    (
+                        description.to_string(),
+                        json!({
+                            "activations": summary.activations,
+                            "active_us": micros(summary.active),
+                            "waiting_us": micros(span.saturating_sub(summary.active)),
+                        }),
+                    )
+                })
+                .collect();
+
+            let trace = json!({
+                "traceEvents": events,
+                "otherData": { "caches": caches, "tasks": task_summaries },
+            });
+            let mut w = std::io::BufWriter::new(std::fs::File::create(path)?);
+            serde_json::to_writer(&mut w, &trace)?;
+            writeln!(w)?;
+            w.flush()?;
+        }
+
+        // Folded stacks, weighted by active time (in microseconds).
+        {
+            let mut stacks: BTreeMap<String, u128> = BTreeMap::new();
+            for (description, summary) in &tasks {
+                *stacks
+                    .entry(format!("task;{}", folded_frame(description)))
+                    .or_default() += summary.active.as_micros();
+            }
+            for e in &data.events {
+                if let Event::Work {
+                    id,
+                    active: Some(active),
+                    ..
+                } = e
+                {
+                    let mut stack = String::from("work");
+                    for part in id {
+                        stack.push(';');
+                        stack.push_str(&folded_frame(part));
+                    }
+                    *stacks.entry(stack).or_default() += active.as_micros();
+                }
+            }
+
+            let mut folded = path.as_os_str().to_owned();
+            folded.push(".folded");
+            let mut w = std::io::BufWriter::new(std::fs::File::create(folded)?);
+            for (stack, micros) in stacks {
+                if micros > 0 {
+                    writeln!(w, "{} {}", stack, micros)?;
+                }
+            }
+            w.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write() {
+        let ms = Duration::from_millis;
+        let mut caches = BTreeMap::new();
+        caches.insert("cache/a".to_owned(), CacheCounts { hits: 2, misses: 1 });
+        let profile = Profile(Arc::new(Mutex::new(ProfileData {
+            start: Instant::now(),
+            threads: std::iter::once((std::thread::current().id(), 1)).collect(),
+            next_task_key: Default::default(),
+            active_tasks: Default::default(),
+            pending_work: Default::default(),
+            events: vec![
+                Event::Task {
+                    description: "task \"a\"; b".into(),
+                    thread: 1,
+                    start: ms(1),
+                    end: ms(3),
+                },
+                Event::Task {
+                    description: "task \"a\"; b".into(),
+                    thread: 1,
+                    start: ms(5),
+                    end: ms(6),
+                },
+                Event::Work {
+                    id: vec!["exec".into(), "build".into()],
+                    thread: 1,
+                    start: ms(2),
+                    end: ms(10),
+                    active: Some(ms(5)),
+                },
+                Event::Cache {
+                    id: vec!["cache".into(), "a".into()],
+                    thread: 1,
+                    at: ms(4),
+                    hit: true,
+                },
+            ],
+            caches,
+        })));
+
+        let dir = std::env::temp_dir().join(format!("ergo-profile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("profile.json");
+        profile.write(&path).unwrap();
+
+        let trace: JsonValue =
+            serde_json::from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        // One thread name, two events for each span, and one cache access.
+        assert_eq!(events.len(), 8);
+        assert_eq!(events[0]["ph"], "M");
+        assert_eq!(events[1]["name"], "task \"a\"; b");
+        assert_eq!(events[1]["ph"], "b");
+        assert_eq!(events[1]["ts"], 1000);
+        assert_eq!(events[2]["ph"], "e");
+        assert_eq!(events[2]["ts"], 3000);
+        assert_eq!(events[5]["name"], "exec/build");
+        assert_eq!(events[6]["args"]["active_us"], 5000);
+        assert_eq!(events[6]["args"]["waiting_us"], 3000);
+        assert_eq!(events[7]["name"], "cache hit");
+        assert_eq!(events[7]["args"]["cache"], "cache/a");
+        assert_eq!(
+            trace["otherData"],
+            json!({
+                "caches": { "cache/a": { "hits": 2, "misses": 1 } },
+                "tasks": {
+                    "task \"a\"; b": { "activations": 2, "active_us": 3000, "waiting_us": 2000 },
+                },
+            })
+        );
+
+        let mut folded = path.as_os_str().to_owned();
+        folded.push(".folded");
+        assert_eq!(
+            std::fs::read_to_string(folded).unwrap(),
+            "task;task \"a\"  b 3000\nwork;exec;build 5000\n"
+        );
+        drop(std::fs::remove_dir_all(&dir));
+    }
+}
@@ -0,0 +1,128 @@
+//! Watching evaluation inputs for changes.
+
+use super::Interrupt;
+use crate::AppErr;
+use notify::{event::EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+/// The period to wait for more changes after a change occurs.
+///
+/// Many programs will write files in multiple steps (or write many files at once), so this avoids
+/// re-evaluating more than necessary.
+const SETTLE_DURATION: Duration = Duration::from_millis(100);
+
+/// The period at which to check for interrupts while waiting.
+const INTERRUPT_CHECK_DURATION: Duration = Duration::from_millis(100);
+
+/// Wait for any of the given paths to change.
+///
+/// Returns the (canonical) changed paths when a change occurs, or `Ok(None)` if interrupted.
+pub fn wait_for_change(
+    paths: &[PathBuf],
+    interrupt: &Interrupt,
+) -> Result<Option<Vec<PathBuf>>, String> {
+    let (send, recv) = std::sync::mpsc::channel();
+    let mut watcher = RecommendedWatcher::new(send, notify::Config::default())
+        .app_err("failed to create file watcher")?;
+
+    // Watch events always have absolute paths.
+    let paths: HashSet<PathBuf> = paths
+        .iter()
+        .map(|p| std::fs::canonicalize(p).unwrap_or_else(|_| p.clone()))
+        .collect();
+
+    // Watch the parent directories rather than the files themselves, since many editors replace
+    // files (which would remove a watch on the file).
+    let dirs: HashSet<_> = paths.iter().filter_map(|p| p.parent()).collect();
+    for dir in dirs {
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            log::warn!("failed to watch {}: {}", dir.display(), e);
+        }
+    }
+
+    let mut changed = HashSet::new();
+    let add_changes =
+        |changed: &mut HashSet<PathBuf>, event: notify::Result<notify::Event>| match event {
+            Ok(event) => {
+                if !matches!(event.kind, EventKind::Access(_)) {
+                    changed.extend(event.paths.into_iter().filter(|p| paths.contains(p)));
+                }
+            }
+            Err(e) => log::warn!("file watcher error: {}", e),
+        };
+
+    loop {
+        match recv.recv_timeout(INTERRUPT_CHECK_DURATION) {
+            Ok(event) => {
+                add_changes(&mut changed, event);
+                if !changed.is_empty() {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err("file watcher stopped unexpectedly".into())
+            }
+        }
+        if interrupt.interrupted() {
+            return Ok(None);
+        }
+    }
+
+    // Wait for changes to settle.
+    while let Ok(event) = recv.recv_timeout(SETTLE_DURATION) {
+        add_changes(&mut changed, event);
+    }
+
+    Ok((!interrupt.interrupted()).then(|| changed.into_iter().collect()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn interrupt(interrupted: bool) -> Interrupt {
+        Interrupt {
+            task: Default::default(),
+            interrupted: interrupted.into(),
+        }
+    }
+
+    #[test]
+    fn changed_paths() {
+        let dir = std::env::temp_dir().join(format!("ergo-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let watched = dir.join("watched");
+        let other = dir.join("other");
+        std::fs::write(&watched, "a").unwrap();
+
+        let writer = {
+            let watched = watched.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(200));
+                std::fs::write(&other, "b").unwrap();
+                std::fs::write(&watched, "b").unwrap();
+            })
+        };
+
+        let changed = wait_for_change(&[watched.clone()], &interrupt(false)).unwrap();
+        writer.join().unwrap();
+        drop(std::fs::remove_dir_all(&dir));
+
+        // Changes to unwatched files in the same directory are not reported.
+        assert_eq!(changed, Some(vec![watched]));
+    }
+
+    #[test]
+    fn interrupted() {
+        let dir = std::env::temp_dir();
+        assert_eq!(
+            wait_for_change(&[dir.join("ergo-watch-interrupted")], &interrupt(true)).unwrap(),
+            None
+        );
+    }
+}
@@ -1,18 +1,37 @@
 //! Script formatting.
 
 use ergo_runtime::Source;
-use ergo_script::ast::tokenize::{PairedToken, SymbolicToken, Token, Tokens};
+use ergo_script::ast::tokenize::{self, PairedToken, SymbolicToken, Token, Tokens};
 use pretty::DocAllocator;
 use std::borrow::Cow;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// The name of the formatter configuration file.
+pub const CONFIG_NAME: &str = "ergofmt.conf";
 
 #[derive(Debug, clap::Args)]
 /// Format a script or scripts.
+///
+/// Options are read from the nearest `ergofmt.conf` file in the ancestors of each file (or the
+/// current directory when reading stdin), typically placed next to `workspace.ergo`. The file
+/// contains `name = value` lines (and `#` comments), where names are `line-width` and `indent`.
+/// Options given on the command line take precedence.
 pub struct Format {
     #[clap(short)]
     /// Modify files in-place rather than printing to stdout.
     pub in_place: bool,
+    #[clap(long)]
+    /// Check whether files are formatted rather than formatting them.
+    ///
+    /// Differences are printed to stdout, and the command fails if any file is not formatted.
+    pub check: bool,
+    #[clap(long)]
+    /// The maximum line width.
+    pub line_width: Option<usize>,
+    #[clap(long)]
+    /// The number of spaces to indent nested lines.
+    pub indent: Option<u8>,
     /// Files to format. If no files are specified, stdin is read and the formatted script is written to stdout.
     pub files: Vec<PathBuf>,
 }
@@ -90,6 +109,46 @@ impl<E: std::error::Error + 'static> std::error::Error for Error<E> {
 }
 
 impl FormatOptions {
+    /// Load options from the nearest configuration file in the given directory or its ancestors.
+    ///
+    /// If there is no configuration file, the default options are returned.
+    pub fn from_config(dir: &Path) -> Result<Self, String> {
+        let mut options = Self::default();
+        let path = match dir
+            .ancestors()
+            .map(|p| p.join(CONFIG_NAME))
+            .find(|p| p.exists())
+        {
+            Some(path) => path,
+            None => return Ok(options),
+        };
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        for (n, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = || format!("{}:{}: invalid setting", path.display(), n + 1);
+            let (name, value) = line.split_once('=').ok_or_else(err)?;
+            let value = value.trim();
+            match name.trim() {
+                "line-width" => options.line_width = value.parse().map_err(|_| err())?,
+                "indent" => options.indent = value.parse().map_err(|_| err())?,
+                _ => return Err(err()),
+            }
+        }
+        Ok(options)
+    }
+
+    /// Format a script string.
+    pub fn format_str(&self, s: &str) -> Result<String, Error<tokenize::Error>> {
+        self.format(
+            Tokens::from(Source::new(0).with(s))
+                .map(|r| r.map(|s| s.unwrap()).map_err(|e| e.unwrap())),
+        )
+    }
+
     pub fn format<
         'a,
         S: Clone + Into<Cow<'a, str>>,
@@ -128,10 +187,7 @@ impl FormatOptions {
         if let Err(e) = read().and_then(|mut r| r.read_to_string(&mut s)) {
             return Err(format!("failed to read {}: {}", name, e));
         }
-        match self.format(
-            Tokens::from(Source::new(0).with(s.as_str()))
-                .map(|r| r.map(|s| s.unwrap()).map_err(|e| e.unwrap())),
-        ) {
+        match self.format_str(&s) {
             Err(e) => Err(format!("failed to format {}: {}", name, e)),
             Ok(s) => {
                 if let Err(e) = write().and_then(|mut w| write!(w, "{}", s)) {
@@ -320,22 +376,185 @@ impl<'a> Formatter<'a> {
     ) -> pretty::DocBuilder<'a, pretty::Arena<'a>> {
         self.arena
             .text(start)
-            .append(self.arena.line_().append(inner).nest(4))
+            .append(
+                self.arena
+                    .line_()
+                    .append(inner)
+                    .nest(self.options.indent as isize),
+            )
             .append(self.arena.line_())
             .append(self.arena.text(end))
             .group()
     }
 }
 
+/// A changed block of lines between two texts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineChange {
+    /// The range of lines in the old text.
+    pub old: std::ops::Range<usize>,
+    /// The range of lines in the new text.
+    pub new: std::ops::Range<usize>,
+}
+
+/// Get the changed blocks of lines between two texts.
+pub fn line_changes(old: &[&str], new: &[&str]) -> Vec<LineChange> {
+    // Trim the common prefix and suffix, which are typically most of the lines.
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+
+    // Compute the lengths of the longest common subsequences of the suffixes.
+    let width = new.len() + 1;
+    let mut lcs = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                std::cmp::max(lcs[(i + 1) * width + j], lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let mut current: Option<LineChange> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            changes.extend(current.take());
+            i += 1;
+            j += 1;
+        } else {
+            let change = current.get_or_insert(LineChange {
+                old: i..i,
+                new: j..j,
+            });
+            if j < new.len()
+                && (i == old.len() || lcs[i * width + j + 1] >= lcs[(i + 1) * width + j])
+            {
+                j += 1;
+                change.new.end = j;
+            } else {
+                i += 1;
+                change.old.end = i;
+            }
+        }
+    }
+    changes.extend(current);
+    for change in &mut changes {
+        change.old = change.old.start + prefix..change.old.end + prefix;
+        change.new = change.new.start + prefix..change.new.end + prefix;
+    }
+    changes
+}
+
+/// Write the differences between two texts as a unified diff (without context lines).
+fn write_diff<W: Write>(
+    mut w: W,
+    name: &dyn std::fmt::Display,
+    old: &str,
+    new: &str,
+) -> std::io::Result<()> {
+    let old: Vec<_> = old.split_inclusive('\n').collect();
+    let new: Vec<_> = new.split_inclusive('\n').collect();
+    writeln!(w, "--- {}", name)?;
+    writeln!(w, "+++ {} (formatted)", name)?;
+    for change in line_changes(&old, &new) {
+        writeln!(
+            w,
+            "@@ -{},{} +{},{} @@",
+            change.old.start + 1,
+            change.old.len(),
+            change.new.start + 1,
+            change.new.len()
+        )?;
+        let lines = old[change.old]
+            .iter()
+            .map(|l| ('-', l))
+            .chain(new[change.new].iter().map(|l| ('+', l)));
+        for (prefix, line) in lines {
+            write!(w, "{}{}", prefix, line)?;
+            if !line.ends_with('\n') {
+                writeln!(w)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Format {
+    /// Get the format options for files in the given directory.
+    fn options(&self, dir: &Path) -> Result<FormatOptions, String> {
+        let mut options = FormatOptions::from_config(dir)?;
+        if let Some(line_width) = self.line_width {
+            options.line_width = line_width;
+        }
+        if let Some(indent) = self.indent {
+            options.indent = indent;
+        }
+        Ok(options)
+    }
+
+    /// Check whether the content is formatted, printing the differences if not.
+    fn check<R: Read>(
+        options: &FormatOptions,
+        mut read: R,
+        name: &dyn std::fmt::Display,
+    ) -> Result<bool, String> {
+        let mut s = String::new();
+        read.read_to_string(&mut s)
+            .map_err(|e| format!("failed to read {}: {}", name, e))?;
+        let formatted = options
+            .format_str(&s)
+            .map_err(|e| format!("failed to format {}: {}", name, e))?;
+        if formatted == s {
+            Ok(true)
+        } else {
+            write_diff(std::io::stdout().lock(), name, &s, &formatted)
+                .map_err(|e| format!("failed to write differences: {}", e))?;
+            Ok(false)
+        }
+    }
+}
+
 impl super::Command for Format {
     fn run(self) -> Result<(), String> {
-        let options = FormatOptions::default();
+        let current_dir = std::env::current_dir()
+            .map_err(|e| format!("failed to get current directory: {}", e))?;
 
         if self.files.is_empty() {
-            options.format_io(|| Ok(std::io::stdin()), || Ok(std::io::stdout()), "<stdin>")
+            let options = self.options(&current_dir)?;
+            if self.check {
+                if !Self::check(&options, std::io::stdin(), &"<stdin>")? {
+                    return Err("<stdin> is not formatted".into());
+                }
+                Ok(())
+            } else {
+                options.format_io(|| Ok(std::io::stdin()), || Ok(std::io::stdout()), "<stdin>")
+            }
         } else {
-            for f in self.files {
-                if self.in_place {
+            let mut unformatted = 0;
+            for f in &self.files {
+                let options = self.options(
+                    current_dir
+                        .join(f)
+                        .parent()
+                        .unwrap_or(current_dir.as_path()),
+                )?;
+                if self.check {
+                    let file = std::fs::File::open(f)
+                        .map_err(|e| format!("failed to read {}: {}", f.display(), e))?;
+                    if !Self::check(&options, file, &f.display())? {
+                        unformatted += 1;
+                    }
+                } else if self.in_place {
                     options.format_io(
                         || std::fs::File::open(&f),
                         || std::fs::File::create(&f),
@@ -349,7 +568,93 @@ impl super::Command for Format {
                     )?;
                 }
             }
-            Ok(())
+            if unformatted > 0 {
+                Err(format!("{} file(s) are not formatted", unformatted))
+            } else {
+                Ok(())
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{line_changes, FormatOptions, LineChange, CONFIG_NAME};
+
+    #[test]
+    fn line_changes_none() {
+        assert_eq!(line_changes(&["a", "b"], &["a", "b"]), vec![]);
+    }
+
+    #[test]
+    fn line_changes_replace() {
+        assert_eq!(
+            line_changes(&["a", "b", "c"], &["a", "x", "y", "c"]),
+            vec![LineChange {
+                old: 1..2,
+                new: 1..3
+            }]
+        );
+    }
+
+    #[test]
+    fn line_changes_insert_and_remove() {
+        assert_eq!(
+            line_changes(&["a", "b", "c", "d"], &["x", "a", "b", "d"]),
+            vec![
+                LineChange {
+                    old: 0..0,
+                    new: 0..1
+                },
+                LineChange {
+                    old: 2..3,
+                    new: 3..3
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn line_changes_repeated_lines() {
+        assert_eq!(
+            line_changes(&["a", "a"], &["a", "a", "a"]),
+            vec![LineChange {
+                old: 2..2,
+                new: 2..3
+            }]
+        );
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("ergo-format-{}-{}", name, std::process::id()));
+        drop(std::fs::remove_dir_all(&dir));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn from_config() {
+        let dir = temp_dir("config");
+        std::fs::write(
+            dir.join(CONFIG_NAME),
+            "# comment\nline-width = 80\n\nindent=2\n",
+        )
+        .unwrap();
+        let sub = dir.join("sub");
+        std::fs::create_dir(&sub).unwrap();
+        let options = FormatOptions::from_config(&sub).unwrap();
+        assert_eq!(options.line_width, 80);
+        assert_eq!(options.indent, 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn from_config_invalid() {
+        let dir = temp_dir("config-invalid");
+        std::fs::write(dir.join(CONFIG_NAME), "tabs = true\n").unwrap();
+        assert!(FormatOptions::from_config(&dir).is_err());
+        std::fs::write(dir.join(CONFIG_NAME), "indent = wide\n").unwrap();
+        assert!(FormatOptions::from_config(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+//! Lock file management.
+
+use super::evaluate::{lock_file, Evaluate};
+use crate::AppErr;
+use ergo_runtime::context::LockFile;
+
+#[derive(Debug, clap::Args)]
+/// Manage the lock file.
+///
+/// The lock file (in the project root) pins the content of resources retrieved with `std:net:http
+/// ~lock` (such as `std:load-remote` archives) and `std:fs:unarchive ~lock`. The content of a
+/// resource is recorded when it is first retrieved, and verified on subsequent retrievals.
+pub struct Lock {
+    #[clap(subcommand)]
+    command: LockCommand,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum LockCommand {
+    /// List the lock file entries.
+    List,
+    /// Evaluate a value, recording the content of retrieved resources rather than verifying it.
+    Update(Update),
+}
+
+#[derive(Debug, clap::Args)]
+struct Update {
+    #[clap(short, long = "key")]
+    /// The key of an entry to update (typically a url). May be specified multiple times.
+    ///
+    /// If unspecified, all entries which are retrieved are updated.
+    keys: Vec<String>,
+
+    #[clap(flatten)]
+    evaluate: Evaluate,
+}
+
+impl super::Command for Lock {
+    fn run(self) -> Result<(), String> {
+        match self.command {
+            LockCommand::List => {
+                let lock = LockFile::load(Some(lock_file())).app_err("failed to load lock file")?;
+                for (key, hash) in lock.entries() {
+                    println!("{} {}", hash, key);
+                }
+                Ok(())
+            }
+            LockCommand::Update(Update { keys, mut evaluate }) => {
+                evaluate.lock_update = Some(keys);
+                super::Command::run(evaluate)
+            }
+        }
+    }
+}
@@ -0,0 +1,401 @@
+//! Task logs of past runs.
+
+use super::evaluate::storage_directory;
+use crate::AppErr;
+use ergo_runtime::context::{task_directory_name, ChildUsage, TASK_LOG_ENTRIES, TASK_LOG_USAGE};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The directory (within the storage directory) in which run logs are saved.
+const LOGS_DIRECTORY: &str = "logs";
+
+/// The file (within a run directory) containing the command line of the run.
+const COMMAND_FILE: &str = "command";
+
+/// The number of runs for which logs are retained.
+const MAX_RUNS: usize = 20;
+
+/// The minimum age of a run before its logs may be removed.
+///
+/// This prevents removing the logs of runs which may still be in progress.
+const RETENTION_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, clap::Args)]
+/// Show the task logs of past runs.
+///
+/// When evaluating, the log entries of each task (created with `std:task`) and the stdout and
+/// stderr of each child process spawned within a task are saved in the storage directory. The logs
+/// of the most recent 20 runs are retained (older runs are removed once they are an hour old). Runs
+/// which do not execute tasks (with `--doc`, `--list`, `--dry-run`, or `--explain-id`) are not
+/// recorded.
+///
+/// With no task, past runs and their tasks are listed. Otherwise the log entries and child output
+/// of the given task are shown.
+pub struct Logs {
+    #[clap(long, default_value = ".ergo_work")]
+    /// The storage directory for the runtime.
+    ///
+    /// If a relative path, it will be made relative to the furthest ancestor directory that is a
+    /// workspace. If none are found, the current directory is used.
+    pub storage: PathBuf,
+
+    #[clap(long)]
+    /// The run to show.
+    ///
+    /// If unspecified, all runs are listed, and a task is shown from the most recent run which
+    /// has the task.
+    pub run: Option<String>,
+
+    /// The description of the task to show.
+    pub task: Option<String>,
+}
+
+/// Create the log directory of a new run, removing the oldest runs beyond the retained number.
+///
+/// Runs are only removed once they are older than the retention window, and failures to remove
+/// runs are logged rather than returned.
+///
+/// Returns the directory in which task logs should be written.
+pub(crate) fn new_run(storage_directory: &Path, command: &str) -> std::io::Result<PathBuf> {
+    let logs = storage_directory.join(LOGS_DIRECTORY);
+    let run = chrono::Local::now()
+        .format("%Y-%m-%dT%H-%M-%S%.3f")
+        .to_string();
+    let dir = logs.join(run);
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(COMMAND_FILE), command)?;
+    prune_runs(&logs, &dir, MAX_RUNS, RETENTION_WINDOW);
+    Ok(dir)
+}
+
+/// Remove the oldest runs beyond `max_runs`, other than `current` and runs which are not older
+/// than `retention`.
+fn prune_runs(logs: &Path, current: &Path, max_runs: usize, retention: Duration) {
+    match runs(logs) {
+        Ok(old_runs) => {
+            for old in &old_runs[..old_runs.len().saturating_sub(max_runs)] {
+                let old = logs.join(old);
+                if old == current || !older_than(&old, retention) {
+                    continue;
+                }
+                if let Err(e) = std::fs::remove_dir_all(&old) {
+                    log::warn!("failed to remove old run logs {}: {}", old.display(), e);
+                }
+            }
+        }
+        Err(e) => log::warn!("failed to read run logs: {}", e),
+    }
+}
+
+/// Return whether the given path was last modified more than `age` ago.
+fn older_than(path: &Path, age: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok())
+        .map(|elapsed| elapsed > age)
+        .unwrap_or(false)
+}
+
+/// Get the runs in the logs directory, from oldest to newest.
+fn runs(logs: &Path) -> std::io::Result<Vec<String>> {
+    if !logs.exists() {
+        return Ok(Vec::new());
+    }
+    // Run names sort chronologically.
+    subdirectories(logs)
+}
+
+/// Get the sorted names of the subdirectories of a directory.
+fn subdirectories(dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut ret = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            if let Ok(name) = entry.file_name().into_string() {
+                ret.push(name);
+            }
+        }
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+/// Get the output files of the children of a task, in order.
+fn child_outputs(task: &Path) -> std::io::Result<Vec<(usize, PathBuf)>> {
+    let mut ret = Vec::new();
+    for entry in std::fs::read_dir(task)? {
+        let path = entry?.path();
+        let n = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse::<usize>().ok());
+        if let Some(n) = n {
+            ret.push((n, path));
+        }
+    }
+    // Sort by child number, with stdout before stderr.
+    ret.sort_by(|(a, pa), (b, pb)| a.cmp(b).then_with(|| pb.cmp(pa)));
+    Ok(ret)
+}
+
+/// The resource usage of the children of a task.
+#[derive(Default)]
+struct TaskUsage {
+    children: usize,
+    wall: Duration,
+    cpu: Duration,
+    max_rss: Option<u64>,
+}
+
+impl TaskUsage {
+    fn add(&mut self, usage: ChildUsage) {
+        self.children += 1;
+        self.wall += usage.wall;
+        self.cpu += usage.user.unwrap_or_default() + usage.system.unwrap_or_default();
+        self.max_rss = self.max_rss.max(usage.max_rss);
+    }
+}
+
+/// Format a number of bytes with a binary unit.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut n = bytes as f64;
+    let mut unit = 0;
+    while n >= 1024. && unit + 1 < UNITS.len() {
+        n /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", n, UNITS[unit])
+    }
+}
+
+/// Summarize the resource usage of child processes of each task in a run.
+///
+/// Tasks are sorted by the peak resident set size of their children, largest first.
+pub(crate) fn usage_summary(run: &Path) -> std::io::Result<String> {
+    let mut tasks = Vec::new();
+    for task in subdirectories(run)? {
+        let path = run.join(&task).join(TASK_LOG_USAGE);
+        if !path.exists() {
+            continue;
+        }
+        let mut usage = TaskUsage::default();
+        for line in std::fs::read_to_string(path)?.lines() {
+            // Lines are the child number followed by the usage.
+            let child_usage = line
+                .split_once(' ')
+                .and_then(|(_, u)| u.parse::<ChildUsage>().ok());
+            if let Some(u) = child_usage {
+                usage.add(u);
+            }
+        }
+        tasks.push((task, usage));
+    }
+    tasks.sort_by(|(_, a), (_, b)| b.max_rss.cmp(&a.max_rss));
+
+    let rows: Vec<[String; 5]> = tasks
+        .into_iter()
+        .map(|(task, u)| {
+            [
+                task,
+                u.children.to_string(),
+                format!("{:.1}s", u.wall.as_secs_f64()),
+                format!("{:.1}s", u.cpu.as_secs_f64()),
+                u.max_rss.map(format_bytes).unwrap_or_else(|| "-".into()),
+            ]
+        })
+        .collect();
+    if rows.is_empty() {
+        return Ok("no child processes were spawned in tasks\n".into());
+    }
+
+    let header = ["task", "children", "wall", "cpu", "peak rss"].map(String::from);
+    let mut widths = [0; 5];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (w, s) in widths.iter_mut().zip(row) {
+            *w = (*w).max(s.len());
+        }
+    }
+    let mut s = String::from("child process usage by task:\n");
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, w)| format!("{:w$}", cell, w = w))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(s, "  {}", line.trim_end()).unwrap();
+    }
+    Ok(s)
+}
+
+/// List runs and their tasks.
+fn list(logs: &Path, runs: &[String]) -> Result<(), String> {
+    for run in runs {
+        let dir = logs.join(run);
+        let command = std::fs::read_to_string(dir.join(COMMAND_FILE)).unwrap_or_default();
+        println!("{}  {}", run, command);
+        for task in subdirectories(&dir).app_err("failed to read run directory")? {
+            println!("  {}", task);
+        }
+    }
+    Ok(())
+}
+
+/// Show the log entries and child output of a task, from the most recent of the runs which has the
+/// task.
+fn show(logs: &Path, runs: &[String], task: &str) -> Result<(), String> {
+    let name = task_directory_name(task);
+    let dir = runs
+        .iter()
+        .rev()
+        .map(|run| logs.join(run).join(&name))
+        .find(|dir| dir.exists())
+        .app_err(&format!("no logs found for task '{}'", task))?;
+
+    let entries = dir.join(TASK_LOG_ENTRIES);
+    if entries.exists() {
+        print!(
+            "{}",
+            std::fs::read_to_string(&entries).app_err("failed to read task log")?
+        );
+    }
+    for (n, path) in child_outputs(&dir).app_err("failed to read task log directory")? {
+        let output = std::fs::read(&path).app_err("failed to read child output")?;
+        if output.is_empty() {
+            continue;
+        }
+        let stream = path
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        println!("--- child {} {} ---", n, stream);
+        std::io::stdout()
+            .write_all(&output)
+            .app_err("failed to write output")?;
+        if !output.ends_with(b"\n") {
+            println!();
+        }
+    }
+    Ok(())
+}
+
+impl super::Command for Logs {
+    fn run(self) -> Result<(), String> {
+        let logs = storage_directory(&self.storage).join(LOGS_DIRECTORY);
+        let mut runs = runs(&logs).app_err("failed to read logs directory")?;
+        if let Some(run) = &self.run {
+            if !runs.contains(run) {
+                return Err(format!("no logs found for run '{}'", run));
+            }
+            runs = vec![run.clone()];
+        }
+
+        match &self.task {
+            None => list(&logs, &runs),
+            Some(task) => show(&logs, &runs, task),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Create an empty temporary directory for a test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ergo-logs-{}-{}", name, std::process::id()));
+        drop(std::fs::remove_dir_all(&dir));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn new_run_command() {
+        let storage = temp_dir("new-run");
+        let run = new_run(&storage, "build --release").unwrap();
+        assert_eq!(run.parent(), Some(storage.join(LOGS_DIRECTORY).as_path()));
+        assert_eq!(
+            std::fs::read_to_string(run.join(COMMAND_FILE)).unwrap(),
+            "build --release"
+        );
+        drop(std::fs::remove_dir_all(&storage));
+    }
+
+    #[test]
+    fn prune() {
+        let logs = temp_dir("prune");
+        let names: Vec<_> = (0..5)
+            .map(|i| format!("2020-01-01T00-00-0{}.000", i))
+            .collect();
+        for name in &names {
+            std::fs::create_dir(logs.join(name)).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(10));
+
+        // Runs within the retention window are retained.
+        prune_runs(&logs, &logs.join(&names[4]), 2, RETENTION_WINDOW);
+        assert_eq!(runs(&logs).unwrap(), names);
+
+        // The oldest runs beyond the maximum are removed, other than the current run.
+        prune_runs(&logs, &logs.join(&names[0]), 2, Duration::ZERO);
+        assert_eq!(
+            runs(&logs).unwrap(),
+            vec![names[0].clone(), names[3].clone(), names[4].clone()]
+        );
+        drop(std::fs::remove_dir_all(&logs));
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1.0 KiB");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 << 30), "3.0 GiB");
+        assert_eq!(format_bytes(2048 << 40), "2048.0 TiB");
+    }
+
+    #[test]
+    fn usage() {
+        let run = temp_dir("usage");
+        let tasks = [
+            ("small", "1 1000000 500000 200000 1048576\n"),
+            ("big", "1 2000000 1000000 - 2097152\n2 500000 - - 3145728\n"),
+            ("none", "1 100000 - - -\nmalformed\n"),
+        ];
+        for (name, usage) in tasks {
+            std::fs::create_dir(run.join(name)).unwrap();
+            std::fs::write(run.join(name).join(TASK_LOG_USAGE), usage).unwrap();
+        }
+        // Tasks without child processes are omitted.
+        std::fs::create_dir(run.join("empty")).unwrap();
+
+        assert_eq!(
+            usage_summary(&run).unwrap(),
+            "child process usage by task:\n\
+             \x20 task   children  wall  cpu   peak rss\n\
+             \x20 big    2         2.5s  1.0s  3.0 MiB\n\
+             \x20 small  1         1.0s  0.7s  1.0 MiB\n\
+             \x20 none   1         0.1s  0.0s  -\n"
+        );
+        drop(std::fs::remove_dir_all(&run));
+    }
+
+    #[test]
+    fn no_usage() {
+        let run = temp_dir("no-usage");
+        std::fs::create_dir(run.join("task")).unwrap();
+        assert_eq!(
+            usage_summary(&run).unwrap(),
+            "no child processes were spawned in tasks\n"
+        );
+        drop(std::fs::remove_dir_all(&run));
+    }
+}
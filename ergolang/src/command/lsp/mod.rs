@@ -1,4 +1,4 @@
-use super::format::FormatOptions;
+use super::format::{line_changes, FormatOptions};
 use ergo_runtime::async_executor;
 use ergo_runtime::source::{Location, Source};
 use ergo_script::ast::tokenize;
@@ -244,6 +244,108 @@ fn char_offset(s: &ropey::Rope, pos: Position) -> Option<usize> {
     Some(line_offset + pos.character as usize)
 }
 
+fn position(s: &ropey::Rope, byte_offset: usize) -> Position {
+    let c = s.byte_to_char(byte_offset);
+    let line = s.char_to_line(c);
+    Position {
+        line: line as u32,
+        character: (c - s.line_to_char(line)) as u32,
+    }
+}
+
+/// A fix of part of a script.
+struct Fix {
+    title: String,
+    location: Location,
+    replacement: String,
+}
+
+/// Get the available fixes of a script, from migrations and lints.
+fn fixes(script: &str) -> Vec<Fix> {
+    let mut ret: Vec<_> = super::migrate::migrations(Source::new(0).with(script))
+        .into_iter()
+        .filter_map(|m| {
+            m.change.map(|change| Fix {
+                title: m.message.into_owned(),
+                location: m.source.location,
+                replacement: change.into_owned(),
+            })
+        })
+        .collect();
+    if let Ok((_, _, lints)) = ergo_script::ast::load(
+        Source::new(0).with(script),
+        &mut Default::default(),
+        ergo_script::LintLevel::Aggressive,
+    ) {
+        ret.extend(lints.into_iter().filter_map(|l| {
+            let (src, l) = l.take();
+            l.fix.map(|fix| Fix {
+                title: format!("{}: replace with `{}`", l.message, fix),
+                location: src.location,
+                replacement: fix,
+            })
+        }));
+    }
+    ret
+}
+
+/// Get the format options for a document, read from any configuration file.
+fn format_options(uri: &Url) -> FormatOptions {
+    uri.to_file_path()
+        .ok()
+        .and_then(|p| p.parent().map(|p| FormatOptions::from_config(p).ok()))
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// Format a document, returning edits for the changed lines.
+///
+/// If `lines` is provided, only changes overlapping that range of lines are returned.
+fn format_edits(
+    uri: &Url,
+    source: &ropey::Rope,
+    lines: Option<std::ops::Range<usize>>,
+) -> Option<Vec<TextEdit>> {
+    let new_text = format_options(uri)
+        .format(
+            tokenize::Tokens::from(Source::new(0).with(RopeSlice(source.slice(..))))
+                .map(|r| r.map(Source::unwrap).map_err(Source::unwrap)),
+        )
+        // XXX send error
+        .ok()?;
+    let old_text = source.to_string();
+    let old: Vec<_> = old_text.split_inclusive('\n').collect();
+    let new: Vec<_> = new_text.split_inclusive('\n').collect();
+
+    let line_position = |line: usize| {
+        if line < old.len() {
+            Position {
+                line: line as u32,
+                character: 0,
+            }
+        } else {
+            position(source, source.len_bytes())
+        }
+    };
+
+    Some(
+        line_changes(&old, &new)
+            .into_iter()
+            .filter(|c| match &lines {
+                None => true,
+                Some(r) => c.old.start < r.end && (c.old.end > r.start || c.old.start == r.start),
+            })
+            .map(|c| TextEdit {
+                range: Range {
+                    start: line_position(c.old.start),
+                    end: line_position(c.old.end),
+                },
+                new_text: new[c.new].concat(),
+            })
+            .collect(),
+    )
+}
+
 #[derive(Debug, Clone)]
 struct RopeSlice<'a>(ropey::RopeSlice<'a>);
 
@@ -330,6 +432,12 @@ impl LanguageServer for Service {
             TextDocumentSyncKind::INCREMENTAL,
         ));
         capabilities.document_formatting_provider = Some(OneOf::Left(true));
+        capabilities.document_range_formatting_provider = Some(OneOf::Left(true));
+        capabilities.document_on_type_formatting_provider = Some(DocumentOnTypeFormattingOptions {
+            first_trigger_character: "}".into(),
+            more_trigger_character: Some(vec!["]".into(), ")".into(), "\n".into()]),
+        });
+        capabilities.code_action_provider = Some(CodeActionProviderCapability::Simple(true));
         Ok(InitializeResult {
             server_info: Some(ServerInfo {
                 name: env!("CARGO_PKG_NAME").into(),
@@ -360,31 +468,80 @@ impl LanguageServer for Service {
 
     async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
         let source = self.files.content(&params.text_document.uri).await;
+        Ok(format_edits(&params.text_document.uri, &*source, None))
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let source = self.files.content(&params.text_document.uri).await;
+        let lines = params.range.start.line as usize..params.range.end.line as usize + 1;
+        Ok(format_edits(
+            &params.text_document.uri,
+            &*source,
+            Some(lines),
+        ))
+    }
 
-        let format_options = FormatOptions::default();
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let doc = params.text_document_position;
+        let source = self.files.content(&doc.text_document.uri).await;
+        // Format the line with the typed character, and the previous line for newlines.
+        let line = doc.position.line as usize;
+        let lines = line.saturating_sub((params.ch == "\n") as usize)..line + 1;
+        Ok(format_edits(&doc.text_document.uri, &*source, Some(lines)))
+    }
 
-        match format_options.format(
-            tokenize::Tokens::from(Source::new(0).with(RopeSlice(source.slice(..))))
-                .map(|r| r.map(Source::unwrap).map_err(Source::unwrap)),
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let source = self.files.content(&params.text_document.uri).await;
+        let (start, end) = match (
+            char_offset(&*source, params.range.start),
+            char_offset(&*source, params.range.end),
         ) {
-            Err(_) => {
-                // XXX send error
-                Ok(None)
+            (Some(start), Some(end)) => {
+                let len = source.len_chars();
+                (
+                    source.char_to_byte(start.min(len)),
+                    source.char_to_byte(end.min(len)),
+                )
             }
-            Ok(new_text) => Ok(Some(vec![TextEdit {
-                range: Range {
-                    start: Position {
-                        line: 0,
-                        character: 0,
+            _ => return Ok(None),
+        };
+
+        let actions = fixes(&source.to_string())
+            .into_iter()
+            .filter(|f| f.location.start <= end && f.location.end() >= start)
+            .map(|f| {
+                let edit = TextEdit {
+                    range: Range {
+                        start: position(&*source, f.location.start),
+                        end: position(&*source, f.location.end()),
                     },
-                    end: Position {
-                        line: source.len_lines() as u32,
-                        character: source.line(source.len_lines() - 1).len_chars() as u32,
-                    },
-                },
-                new_text,
-            }])),
-        }
+                    new_text: f.replacement,
+                };
+                CodeActionOrCommand::CodeAction(CodeAction {
+                    title: f.title,
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(
+                            std::iter::once((params.text_document.uri.clone(), vec![edit]))
+                                .collect(),
+                        ),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(if actions.is_empty() {
+            None
+        } else {
+            Some(actions)
+        })
     }
 
     async fn semantic_tokens_full(
@@ -540,3 +697,49 @@ impl LanguageServer for Service {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn multibyte_position() {
+        // Locations are byte offsets, while positions are in characters.
+        let content = "é = 1\nünused = 2\n";
+        let rope = ropey::Rope::from_str(content);
+        let offset = content.find("= 2").unwrap();
+        let pos = position(&rope, offset);
+        assert_eq!(pos, Position::new(1, 7));
+        assert_eq!(rope.char_to_byte(char_offset(&rope, pos).unwrap()), offset);
+        assert_eq!(
+            position(&rope, content.find("1").unwrap()),
+            Position::new(0, 4)
+        );
+    }
+
+    #[test]
+    fn migration_fixes() {
+        let fixes = fixes("^a");
+        let fix = fixes
+            .iter()
+            .find(|f| f.replacement == "~a")
+            .expect("missing migration fix");
+        assert_eq!(
+            fix.title,
+            "you can no longer merge strings; use `~` to set keyed values"
+        );
+        assert_eq!(fix.location, Location::new(0, 2));
+    }
+
+    #[test]
+    fn lint_fixes() {
+        let fixes = fixes("x = \"é\"; y = 2; $x");
+        let fix = fixes
+            .iter()
+            .find(|f| f.title == "unused binding: replace with `_`")
+            .expect("missing lint fix");
+        assert_eq!(fix.replacement, "_");
+        // The location is in bytes, after the multi-byte character.
+        assert_eq!(fix.location, Location::new(10, 1));
+    }
+}
@@ -22,12 +22,12 @@ pub struct Migrate {
 
 type Str = Cow<'static, str>;
 
-enum MigrationLevel {
+pub(crate) enum MigrationLevel {
     Syntactic,
     Semantic,
 }
 
-struct Migration {
+pub(crate) struct Migration {
     pub source: Source<()>,
     pub message: Str,
     pub level: MigrationLevel,
@@ -75,6 +75,15 @@ impl Migration {
     }
 }
 
+/// Get the migrations of a script.
+///
+/// If the script cannot be parsed, no migrations are returned.
+pub(crate) fn migrations(source: Source<&str>) -> Vec<Migration> {
+    let tokens = tokenize::Tokens::from(source);
+    let parser = parse_tree::Parser::from(tokens);
+    parse::migrations(parser).unwrap_or_default()
+}
+
 impl super::Command for Migrate {
     fn run(self) -> Result<(), String> {
         let sources = Sources::new();
@@ -82,11 +91,7 @@ impl super::Command for Migrate {
         for f in self.files {
             let source_id = sources.add_file(f).map_err(|e| e.to_string())?;
             let source = Source::new(source_id).with(sources.content(source_id).unwrap());
-            let tokens = tokenize::Tokens::from(source);
-            let parser = parse_tree::Parser::from(tokens);
-            if let Ok(m) = parse::migrations(parser) {
-                migrations.extend(m);
-            }
+            migrations.extend(self::migrations(source));
         }
 
         let diagnostics: Vec<_> = migrations
@@ -107,6 +112,8 @@ impl super::Command for Migrate {
                         message: message.clone().into_owned().into(),
                         labels: vec![Label::primary(source.with(""))].into(),
                         notes: Default::default(),
+                        kind: Default::default(),
+                        fields: Default::default(),
                     }
                 },
             )
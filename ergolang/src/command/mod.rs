@@ -1,12 +1,24 @@
+mod check;
+mod completions;
+mod dap;
 mod evaluate;
 mod format;
+mod lock;
+mod logs;
 mod lsp;
 mod migrate;
+mod repl;
 
+pub use check::Check;
+pub use completions::Completions;
+pub use dap::Dap;
 pub use evaluate::Evaluate;
 pub use format::Format;
+pub use lock::Lock;
+pub use logs::Logs;
 pub use lsp::Lsp;
 pub use migrate::Migrate;
+pub use repl::Repl;
 
 pub trait Command {
     fn run(self) -> Result<(), String>;
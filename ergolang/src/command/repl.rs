@@ -0,0 +1,517 @@
+//! Interactive evaluation.
+
+use super::completions;
+use super::evaluate::{
+    constants, load_path, lock_file,
+    output::{error as error_output, output, Output, TermToTermcolor},
+    render_markdown, storage_directory, sync, Interrupt, OutputFormat,
+};
+use crate::AppErr;
+use ergo_runtime::{context::LogLevel, metadata::Doc, types, Context, Error, Value};
+use ergo_script::ast::tokenize::{Error as TokenizeError, SymbolicToken, Token, Tokens};
+use ergo_script::{Runtime, Source};
+use rustyline::error::ReadlineError;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[derive(Debug, clap::Args)]
+/// Evaluate expressions interactively.
+///
+/// Each input is evaluated as a script and the result is displayed. If the input ends with a
+/// binding, the bindings of the input are retained for subsequent inputs rather than displaying
+/// the result.
+///
+/// Input continues over multiple lines while brackets are unbalanced. Tab completes the indices of
+/// values (e.g. `std:fs:`); completion never evaluates bound values, evaluates other values without
+/// permission to execute programs, write files, or access the network, and gives up after two
+/// seconds. The following commands are also supported:
+/// * `:doc <expression>`: display the documentation of the expression
+/// * `:help`: display the supported commands
+pub struct Repl {
+    #[clap(long = "log", default_value = "warn")]
+    /// The runtime log level.
+    ///
+    /// May be "debug", "info", "warn", or "error".
+    pub log_level: LogLevel,
+
+    #[clap(short, long)]
+    /// The maximum number of jobs to run concurrently.
+    ///
+    /// If unspecified, the number of cpus is used.
+    pub jobs: Option<usize>,
+
+    #[clap(long, default_value = concat!(".ergo_work"))]
+    /// The storage directory for the runtime.
+    ///
+    /// If a relative path, it will be made relative to the furthest ancestor directory that is a
+    /// workspace. If none are found, the current directory is used.
+    pub storage: std::path::PathBuf,
+
+    #[clap(long)]
+    /// The file in which to store input history.
+    ///
+    /// If unspecified, history is stored in the user data directory.
+    pub history: Option<std::path::PathBuf>,
+}
+
+const PROMPT: &str = "> ";
+
+const HELP: &str = "\
+Inputs are evaluated as scripts. Bindings are retained when an input ends with a binding.
+
+Commands:
+  :doc <expression>  display the documentation of the expression
+  :help              display this message";
+
+/// The maximum time spent evaluating a value to complete its indices.
+const COMPLETION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The evaluation state of a repl session.
+struct Session {
+    runtime: Runtime,
+    completer: Completer,
+    bindings: RefCell<HashMap<String, Value>>,
+    inputs: Cell<usize>,
+}
+
+/// Evaluates values for completion on a separate thread.
+///
+/// Values are evaluated with a separate runtime (see `completions::runtime`) so that completion
+/// has no side effects, and the session does not wait more than `COMPLETION_TIMEOUT` for them.
+struct Completer {
+    requests: mpsc::Sender<(String, mpsc::Sender<Vec<String>>)>,
+}
+
+impl Completer {
+    fn new() -> Self {
+        let (requests, received) = mpsc::channel::<(String, mpsc::Sender<Vec<String>>)>();
+        std::thread::spawn(move || {
+            let runtime = match completions::runtime() {
+                Ok(runtime) => runtime,
+                Err(_) => return,
+            };
+            for (script, reply) in received {
+                drop(reply.send(completions::script_indices(&runtime, &script)));
+            }
+
+            // Before the context is destroyed (unloading plugins), clear the thread-local storage
+            // in case there are values which were allocated in the plugins.
+            ergo_runtime::plugin::Context::reset();
+            drop(runtime);
+        });
+        Completer { requests }
+    }
+
+    /// Evaluate a script and get the (String) indices of the resulting value.
+    ///
+    /// Returns no indices if evaluation fails or takes longer than `COMPLETION_TIMEOUT`.
+    fn indices(&self, script: &str) -> Vec<String> {
+        let (send, recv) = mpsc::channel();
+        if self.requests.send((script.to_owned(), send)).is_err() {
+            return Vec::new();
+        }
+        recv.recv_timeout(COMPLETION_TIMEOUT).unwrap_or_default()
+    }
+}
+
+/// The result of evaluating an input.
+enum Evaluated {
+    /// A value to display.
+    Display(String),
+    /// Markdown documentation to display.
+    Doc(String),
+    /// Nothing to display.
+    Nothing,
+}
+
+impl Session {
+    /// Load and evaluate a script string with the current bindings.
+    fn evaluate(&self, name: &str, script: &str) -> Result<Value, Error> {
+        let mut script = self.runtime.load_string(name, script)?;
+        script.extend_top_level_env(self.bindings.borrow().clone());
+        self.runtime.block_on(script.evaluate())
+    }
+
+    /// Evaluate a line of input.
+    fn input(&self, line: &str) -> Result<Evaluated, Error> {
+        let trimmed = line.trim();
+        if trimmed == ":help" {
+            return Ok(Evaluated::Display(HELP.into()));
+        }
+
+        let input = self.inputs.get() + 1;
+        self.inputs.set(input);
+        let name = format!("<repl:{}>", input);
+
+        if let Some(expr) = command_argument(trimmed, ":doc") {
+            let value = self.evaluate(&name, expr)?;
+            return self.runtime.block_on(Doc::get(&value)).map(Evaluated::Doc);
+        }
+
+        let mut value = self.evaluate(&name, line)?;
+
+        if ends_with_binding(line) {
+            // Retain the bindings, without evaluating the bound values.
+            self.runtime.block_on(Context::eval(&mut value))?;
+            if let Some(types::Map(entries)) = value.as_ref::<types::Map>() {
+                let mut bindings = self.bindings.borrow_mut();
+                for (k, v) in entries.iter() {
+                    if let Some(k) = k.as_ref::<types::String>() {
+                        bindings.insert(k.to_string(), v.clone());
+                    }
+                }
+                return Ok(Evaluated::Nothing);
+            }
+        }
+
+        self.runtime.block_on(async move {
+            use ergo_runtime::traits::{display, eval_nested, Formatter};
+            eval_nested(value.clone()).await?;
+            let mut s = String::new();
+            {
+                let mut formatter = Formatter::new(&mut s);
+                display(value, &mut formatter).await?;
+            }
+            Ok(Evaluated::Display(s))
+        })
+    }
+
+    /// Get the (String) indices of the value resulting from the given expression.
+    ///
+    /// Only expressions consisting of strings and index operators are completed. Bound values are
+    /// only indexed if already evaluated, and other values are evaluated by the `Completer`.
+    fn indices(&self, expr: &str) -> Vec<String> {
+        if expr.is_empty()
+            || !expr
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ':')
+        {
+            return Vec::new();
+        }
+
+        let mut path = expr.split(':');
+        let bindings = self.bindings.borrow();
+        let mut value = match path.next().and_then(|name| bindings.get(name)) {
+            Some(v) => v.clone(),
+            None => return self.completer.indices(expr),
+        };
+        for index in path {
+            value = match evaluated_entries(&value)
+                .and_then(|entries| entries.into_iter().find(|(k, _)| k == index))
+            {
+                Some((_, v)) => v,
+                None => return Vec::new(),
+            };
+        }
+        evaluated_entries(&value)
+            .map(|entries| entries.into_iter().map(|(k, _)| k).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Get the String-keyed entries of a value which is (or is a Type with an index which is) an
+/// evaluated Map.
+///
+/// Nothing is evaluated, so that completing bound values has no side effects.
+fn evaluated_entries(value: &Value) -> Option<Vec<(String, Value)>> {
+    let value = match value.as_ref::<types::Type>() {
+        Some(t) => &t.index,
+        None => value,
+    };
+    let types::Map(entries) = value.as_ref::<types::Map>()?;
+    Some(
+        entries
+            .iter()
+            .filter_map(|(k, v)| {
+                k.as_ref::<types::String>()
+                    .map(|k| (k.to_string(), v.clone()))
+            })
+            .collect(),
+    )
+}
+
+/// Get the argument of a repl command, if `line` is the given command.
+fn command_argument<'a>(line: &'a str, command: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(command)?;
+    let arg = rest.trim_start();
+    // `:doc = ...` is a binding rather than a command.
+    if rest.len() == arg.len() || arg.is_empty() || arg.starts_with('=') {
+        None
+    } else {
+        Some(arg)
+    }
+}
+
+/// Return whether the last top-level expression of a script is a binding.
+fn ends_with_binding(script: &str) -> bool {
+    let mut depth = 0;
+    let mut binding = false;
+    let mut keyed = false;
+    for t in Tokens::from(Source::new(0).with(script)) {
+        let t = match t {
+            Ok(t) => t.unwrap(),
+            Err(_) => return false,
+        };
+        match t {
+            Token::StartNested(_) => depth += 1,
+            Token::EndNested => depth -= 1,
+            _ if depth > 0 => (),
+            Token::NextChild => {
+                binding = false;
+                keyed = false;
+            }
+            Token::Symbol(SymbolicToken::Tilde | SymbolicToken::TildeEqual) => keyed = true,
+            // An `=` following a `~` is a keyed argument of a command rather than a binding.
+            Token::Symbol(SymbolicToken::Equal) if !keyed => binding = true,
+            _ => (),
+        }
+    }
+    binding
+}
+
+/// Return whether the script has unclosed brackets.
+fn is_incomplete(script: &str) -> bool {
+    Tokens::from(Source::new(0).with(script)).any(|t| {
+        matches!(
+            t.map_err(|e| e.unwrap()),
+            Err(TokenizeError::UnmatchedOpeningToken(_))
+        )
+    })
+}
+
+/// Line editor integration.
+struct Helper<'a> {
+    session: &'a Session,
+}
+
+impl rustyline::completion::Completer for Helper<'_> {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        let start = before
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_' || c == ':'))
+            .map(|i| i + before[i..].chars().next().unwrap().len_utf8())
+            .unwrap_or(0);
+        let word = &before[start..];
+
+        Ok(match word.rfind(':') {
+            Some(i) => {
+                let partial = &word[i + 1..];
+                let mut candidates: Vec<String> = self
+                    .session
+                    .indices(&word[..i])
+                    .into_iter()
+                    .filter(|k| k.starts_with(partial))
+                    .collect();
+                candidates.sort();
+                (start + i + 1, candidates)
+            }
+            None => {
+                let mut candidates: Vec<String> = self
+                    .session
+                    .bindings
+                    .borrow()
+                    .keys()
+                    .map(|k| k.as_str())
+                    .chain(std::iter::once("std"))
+                    .filter(|k| k.starts_with(word))
+                    .map(|k| k.to_owned())
+                    .collect();
+                candidates.sort();
+                candidates.dedup();
+                (start, candidates)
+            }
+        })
+    }
+}
+
+impl rustyline::hint::Hinter for Helper<'_> {
+    type Hint = String;
+}
+
+impl rustyline::highlight::Highlighter for Helper<'_> {}
+
+impl rustyline::validate::Validator for Helper<'_> {
+    fn validate(
+        &self,
+        ctx: &mut rustyline::validate::ValidationContext,
+    ) -> rustyline::Result<rustyline::validate::ValidationResult> {
+        Ok(if is_incomplete(ctx.input()) {
+            rustyline::validate::ValidationResult::Incomplete
+        } else {
+            rustyline::validate::ValidationResult::Valid(None)
+        })
+    }
+}
+
+impl rustyline::Helper for Helper<'_> {}
+
+impl super::Command for Repl {
+    fn run(self) -> Result<(), String> {
+        let interrupt = Interrupt::install()?;
+
+        let (mut output, _) = output(OutputFormat::Basic, true)
+            .app_err("could not create output with requested format")?;
+        output.set_log_level(self.log_level);
+
+        let error_logger = output.error_log();
+
+        let runtime = Runtime::new(
+            ergo_runtime::Context::builder()
+                .logger(output.log())
+                .storage_directory(storage_directory(&self.storage))
+                .lock_file(lock_file())
+                .threads(self.jobs)
+                .keep_going(true)
+                .error_handler(move |e: Error| error_logger.new_error(e)),
+            load_path(),
+        )
+        .expect("failed to create script context");
+
+        // Set interrupt signal handler to abort tasks while evaluating.
+        let (signal_handler_task, task_ref) = sync::Scoped::new_pair(runtime.ctx.task.clone());
+        interrupt.set_task(task_ref);
+
+        let session = Session {
+            runtime,
+            completer: Completer::new(),
+            bindings: Default::default(),
+            inputs: Cell::new(0),
+        };
+
+        let history = self
+            .history
+            .or_else(|| constants::app_dirs().map(|dirs| dirs.data_dir().join("repl_history")));
+
+        let mut editor = rustyline::Editor::<Helper>::new();
+        editor.set_helper(Some(Helper { session: &session }));
+        if let Some(path) = &history {
+            // The history file may not exist yet.
+            drop(editor.load_history(path));
+        }
+
+        eprintln!("Type `:help` for help, and press Ctrl-D to exit.");
+
+        let result = loop {
+            let line = match editor.readline(PROMPT) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break Ok(()),
+                Err(e) => break Err(format!("failed to read input: {}", e)),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            editor.add_history_entry(line.as_str());
+
+            // Discard any errors which occurred outside of evaluation (e.g. during completion).
+            output.update();
+            drop(output.take_errors());
+
+            let result = session.input(&line);
+
+            output.update();
+            let mut errors = output.take_errors();
+            match result {
+                Ok(Evaluated::Display(s)) => println!("{}", s),
+                Ok(Evaluated::Doc(s)) => {
+                    render_markdown::render_markdown(render_markdown::ColorSupport::new(), &s)
+                }
+                Ok(Evaluated::Nothing) => (),
+                Err(e) => {
+                    errors.insert(&e);
+                }
+            }
+
+            if errors.len() > 0 {
+                use ergo_runtime::error::emit_diagnostics;
+                let sources = session.runtime.ctx.global.diagnostic_sources();
+                let written = error_output(OutputFormat::Auto)
+                    .app_err("could not create error output")
+                    .and_then(|err| {
+                        emit_diagnostics(&errors, sources.as_ref(), &mut TermToTermcolor(err))
+                            .map_err(|e| e.to_string())
+                    });
+                if let Err(e) = written {
+                    break Err(e);
+                }
+            }
+        };
+
+        if let Some(path) = &history {
+            if let Some(parent) = path.parent() {
+                drop(std::fs::create_dir_all(parent));
+            }
+            if let Err(e) = editor.save_history(path) {
+                eprintln!("failed to save history to {}: {}", path.display(), e);
+            }
+        }
+        drop(editor);
+
+        let Session {
+            runtime, bindings, ..
+        } = session;
+        drop(bindings);
+
+        // Before the context is destroyed (unloading plugins), clear the thread-local storage in case
+        // there are values which were allocated in the plugins.
+        ergo_runtime::plugin::Context::reset();
+
+        drop(signal_handler_task);
+        runtime.ctx.global.hooks().shutdown();
+
+        // Drop the output prior to the context dropping, so that any stored state (like errors) can
+        // drop with the plugins still loaded.
+        drop(output);
+        drop(runtime);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn command_arguments() {
+        assert_eq!(command_argument(":doc std:fs", ":doc"), Some("std:fs"));
+        assert_eq!(command_argument(":doc   std:fs", ":doc"), Some("std:fs"));
+        assert_eq!(command_argument(":doc", ":doc"), None);
+        assert_eq!(command_argument(":doc ", ":doc"), None);
+        assert_eq!(command_argument(":doc = 1", ":doc"), None);
+        assert_eq!(command_argument(":docs std:fs", ":doc"), None);
+        assert_eq!(command_argument("std:fs", ":doc"), None);
+    }
+
+    #[test]
+    fn ends_with_bindings() {
+        assert!(ends_with_binding("a = 1"));
+        assert!(ends_with_binding("a = 1; b = 2"));
+        assert!(ends_with_binding("a = 1\nb = [1, 2]"));
+        assert!(!ends_with_binding("a = 1; $a"));
+        assert!(!ends_with_binding("[a = 1]"));
+        assert!(!ends_with_binding("{a = 1}"));
+        assert!(!ends_with_binding("f ~a=1"));
+        assert!(!ends_with_binding("std:fs"));
+        assert!(!ends_with_binding("\"unterminated"));
+    }
+
+    #[test]
+    fn incomplete() {
+        assert!(is_incomplete("["));
+        assert!(is_incomplete("{a = [1, 2]"));
+        assert!(is_incomplete("f (g"));
+        assert!(!is_incomplete("[1, 2]"));
+        assert!(!is_incomplete("a = {b = 1}"));
+        assert!(!is_incomplete(""));
+    }
+}
@@ -14,23 +14,41 @@ pub use app_err::AppErr;
 /// Ergo is a runtime and language built for lazy task execution.
 #[clap(version = env!("CARGO_PKG_VERSION"))]
 enum Command {
+    /// Check scripts without evaluating them.
+    Check(command::Check),
+    /// Generate shell completion scripts.
+    Completions(command::Completions),
+    /// Run the debug adapter.
+    Dap(command::Dap),
     /// Load and evaluate a value.
     Evaluate(command::Evaluate),
     /// Format scripts.
     Format(command::Format),
+    /// Manage the lock file.
+    Lock(command::Lock),
+    /// Show the task logs of past runs.
+    Logs(command::Logs),
     /// Run the language server.
     Lsp(command::Lsp),
     /// Migrate source files.
     Migrate(command::Migrate),
+    /// Evaluate expressions interactively.
+    Repl(command::Repl),
 }
 
 impl command::Command for Command {
     fn run(self) -> Result<(), String> {
         match self {
+            Command::Check(a) => a.run(),
+            Command::Completions(a) => a.run(),
+            Command::Dap(a) => a.run(),
             Command::Evaluate(a) => a.run(),
             Command::Format(a) => a.run(),
+            Command::Lock(a) => a.run(),
+            Command::Logs(a) => a.run(),
             Command::Lsp(a) => a.run(),
             Command::Migrate(a) => a.run(),
+            Command::Repl(a) => a.run(),
         }
     }
 }